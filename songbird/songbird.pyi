@@ -1,6 +1,9 @@
 from __future__ import annotations
 
-from typing import Any, Callable, List, Optional, Tuple
+from typing import (Any, AsyncIterator, Awaitable, BinaryIO, Callable, Dict, Generic,
+                    List, Optional, Tuple, TypeVar, Union)
+
+T = TypeVar("T")
 
 
 class SongbirdError(Exception):
@@ -16,6 +19,64 @@ class UseAsyncConstructorError(SongbirdError):
 
 
 class CouldNotConnectToRTPError(SongbirdError):
+    attempts: int
+    close_code: Optional[int]
+    errno: Optional[int]
+
+
+class ConnectionTimeoutError(CouldNotConnectToRTPError):
+    ...
+
+
+class AttemptDiscardedError(CouldNotConnectToRTPError):
+    ...
+
+
+class ConnectionCryptoError(CouldNotConnectToRTPError):
+    ...
+
+
+class CryptoModeRejectedError(CouldNotConnectToRTPError):
+    ...
+
+
+class CryptoModeInvalidError(CryptoModeRejectedError):
+    ...
+
+
+class CryptoModeUnavailableError(CryptoModeRejectedError):
+    ...
+
+
+class EndpointUrlError(CouldNotConnectToRTPError):
+    ...
+
+
+class HandshakeError(CouldNotConnectToRTPError):
+    ...
+
+
+class IpDiscoveryError(CouldNotConnectToRTPError):
+    ...
+
+
+class ConnectionIoError(CouldNotConnectToRTPError):
+    ...
+
+
+class ConnectionJsonError(CouldNotConnectToRTPError):
+    ...
+
+
+class InterconnectError(CouldNotConnectToRTPError):
+    ...
+
+
+class WebsocketError(CouldNotConnectToRTPError):
+    ...
+
+
+class WebsocketClosedError(WebsocketError):
     ...
 
 
@@ -37,7 +98,29 @@ class Driver:
     async def connect(self, token: str, endpoint: str, session_id: str,
                       guild_id: int, channel_id: int, user_id: int) -> None: ...
 
+    async def move_to(self, channel_id: int, session_id: Optional[str] = None,
+                      token: Optional[str] = None, endpoint: Optional[str] = None) -> None: ...
+
     async def leave(self) -> None: ...
+    @property
+    def connection_state(self) -> ConnectionState: ...
+    @property
+    def is_connected(self) -> bool: ...
+    @property
+    def last_connect(self) -> Optional[ConnectData]: ...
+    @property
+    def last_disconnect(self) -> Optional[DisconnectData]: ...
+    @property
+    def channel_id(self) -> Optional[int]: ...
+    @property
+    def guild_id(self) -> Optional[int]: ...
+    @property
+    def ssrc(self) -> Optional[int]: ...
+    @property
+    def server(self) -> Optional[str]: ...
+    @property
+    def ssrc_map(self) -> Dict[int, int]: ...
+    async def wait_connected(self, timeout: Optional[float] = None) -> Optional[ConnectData]: ...
     async def mute(self) -> None: ...
     async def unmute(self) -> None: ...
     async def is_muted(self) -> bool: ...
@@ -52,26 +135,139 @@ class Driver:
     async def set_config(self, config: Config) -> None: ...
     async def get_config(self) -> Config: ...
     async def add_event(self, event: Event,
-                        call: Callable[..., None]) -> None: ...
+                        call: Callable[..., Awaitable[Optional[Event]]]) -> EventSubscription: ...
+
+    def voice_packets(self, capacity: int = 256,
+                      overflow: Optional[OverflowPolicy] = None) -> EventStream[VoiceData]: ...
+
+    def buffered_audio(self, depth: int = 3, capacity: int = 256,
+                       overflow: Optional[OverflowPolicy] = None
+                       ) -> EventStream[AudioFrame]: ...
+
+    def speaking_updates(self, capacity: int = 256,
+                         overflow: Optional[OverflowPolicy] = None
+                         ) -> EventStream[SpeakingUpdateData]: ...
+
+    def events(self, *kinds: Event, capacity: int = 256,
+               overflow: Optional[OverflowPolicy] = None) -> EventStream[Tuple[Event, Any]]: ...
 
     async def remove_all_events(self) -> None: ...
 
 
+class DryRunDriver:
+    def __init__(self, config: Optional[Config] = None) -> None: ...
+    async def play_source(self, source: Source) -> TrackHandle: ...
+    async def play_only_source(self, source: Source) -> TrackHandle: ...
+    async def play(self, source: Track) -> TrackHandle: ...
+    async def play_only(self, source: Track) -> TrackHandle: ...
+    async def stop(self) -> None: ...
+    async def render(self, duration: Optional[float] = None,
+                     realtime: bool = False) -> bytes: ...
+
+    async def render_to_file(self, path: str, duration: Optional[float] = None,
+                             realtime: bool = False) -> None: ...
+
+
+class RepeatMode:
+    Off: RepeatMode
+    One: RepeatMode
+    All: RepeatMode
+
+    def __eq__(self, object: Any) -> bool: ...
+
+
+class TrackQueue:
+    repeat: RepeatMode
+
+    def __init__(self, driver: Driver, history_size: int = 20) -> None: ...
+    async def enqueue_source(self, source: Source) -> TrackHandle: ...
+    async def enqueue(self, track: Track) -> TrackHandle: ...
+    def skip(self) -> None: ...
+    async def previous(self) -> Optional[TrackHandle]: ...
+    def stop(self) -> None: ...
+    def pause(self) -> None: ...
+    def resume(self) -> None: ...
+    def current(self) -> Optional[TrackHandle]: ...
+    def dequeue(self, index: int) -> Optional[TrackHandle]: ...
+    def remove(self, uuid: str) -> Optional[TrackHandle]: ...
+    def move(self, source: int, destination: int) -> None: ...
+    def shuffle(self) -> None: ...
+    def tracks(self) -> List[TrackHandle]: ...
+    def history(self) -> List[TrackHandle]: ...
+    def clear_history(self) -> None: ...
+    def is_empty(self) -> bool: ...
+    def __len__(self) -> int: ...
+
+
+class ConnectionState:
+    Disconnected: ConnectionState
+    Connecting: ConnectionState
+    Connected: ConnectionState
+
+    def __eq__(self, object: Any) -> bool: ...
+
+
+class VoiceManager:
+    def __init__(self, user_id: int, config: Optional[Config] = None) -> None: ...
+    @property
+    def user_id(self) -> int: ...
+    async def on_voice_state_update(self, payload: Dict[str, Any]) -> None: ...
+    async def on_voice_server_update(self, payload: Dict[str, Any]) -> None: ...
+    async def get(self, guild_id: int) -> Optional[Driver]: ...
+    async def get_or_create(self, guild_id: int) -> Driver: ...
+    async def remove(self, guild_id: int) -> None: ...
+    async def guild_ids(self) -> List[int]: ...
+
+
 class Source:
     @staticmethod
     def bytes(bytes: bytes, stereo: bool) -> Source: ...
 
     @staticmethod
     async def ffmpeg(filename: str, pre_input_args=None,
-                     args=None) -> Source: ...
+                     args=None, headers: Optional[Dict[str, str]] = None,
+                     reconnect: bool = False,
+                     timeout: Optional[float] = None) -> Source: ...
 
     @staticmethod
     async def ytdl(url: str) -> Source: ...
     @staticmethod
     def file(filename: str) -> Source: ...
+    @staticmethod
+    def from_reader(reader: Union[BinaryIO, AsyncIterator[bytes]], stereo: bool = True,
+                    seekable: bool = False, buffer_size: int = 1048576,
+                    format: Optional[PcmFormat] = None) -> Source: ...
+    @staticmethod
+    def pcm(bytes: bytes, format: PcmFormat) -> Source: ...
+    @staticmethod
+    def pcm_file(filepath: str, format: PcmFormat) -> Source: ...
+    @staticmethod
+    def decode(filepath: str) -> Source: ...
+    @staticmethod
+    def opus_file(filepath: str) -> Source: ...
+    @staticmethod
+    async def dca(filepath: str) -> Source: ...
     async def metadata(self) -> Metadata: ...
     async def stereo(self) -> bool: ...
 
+class SampleFormat:
+    S16LE: SampleFormat
+    S16BE: SampleFormat
+    S32LE: SampleFormat
+    S32BE: SampleFormat
+    F32LE: SampleFormat
+    F32BE: SampleFormat
+
+
+class PcmFormat:
+    sample_format: SampleFormat
+    sample_rate: int
+    channels: int
+
+    def __init__(self, sample_format: SampleFormat, sample_rate: int = 48000,
+                 channels: int = 2) -> None: ...
+
+
 class RestartableSource:
     def into_source(self) -> Source: ...
     @classmethod
@@ -140,7 +336,8 @@ class TrackHandle:
     @property
     def is_seekable(self) -> bool: ...
     def seek_time(self, position: float) -> float: ...
-    def add_event(self, event: Event, call: Callable) -> None: ...
+    def add_event(self, event: Event,
+                  call: Callable[..., Awaitable[Optional[Event]]]) -> EventSubscription: ...
     async def get_info(self) -> TrackState: ...
     def enable_loop(self) -> None: ...
     def disable_loop(self) -> None: ...
@@ -221,6 +418,126 @@ class Track:
     async def uuid(self) -> str: ...
 
 
+class VoiceRecorder:
+    def __init__(self, driver: Driver, directory: str) -> None: ...
+    @property
+    def directory(self) -> str: ...
+    @property
+    def recording(self) -> bool: ...
+    def start(self) -> None: ...
+    def stop(self) -> List[str]: ...
+    def rotate(self) -> List[str]: ...
+
+
+class MixedRecorder:
+    def __init__(
+        self, driver: Driver, gains: Optional[Dict[int, float]] = None
+    ) -> None: ...
+    @property
+    def path(self) -> Optional[str]: ...
+    @property
+    def recording(self) -> bool: ...
+    @property
+    def gains(self) -> Dict[int, float]: ...
+    def set_gain(self, user_id: int, gain: float) -> None: ...
+    def start(self, path: str) -> None: ...
+    def stop(self) -> Optional[str]: ...
+
+
+class Utterance:
+    ssrc: int
+    user_id: Optional[int]
+    timestamp: int
+    finished: bool
+    # A numpy array of int16 when numpy is installed.
+    audio: Union[memoryview, Any]
+    duration: float
+
+
+class VoiceActivityDetector:
+    def __init__(
+        self,
+        driver: Driver,
+        start_threshold: float = -40.0,
+        stop_threshold: float = -50.0,
+        hangover: float = 0.3,
+        min_length: float = 0.2,
+        max_length: float = 30.0,
+        depth: int = 3,
+        capacity: int = 64,
+        overflow: Optional[OverflowPolicy] = None,
+    ) -> None: ...
+    @property
+    def events(self) -> EventStream[Utterance]: ...
+    def __aiter__(self) -> EventStream[Utterance]: ...
+    def set_thresholds(self, user_id: int, start: float, stop: float) -> None: ...
+    def reset_thresholds(self, user_id: int) -> None: ...
+    def thresholds(self, user_id: int) -> Tuple[float, float]: ...
+
+
+class SpeakerStats:
+    user_id: Optional[int]
+    ssrc: Optional[int]
+    talk_time: float
+    utterances: int
+    overlap_time: float
+    overlaps: int
+    level: Optional[float]
+
+
+class SpeakingInterval:
+    user_id: Optional[int]
+    ssrc: int
+    start: float
+    end: Optional[float]
+    level: Optional[float]
+
+
+class SpeakingStats:
+    def __init__(self, driver: Driver) -> None: ...
+    @property
+    def started(self) -> float: ...
+    @property
+    def duration(self) -> float: ...
+    @property
+    def users(self) -> List[SpeakerStats]: ...
+    def user(self, user_id: int) -> Optional[SpeakerStats]: ...
+    @property
+    def timeline(self) -> List[SpeakingInterval]: ...
+    @property
+    def overlap_time(self) -> float: ...
+    @property
+    def overlaps(self) -> int: ...
+    def reset(self) -> None: ...
+    def to_json(self) -> str: ...
+
+
+class OverflowPolicy:
+    DropOldest: OverflowPolicy
+    DropNewest: OverflowPolicy
+    Block: OverflowPolicy
+
+    def __eq__(self, object: Any) -> bool: ...
+
+
+class EventStream(Generic[T]):
+    @property
+    def lost(self) -> int: ...
+    def close(self) -> None: ...
+    def __len__(self) -> int: ...
+    def __aiter__(self) -> EventStream[T]: ...
+    async def __anext__(self) -> T: ...
+
+
+class EventSubscription:
+    @property
+    def active(self) -> bool: ...
+    def remove(self) -> None: ...
+    def cancel(self) -> None: ...
+    def __enter__(self) -> EventSubscription: ...
+    def __exit__(self, exc_type: Any, exc_value: Any, traceback: Any) -> None: ...
+
+
 class Event:
     Cancel: Event
     Play: Event
@@ -257,6 +574,7 @@ class Speaking:
 class SpeakingUpdateData:
     speaking: bool
     ssrc: int
+    user_id: Optional[int]
 
 
 class ClientConnect:
@@ -266,7 +584,7 @@ class ClientConnect:
 
 
 class ConnectData:
-    channel_id: int
+    channel_id: Optional[int]
     guild_id: int
     session_id: str
     server: str
@@ -296,11 +614,73 @@ class DisconnectReason:
     WsClosed: DisconnectReason
 
 
+class SharedBuffer:
+    def __len__(self) -> int: ...
+
+
+class AudioFrame:
+    ssrc: int
+    user_id: Optional[int]
+    timestamp: int
+    concealed: bool
+    # A numpy array of int16 when numpy is installed.
+    audio: Union[memoryview, Any]
+
+
 class VoiceData:
-    audio: Optional[List[int]]
+    # A numpy array of int16 when numpy is installed.
+    audio: Optional[Union[memoryview, Any]]
     packet: Rtp
     payload_offset: int
     payload_end_pad: int
+    user_id: Optional[int]
+
+
+class ReportBlock:
+    ssrc: int
+    fraction_lost: float
+    cumulative_lost: int
+    highest_sequence: int
+    jitter: int
+    jitter_seconds: float
+    last_sender_report: int
+    delay_since_last_sender_report: float
+    round_trip_time: Optional[float]
+
+
+class SenderReport:
+    ssrc: int
+    ntp_timestamp: int
+    rtp_timestamp: int
+    packet_count: int
+    octet_count: int
+    reports: List[ReportBlock]
+
+
+class ReceiverReport:
+    ssrc: int
+    reports: List[ReportBlock]
+
+
+class SdesChunk:
+    ssrc: int
+    items: Dict[str, str]
+
+
+class SourceDescription:
+    chunks: List[SdesChunk]
+
+
+class Goodbye:
+    ssrcs: List[int]
+    reason: Optional[str]
+
+
+class RtcpData:
+    packet_type: int
+    packets: List[Union[SenderReport, ReceiverReport, SourceDescription, Goodbye]]
+    payload_offset: int
+    payload_end_pad: int
 
 
 class Rtp:
@@ -313,7 +693,8 @@ class Rtp:
     timestamp: int
     ssrc: int
     csrc_list: List[int]
-    payload: List[int]
+    # A numpy array of uint8 when numpy is installed.
+    payload: Union[memoryview, Any]
 
 
 class RtpType:
@@ -2,7 +2,7 @@ from __future__ import annotations
 
 from typing import Callable
 
-from songbird import Driver, Event, Source, Config, TrackHandle, Track
+from songbird import Driver, Event, EventSubscription, Source, Config, TrackHandle, Track
 
 
 class VoiceboxBase:
@@ -53,5 +53,5 @@ class VoiceboxBase:
     async def get_config(self) -> Config:
         return await self.driver.get_config()
 
-    async def add_event(self, event: Event, call: Callable) -> None:
+    async def add_event(self, event: Event, call: Callable) -> EventSubscription:
         return await self.driver.add_event(event, call)
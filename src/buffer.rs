@@ -0,0 +1,139 @@
+use std::ffi::CStr;
+use std::mem;
+use std::os::raw::{c_int, c_void};
+use std::ptr;
+use std::sync::Arc;
+
+use pyo3::exceptions::PyBufferError;
+use pyo3::once_cell::GILOnceCell;
+use pyo3::prelude::*;
+use pyo3::{ffi, AsPyPointer};
+
+enum Data {
+    Pcm(Arc<Vec<i16>>),
+    Bytes(Arc<Vec<u8>>),
+}
+
+/// Read-only memory owned by Rust that Python can use without copying it, through the
+/// buffer protocol.
+#[pyclass(name = "SharedBuffer")]
+pub struct PySharedBuffer {
+    data: Data,
+    // The buffer protocol needs pointers to these, so they live as long as the object.
+    shape: isize,
+    itemsize: isize,
+}
+
+impl PySharedBuffer {
+    fn new(data: Data) -> Self {
+        let (shape, itemsize) = match &data {
+            Data::Pcm(samples) => (samples.len(), mem::size_of::<i16>()),
+            Data::Bytes(bytes) => (bytes.len(), mem::size_of::<u8>()),
+        };
+        Self {
+            data,
+            shape: shape as isize,
+            itemsize: itemsize as isize,
+        }
+    }
+
+    fn data_ptr(&self) -> *mut c_void {
+        match &self.data {
+            Data::Pcm(samples) => samples.as_ptr() as *mut c_void,
+            Data::Bytes(bytes) => bytes.as_ptr() as *mut c_void,
+        }
+    }
+
+    fn format(&self) -> &'static CStr {
+        let format: &[u8] = match self.data {
+            Data::Pcm(_) => b"h\0",
+            Data::Bytes(_) => b"B\0",
+        };
+        CStr::from_bytes_with_nul(format).unwrap()
+    }
+}
+
+#[pymethods]
+impl PySharedBuffer {
+    unsafe fn __getbuffer__(
+        slf: PyRefMut<'_, Self>,
+        view: *mut ffi::Py_buffer,
+        flags: c_int,
+    ) -> PyResult<()> {
+        if view.is_null() {
+            return Err(PyBufferError::new_err("View is null"));
+        }
+        if flags & ffi::PyBUF_WRITABLE == ffi::PyBUF_WRITABLE {
+            return Err(PyBufferError::new_err("Buffer is read-only"));
+        }
+
+        (*view).obj = ffi::_Py_NewRef(slf.as_ptr());
+        (*view).buf = slf.data_ptr();
+        (*view).len = slf.shape * slf.itemsize;
+        (*view).readonly = 1;
+        (*view).itemsize = slf.itemsize;
+        (*view).ndim = 1;
+
+        // The fields that were not asked for have to be left empty.
+        (*view).format = if flags & ffi::PyBUF_FORMAT == ffi::PyBUF_FORMAT {
+            slf.format().as_ptr() as *mut _
+        } else {
+            ptr::null_mut()
+        };
+        (*view).shape = if flags & ffi::PyBUF_ND == ffi::PyBUF_ND {
+            &slf.shape as *const isize as *mut isize
+        } else {
+            ptr::null_mut()
+        };
+        (*view).strides = if flags & ffi::PyBUF_STRIDES == ffi::PyBUF_STRIDES {
+            &slf.itemsize as *const isize as *mut isize
+        } else {
+            ptr::null_mut()
+        };
+
+        (*view).suboffsets = ptr::null_mut();
+        (*view).internal = ptr::null_mut();
+        Ok(())
+    }
+
+    fn __len__(&self) -> usize {
+        self.shape as usize
+    }
+}
+
+/// `numpy.frombuffer`, or `None` if numpy is not installed.
+static FROM_BUFFER: GILOnceCell<Option<PyObject>> = GILOnceCell::new();
+
+/// Returns the data as a numpy array if numpy is installed and as a memoryview otherwise.
+/// Neither copies the data.
+fn to_py(py: Python, data: Data) -> PyResult<PyObject> {
+    let dtype = match data {
+        Data::Pcm(_) => "int16",
+        Data::Bytes(_) => "uint8",
+    };
+    let buffer = Py::new(py, PySharedBuffer::new(data))?;
+
+    let from_buffer = FROM_BUFFER.get_or_init(py, || {
+        py.import("numpy")
+            .and_then(|numpy| numpy.getattr("frombuffer"))
+            .map(PyObject::from)
+            .ok()
+    });
+
+    match from_buffer {
+        Some(from_buffer) => from_buffer.call1(py, (buffer, dtype)),
+        None => unsafe {
+            PyObject::from_owned_ptr_or_err(py, ffi::PyMemoryView_FromObject(buffer.as_ptr()))
+        },
+    }
+}
+
+/// Returns 16 bit PCM audio without copying it. See `to_py`.
+pub fn pcm_to_py(py: Python, samples: Arc<Vec<i16>>) -> PyResult<PyObject> {
+    to_py(py, Data::Pcm(samples))
+}
+
+/// Returns bytes without copying them. See `to_py`.
+pub fn bytes_to_py(py: Python, bytes: Arc<Vec<u8>>) -> PyResult<PyObject> {
+    to_py(py, Data::Bytes(bytes))
+}
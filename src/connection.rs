@@ -0,0 +1,189 @@
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use pyo3::basic::CompareOp;
+use pyo3::exceptions::PyTypeError;
+use pyo3::prelude::*;
+use songbird::driver::Driver;
+use songbird::{ConnectionInfo, CoreEvent, Event, EventContext, EventHandler};
+use tokio::sync::watch;
+
+use crate::event::{PyConnectData, PyDisconnectData};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Stage {
+    Disconnected,
+    Connecting,
+    Connected,
+}
+
+/// Whether a driver is connected to a voice channel.
+#[pyclass(name = "ConnectionState")]
+#[derive(Clone)]
+pub struct PyConnectionState {
+    stage: Stage,
+}
+
+impl PyConnectionState {
+    fn from(stage: Stage) -> Self {
+        Self { stage }
+    }
+}
+
+#[allow(non_snake_case)]
+#[pymethods]
+impl PyConnectionState {
+    #[classattr]
+    /// The driver is not connected and is not trying to connect.
+    fn Disconnected() -> Self {
+        Self::from(Stage::Disconnected)
+    }
+    #[classattr]
+    /// A connection attempt is in progress.
+    fn Connecting() -> Self {
+        Self::from(Stage::Connecting)
+    }
+    #[classattr]
+    /// The driver is connected to a voice channel.
+    fn Connected() -> Self {
+        Self::from(Stage::Connected)
+    }
+
+    fn __str__(&self) -> &str {
+        match self.stage {
+            Stage::Disconnected => "<ConnectionState.Disconnected>",
+            Stage::Connecting => "<ConnectionState.Connecting>",
+            Stage::Connected => "<ConnectionState.Connected>",
+        }
+    }
+
+    fn __richcmp__(&self, other: Self, op: CompareOp) -> PyResult<PyObject> {
+        Python::with_gil(|py| match op {
+            CompareOp::Eq => PyResult::Ok((self.stage == other.stage).into_py(py)),
+            _ => PyResult::Err(PyTypeError::new_err(
+                "Only __eq__ is implemented for this type",
+            )),
+        })
+    }
+}
+
+#[derive(Default)]
+struct History {
+    info: Option<ConnectionInfo>,
+    attempts: usize,
+    last_connect: Option<PyConnectData>,
+    last_disconnect: Option<PyDisconnectData>,
+}
+
+/// Keeps track of the connection state of a driver using its connect and disconnect events.
+pub struct ConnectionTracker {
+    stage: watch::Sender<Stage>,
+    history: Mutex<History>,
+}
+
+impl ConnectionTracker {
+    pub fn new() -> Self {
+        let (stage, _) = watch::channel(Stage::Disconnected);
+        Self {
+            stage,
+            history: Mutex::new(History::default()),
+        }
+    }
+
+    /// Registers the events needed to follow the connection state on a driver.
+    /// This has to be done again if the global events are removed.
+    pub fn register(tracker: &Arc<Self>, driver: &mut Driver) {
+        for event in [
+            CoreEvent::DriverConnect,
+            CoreEvent::DriverReconnect,
+            CoreEvent::DriverDisconnect,
+        ] {
+            driver.add_global_event(Event::Core(event), TrackerHandler(tracker.clone()));
+        }
+    }
+
+    /// Marks the start of a connection attempt.
+    /// Returns how many attempts have been made since the driver was last connected.
+    pub fn set_connecting(&self, info: ConnectionInfo) -> usize {
+        let attempts = {
+            let mut history = self.history.lock().unwrap();
+            history.info = Some(info);
+            history.attempts += 1;
+            history.attempts
+        };
+        self.stage.send_replace(Stage::Connecting);
+        attempts
+    }
+
+    pub fn set_disconnected(&self) {
+        self.stage.send_replace(Stage::Disconnected);
+    }
+
+    pub fn state(&self) -> PyConnectionState {
+        PyConnectionState::from(*self.stage.borrow())
+    }
+
+    pub fn is_connected(&self) -> bool {
+        *self.stage.borrow() == Stage::Connected
+    }
+
+    /// The info used for the last connection attempt.
+    pub fn info(&self) -> Option<ConnectionInfo> {
+        self.history.lock().unwrap().info.clone()
+    }
+
+    pub fn last_connect(&self) -> Option<PyConnectData> {
+        self.history.lock().unwrap().last_connect.clone()
+    }
+
+    pub fn last_disconnect(&self) -> Option<PyDisconnectData> {
+        self.history.lock().unwrap().last_disconnect.clone()
+    }
+
+    /// The data of the current connection, if the driver is connected.
+    pub fn current(&self) -> Option<PyConnectData> {
+        if self.is_connected() {
+            self.last_connect()
+        } else {
+            None
+        }
+    }
+
+    /// Waits until the driver is connected and returns the connection data.
+    pub async fn wait_connected(&self) -> Option<PyConnectData> {
+        let mut stage = self.stage.subscribe();
+        loop {
+            if *stage.borrow_and_update() == Stage::Connected {
+                return self.last_connect();
+            }
+            if stage.changed().await.is_err() {
+                return None;
+            }
+        }
+    }
+}
+
+struct TrackerHandler(Arc<ConnectionTracker>);
+
+#[async_trait]
+impl EventHandler for TrackerHandler {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        let tracker = &self.0;
+        match ctx {
+            EventContext::DriverConnect(connect) | EventContext::DriverReconnect(connect) => {
+                let mut history = tracker.history.lock().unwrap();
+                history.last_connect = Some(PyConnectData::from(connect));
+                history.attempts = 0;
+                drop(history);
+                tracker.stage.send_replace(Stage::Connected);
+            }
+            EventContext::DriverDisconnect(disconnect) => {
+                tracker.history.lock().unwrap().last_disconnect =
+                    Some(PyDisconnectData::from(disconnect));
+                tracker.stage.send_replace(Stage::Disconnected);
+            }
+            _ => {}
+        }
+        None
+    }
+}
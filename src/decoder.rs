@@ -0,0 +1,375 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::Duration;
+
+use songbird::input::reader::MediaSource;
+use songbird::input::{Input, Metadata};
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{Decoder, DecoderOptions, CODEC_TYPE_NULL, CODEC_TYPE_OPUS};
+use symphonia::core::errors::Error;
+use symphonia::core::formats::{FormatOptions, FormatReader, SeekMode, SeekTo};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{MetadataOptions, MetadataRevision, StandardTagKey};
+use symphonia::core::probe::Hint;
+
+use crate::opus;
+use crate::pcm::PyPcmFormat;
+
+fn to_io(err: Error) -> io::Error {
+    match err {
+        Error::IoError(err) => err,
+        err => io::Error::other(err),
+    }
+}
+
+/// Decodes a track into interleaved little-endian float samples at its own sample rate.
+/// The audio is resampled and mixed down by a `PcmReader` afterwards.
+struct DecodedSource {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    channels: usize,
+    frames: Option<u64>,
+    /// The samples of the last packet.
+    buffer: Vec<u8>,
+    position: usize,
+    /// Samples before this timestamp are dropped, which is where the last seek went to.
+    start: u64,
+}
+
+impl DecodedSource {
+    fn frame_size(&self) -> usize {
+        std::mem::size_of::<f32>() * self.channels
+    }
+
+    /// Decodes the next packet into `buffer`. Returns `false` at the end of the track.
+    fn decode(&mut self) -> io::Result<bool> {
+        loop {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(Error::IoError(err)) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                    return Ok(false)
+                }
+                // Chained Ogg streams can change the format, which is not supported.
+                Err(Error::ResetRequired) => return Ok(false),
+                Err(err) => return Err(to_io(err)),
+            };
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+
+            let decoded = match self.decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                Err(Error::DecodeError(err)) => {
+                    log::warn!("Skipping a packet that could not be decoded: {}", err);
+                    continue;
+                }
+                Err(err) => return Err(to_io(err)),
+            };
+            let mut samples = SampleBuffer::<f32>::new(decoded.capacity() as u64, *decoded.spec());
+            samples.copy_interleaved_ref(decoded);
+
+            // Symphonia's audio formats count timestamps in frames.
+            let skip = self.start.saturating_sub(packet.ts()) as usize * self.channels;
+            self.buffer.clear();
+            for sample in samples.samples().iter().skip(skip) {
+                self.buffer.extend_from_slice(&sample.to_le_bytes());
+            }
+            self.position = 0;
+            if !self.buffer.is_empty() {
+                return Ok(true);
+            }
+        }
+    }
+}
+
+impl Read for DecodedSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.buffer.len() && !self.decode()? {
+            return Ok(0);
+        }
+        let available = &self.buffer[self.position..];
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+impl Seek for DecodedSource {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(target) => target,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "Decoded sources can only seek from the start.",
+                ))
+            }
+        };
+
+        let frame = target / self.frame_size() as u64;
+        let seeked = self
+            .format
+            .seek(
+                SeekMode::Accurate,
+                SeekTo::TimeStamp {
+                    ts: frame,
+                    track_id: self.track_id,
+                },
+            )
+            .map_err(to_io)?;
+        self.decoder.reset();
+        self.start = seeked.required_ts;
+        self.buffer.clear();
+        self.position = 0;
+        Ok(seeked.required_ts * self.frame_size() as u64)
+    }
+}
+
+impl MediaSource for DecodedSource {
+    fn is_seekable(&self) -> bool {
+        true
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        self.frames.map(|frames| frames * self.frame_size() as u64)
+    }
+}
+
+fn tag(revision: Option<&MetadataRevision>, key: StandardTagKey) -> Option<String> {
+    revision?
+        .tags()
+        .iter()
+        .find(|tag| tag.std_key == Some(key))
+        .map(|tag| tag.value.to_string())
+}
+
+/// Opens an audio file and decodes it in this process. WAV, FLAC, MP3 and Ogg Vorbis are
+/// decoded with symphonia, Ogg Opus with the Opus decoder songbird already uses.
+pub fn decode(path: impl AsRef<Path>) -> io::Result<Input> {
+    let path = path.as_ref();
+    let file = File::open(path)?;
+
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|extension| extension.to_str()) {
+        hint.with_extension(extension);
+    }
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut probed = symphonia::default::get_probe()
+        .format(
+            &hint,
+            stream,
+            &FormatOptions {
+                enable_gapless: true,
+                ..Default::default()
+            },
+            &MetadataOptions::default(),
+        )
+        .map_err(to_io)?;
+
+    let track = probed
+        .format
+        .default_track()
+        .filter(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "The file has no audio."))?;
+    if track.codec_params.codec == CODEC_TYPE_OPUS {
+        return opus::ogg_opus(path);
+    }
+
+    let params = track.codec_params.clone();
+    let track_id = track.id;
+    let decoder = symphonia::default::get_codecs()
+        .make(&params, &DecoderOptions::default())
+        .map_err(to_io)?;
+    let sample_rate = params
+        .sample_rate
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "The sample rate is unknown."))?;
+    let channels = params
+        .channels
+        .map(|channels| channels.count())
+        .ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "The channel layout is unknown.")
+        })?;
+
+    let mut metadata = Metadata {
+        channels: Some(channels.min(2) as u8),
+        sample_rate: Some(sample_rate),
+        duration: params
+            .n_frames
+            .map(|frames| Duration::from_secs_f64(frames as f64 / f64::from(sample_rate))),
+        ..Default::default()
+    };
+    // Tags can be in the container or in front of it, like ID3 tags in MP3 files.
+    {
+        let mut probed_metadata = probed.metadata.get();
+        let mut format_metadata = probed.format.metadata();
+        for revision in [
+            format_metadata.skip_to_latest(),
+            probed_metadata
+                .as_mut()
+                .and_then(|metadata| metadata.skip_to_latest()),
+        ] {
+            metadata.title = metadata
+                .title
+                .or_else(|| tag(revision, StandardTagKey::TrackTitle));
+            metadata.artist = metadata
+                .artist
+                .or_else(|| tag(revision, StandardTagKey::Artist));
+            metadata.date = metadata
+                .date
+                .or_else(|| tag(revision, StandardTagKey::Date));
+        }
+    }
+
+    let source = DecodedSource {
+        format: probed.format,
+        decoder,
+        track_id,
+        channels,
+        frames: params.n_frames,
+        buffer: Vec::new(),
+        position: 0,
+        start: 0,
+    };
+    let mut input = PyPcmFormat::float(sample_rate, channels).input(Box::new(source));
+    input.metadata = Box::new(metadata);
+    Ok(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufWriter;
+    use std::path::PathBuf;
+
+    use songbird::input::Codec;
+
+    use super::*;
+    use crate::flac::FlacWriter;
+    use crate::ogg::OggOpusWriter;
+
+    /// A fixture in the temp directory that is removed when it is dropped.
+    struct Fixture(PathBuf);
+
+    impl Fixture {
+        fn new(name: &str) -> Self {
+            Self(std::env::temp_dir().join(format!("songbird-py-decoder-{}", name)))
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    /// Samples that are different in every frame and channel.
+    fn samples(frames: usize, channels: usize) -> Vec<i16> {
+        (0..frames * channels)
+            .map(|i| ((i * 7919) % 65_536) as u16 as i16)
+            .collect()
+    }
+
+    fn wav(name: &str, samples: &[i16], sample_rate: u32, channels: u16) -> Fixture {
+        let fixture = Fixture::new(name);
+        let spec = hound::WavSpec {
+            channels,
+            sample_rate,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&fixture.0, spec).unwrap();
+        for &sample in samples {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+        fixture
+    }
+
+    fn flac(name: &str, samples: &[i16], sample_rate: u32, channels: usize) -> Fixture {
+        let fixture = Fixture::new(name);
+        let file = BufWriter::new(File::create(&fixture.0).unwrap());
+        let mut writer = FlacWriter::new(file, sample_rate, channels).unwrap();
+        writer.write_samples(samples).unwrap();
+        writer.finish().unwrap();
+        fixture
+    }
+
+    /// Reads the rest of `input` as float samples.
+    fn read(input: &mut Input) -> Vec<f32> {
+        let mut bytes = Vec::new();
+        input.read_to_end(&mut bytes).unwrap();
+        bytes
+            .chunks_exact(4)
+            .map(|sample| f32::from_le_bytes(sample.try_into().unwrap()))
+            .collect()
+    }
+
+    fn floats(samples: &[i16]) -> Vec<f32> {
+        samples.iter().map(|&s| f32::from(s) / 32768.0).collect()
+    }
+
+    #[test]
+    fn wav_is_resampled() {
+        // A second of 24kHz mono is a second of 48kHz mono.
+        let fixture = wav("mono.wav", &samples(24_000, 1), 24_000, 1);
+        let mut input = decode(&fixture.0).unwrap();
+        assert!(!input.stereo);
+        assert_eq!(input.metadata.sample_rate, Some(24_000));
+        assert_eq!(input.metadata.channels, Some(1));
+        assert_eq!(input.metadata.duration, Some(Duration::from_secs(1)));
+        assert_eq!(read(&mut input).len(), 48_000);
+    }
+
+    #[test]
+    fn wav_is_mixed_down() {
+        // Half a second of 44.1kHz 5.1.
+        let fixture = wav("surround.wav", &samples(22_050, 6), 44_100, 6);
+        let mut input = decode(&fixture.0).unwrap();
+        assert!(input.stereo);
+        assert_eq!(input.metadata.channels, Some(2));
+        assert_eq!(read(&mut input).len(), 24_000 * 2);
+    }
+
+    #[test]
+    fn flac_is_lossless() {
+        let samples = samples(24_000, 2);
+        let fixture = flac("stereo.flac", &samples, 48_000, 2);
+        let mut input = decode(&fixture.0).unwrap();
+        assert!(input.stereo);
+        assert_eq!(read(&mut input), floats(&samples));
+    }
+
+    #[test]
+    fn seek_drops_the_samples_before_the_target() {
+        let samples = samples(48_000, 2);
+        let fixture = flac("seek.flac", &samples, 48_000, 2);
+        let mut input = decode(&fixture.0).unwrap();
+
+        // Not on a FLAC frame boundary, so the decoder starts before the target.
+        let frame = 10_001;
+        assert_eq!(input.seek(SeekFrom::Start(frame * 8)).unwrap(), frame * 8);
+        assert_eq!(read(&mut input), floats(&samples[frame as usize * 2..]));
+
+        assert_eq!(input.seek(SeekFrom::Start(0)).unwrap(), 0);
+        assert_eq!(read(&mut input), floats(&samples));
+    }
+
+    #[test]
+    fn opus_is_passed_to_the_opus_decoder() {
+        let fixture = Fixture::new("celt.opus");
+        let file = File::create(&fixture.0).unwrap();
+        let mut writer = OggOpusWriter::new(file, 1, 2, &[]).unwrap();
+        for i in 0..10 {
+            writer.write_packet(&[0xf8, 0, 0], 960 * (i + 1)).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let input = decode(&fixture.0).unwrap();
+        match input.kind {
+            Codec::Opus(state) => assert!(state.allow_passthrough),
+            _ => panic!("not decoded as Opus"),
+        }
+    }
+}
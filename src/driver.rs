@@ -1,16 +1,29 @@
+use std::collections::HashMap;
+use std::future::Future;
 use std::mem;
 use std::sync::Arc;
+use std::time::Duration;
 
+use pyo3::exceptions::{PyTimeoutError, PyValueError};
 use pyo3::prelude::*;
+use pyo3::types::PyTuple;
 use songbird::driver::{Bitrate, Driver};
 use songbird::id::{ChannelId, GuildId, UserId};
-use songbird::Config;
+use songbird::{Config, ConnectionInfo, CoreEvent, Event};
 use tokio::sync::Mutex;
 
 use crate::config::PyConfig;
-use crate::event::{EventHanlder, PyEvent};
-use crate::exceptions::{CouldNotConnectToRTPError, UseAsyncConstructorError};
+use crate::connection::{ConnectionTracker, PyConnectionState};
+use crate::event::{PyConnectData, PyDisconnectData};
+use crate::event::{EventHanlder, PyEvent, Registrar};
+use crate::exceptions::{
+    connection_error_to_py, CouldNotConnectToRTPError, UseAsyncConstructorError,
+};
+use crate::jitter::{self, StreamSink};
+use crate::registry::Registry;
 use crate::source::{PySource};
+use crate::ssrc::SsrcMap;
+use crate::stream::{PyEventStream, PyOverflowPolicy};
 use crate::track::PyTrack;
 use crate::track_handle::PyTrackHandle;
 
@@ -33,8 +46,91 @@ use crate::track_handle::PyTrackHandle;
 ///
 /// See more examples in the `example` directory.
 #[pyclass(name = "Driver")]
+#[derive(Clone)]
 pub struct PyDriver {
     driver: Arc<Mutex<Driver>>,
+    connection: Arc<ConnectionTracker>,
+    ssrcs: Arc<SsrcMap>,
+    registry: Arc<Registry>,
+}
+
+impl PyDriver {
+    pub fn from(mut driver: Driver) -> Self {
+        let connection = Arc::new(ConnectionTracker::new());
+        ConnectionTracker::register(&connection, &mut driver);
+        let ssrcs = Arc::new(SsrcMap::new());
+        SsrcMap::register(&ssrcs, &mut driver);
+
+        Self {
+            driver: Arc::new(Mutex::new(driver)),
+            connection,
+            ssrcs,
+            registry: Registry::new(),
+        }
+    }
+
+    pub fn driver(&self) -> Arc<Mutex<Driver>> {
+        self.driver.clone()
+    }
+
+    pub fn ssrcs(&self) -> Arc<SsrcMap> {
+        self.ssrcs.clone()
+    }
+
+    /// The registry for global event handlers that can be removed.
+    pub fn registry(&self) -> Arc<Registry> {
+        self.registry.clone()
+    }
+
+    /// Returns a future that connects the driver with `info` and keeps the
+    /// connection state up to date.
+    pub fn connect_with(
+        &self,
+        info: ConnectionInfo,
+    ) -> impl Future<Output = PyResult<()>> + Send + 'static {
+        let driver = self.driver.clone();
+        let connection = self.connection.clone();
+
+        async move {
+            let attempts = connection.set_connecting(info.clone());
+
+            // The lock is only held to start connecting, so the driver can be used while the
+            // connection is made.
+            let connect = driver.lock().await.connect(info);
+            let res = connect.await;
+
+            res.map_err(|err| {
+                connection.set_disconnected();
+                connection_error_to_py(&err, attempts)
+            })
+        }
+    }
+
+    /// Creates a stream and adds a handler for each event in `events` that feeds it.
+    fn stream(
+        &self,
+        events: Vec<Event>,
+        tagged: bool,
+        capacity: usize,
+        overflow: Option<PyOverflowPolicy>,
+    ) -> PyEventStream {
+        let stream = PyEventStream::new(capacity, overflow);
+        let handlers: Vec<_> = events
+            .into_iter()
+            .map(|event| stream.handler(event, tagged, self.ssrcs.clone()))
+            .collect();
+
+        let driver = self.driver.clone();
+        let registry = self.registry.clone();
+        pyo3_asyncio::tokio::get_runtime().spawn(async move {
+            let mut driver = driver.lock().await;
+            for handler in handlers {
+                handler.register(&registry, &mut driver);
+            }
+        });
+
+        stream
+    }
 }
 
 #[pymethods]
@@ -66,11 +162,7 @@ impl PyDriver {
             None => Config::default(),
         };
 
-        pyo3_asyncio::tokio::future_into_py(py, async move {
-            Ok(PyDriver {
-                driver: Arc::new(Mutex::new(Driver::new(config))),
-            })
-        })
+        pyo3_asyncio::tokio::future_into_py(py, async move { Ok(PyDriver::from(Driver::new(config))) })
     }
 
     /// Connect to a voice channel
@@ -86,6 +178,11 @@ impl PyDriver {
     ///     guild_id: Guild id you want to connct to.
     ///     channel_id: Channel id you want to connect to.
     ///     user_id: User id of the current user.
+    ///
+    /// Raises
+    /// ------
+    /// CouldNotConnectToRTPError
+    ///     The connection failed. A subclass is raised for each kind of failure.
     #[pyo3(
         text_signature = "($self, token: str, endpoint: str, session_id: str, guild_id: int, channel_id: int, user_id: int)"
     )]
@@ -99,29 +196,73 @@ impl PyDriver {
         channel_id: u64,
         user_id: u64,
     ) -> PyResult<&'p PyAny> {
-        let driver = self.driver.clone();
-
-        let endpoint = endpoint.replace("wss://", "");
+        let connect = self.connect_with(ConnectionInfo {
+            channel_id: Some(ChannelId::from(channel_id)),
+            endpoint: endpoint.replace("wss://", ""),
+            guild_id: GuildId::from(guild_id),
+            session_id,
+            token,
+            user_id: UserId::from(user_id),
+        });
+
+        pyo3_asyncio::tokio::future_into_py(py, connect)
+    }
 
-        pyo3_asyncio::tokio::future_into_py(py, async move {
-            let res = driver
-                .lock()
-                .await
-                .connect(songbird::ConnectionInfo {
-                    channel_id: Some(ChannelId::from(channel_id)),
-                    endpoint: endpoint,
-                    guild_id: GuildId::from(guild_id),
-                    session_id: session_id,
-                    token: token,
-                    user_id: UserId::from(user_id),
-                })
-                .await;
-
-            match res {
-                Err(err) => Err(CouldNotConnectToRTPError::new_err(format!("{:?}", err))),
-                Ok(_) => Ok(()),
+    /// Moves the driver to another voice channel.
+    /// The info from the last call to ``connect`` is reused for anything that is not passed in.
+    /// Tracks keep playing through the move. ``DriverConnect`` is fired once the new
+    /// connection is made.
+    ///
+    /// .. note::
+    ///
+    ///     Discord sends a new session id and often a new voice server after a move.
+    ///     Pass them in when you have them.
+    ///
+    /// Args:
+    ///     channel_id: Channel id you want to move to.
+    ///     session_id: New session id recieved from the Discord gateway.
+    ///     token: New token recieved from the Discord gateway.
+    ///     endpoint: New endpoint recieved from the Discord gateway.
+    ///
+    /// Raises
+    /// ------
+    /// CouldNotConnectToRTPError
+    ///     The driver was never connected or the new connection failed.
+    #[args(session_id = "None", token = "None", endpoint = "None")]
+    #[pyo3(
+        text_signature = "($self, channel_id: int, session_id: Optional[str], token: Optional[str], endpoint: Optional[str])"
+    )]
+    fn move_to<'p>(
+        &'p self,
+        py: Python<'p>,
+        channel_id: u64,
+        session_id: Option<String>,
+        token: Option<String>,
+        endpoint: Option<String>,
+    ) -> PyResult<&'p PyAny> {
+        let mut info = match self.connection.info() {
+            Some(info) => info,
+            None => {
+                return Err(CouldNotConnectToRTPError::new_err(
+                    "Driver has not been connected. `connect` should be used first.",
+                ))
             }
-        })
+        };
+
+        info.channel_id = Some(ChannelId::from(channel_id));
+        if let Some(session_id) = session_id {
+            info.session_id = session_id;
+        }
+        if let Some(token) = token {
+            info.token = token;
+        }
+        if let Some(endpoint) = endpoint {
+            info.endpoint = endpoint.replace("wss://", "");
+        }
+
+        let connect = self.connect_with(info);
+
+        pyo3_asyncio::tokio::future_into_py(py, connect)
     }
 
     /// Disables the driver.
@@ -129,13 +270,108 @@ impl PyDriver {
     #[pyo3(text_signature = "()")]
     fn leave<'p>(&'p self, py: Python<'p>) -> PyResult<&'p PyAny> {
         let driver = self.driver.clone();
+        let connection = self.connection.clone();
 
         pyo3_asyncio::tokio::future_into_py(py, async move {
             driver.lock().await.leave();
+            connection.set_disconnected();
             Ok(())
         })
     }
 
+    /// The current ``ConnectionState`` of the driver.
+    #[getter]
+    fn connection_state(&self) -> PyConnectionState {
+        self.connection.state()
+    }
+
+    /// Returns :data:`True` if the driver is connected to a voice channel.
+    #[getter]
+    fn is_connected(&self) -> bool {
+        self.connection.is_connected()
+    }
+
+    /// The ``ConnectData`` from the last time the driver connected or reconnected.
+    #[getter]
+    fn last_connect(&self) -> Option<PyConnectData> {
+        self.connection.last_connect()
+    }
+
+    /// The ``DisconnectData`` from the last time the driver disconnected.
+    #[getter]
+    fn last_disconnect(&self) -> Option<PyDisconnectData> {
+        self.connection.last_disconnect()
+    }
+
+    /// The id of the channel the driver is connected to.
+    #[getter]
+    fn channel_id(&self) -> Option<u64> {
+        self.connection.current().and_then(|data| data.channel_id)
+    }
+
+    /// The id of the guild the driver is connected to.
+    #[getter]
+    fn guild_id(&self) -> Option<u64> {
+        self.connection.current().map(|data| data.guild_id)
+    }
+
+    /// The SSRC of the driver's audio stream.
+    #[getter]
+    fn ssrc(&self) -> Option<u32> {
+        self.connection.current().map(|data| data.ssrc)
+    }
+
+    /// The voice server the driver is connected to.
+    #[getter]
+    fn server(&self) -> Option<String> {
+        self.connection.current().map(|data| data.server)
+    }
+
+    /// A copy of the map of SSRCs to the user ids of the other users in the voice channel.
+    /// Users are added when they first start speaking.
+    #[getter]
+    fn ssrc_map(&self) -> HashMap<u32, u64> {
+        self.ssrcs.users()
+    }
+
+    /// Waits until the driver is connected and returns the ``ConnectData``.
+    /// Returns ``None`` if the driver shuts down before it connects.
+    ///
+    /// Raises
+    /// ------
+    /// TimeoutError
+    ///     The driver did not connect within `timeout` seconds.
+    /// ValueError
+    ///     `timeout` is negative or not a finite number.
+    #[args(timeout = "None")]
+    #[pyo3(text_signature = "($self, timeout: Optional[float])")]
+    fn wait_connected<'p>(&'p self, py: Python<'p>, timeout: Option<f64>) -> PyResult<&'p PyAny> {
+        let connection = self.connection.clone();
+        if let Some(timeout) = timeout {
+            if !(timeout >= 0.0 && timeout.is_finite()) {
+                return Err(PyValueError::new_err(
+                    "timeout must be a finite number of seconds that is not negative.",
+                ));
+            }
+        }
+
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let connected = connection.wait_connected();
+
+            match timeout {
+                Some(timeout) => {
+                    match tokio::time::timeout(Duration::from_secs_f64(timeout), connected).await {
+                        Ok(data) => Ok(data),
+                        Err(_) => Err(PyTimeoutError::new_err(
+                            "Driver did not connect in time.",
+                        )),
+                    }
+                }
+                None => Ok(connected.await),
+            }
+        })
+    }
+
     /// Mutes the driver.
     #[pyo3(text_signature = "()")]
     fn mute<'p>(&'p self, py: Python<'p>) -> PyResult<&'p PyAny> {
@@ -300,6 +536,14 @@ impl PyDriver {
         })
     }
 
+    /// Adds a global event handler. `call` is a coroutine function that runs on the current
+    /// event loop. It can return an ``Event`` to change when it fires next, or
+    /// ``Event.Cancel()`` to remove itself.
+    ///
+    /// The driver does not wait for `call`, so the returned ``Event`` only takes effect once
+    /// `call` has finished. A handler that fires again before then is called again.
+    ///
+    /// Returns an ``EventSubscription`` that removes the handler.
     fn add_event<'p>(
         &'p self,
         py: Python<'p>,
@@ -309,21 +553,125 @@ impl PyDriver {
         let driver = self.driver.clone();
 
         let event_loop = pyo3_asyncio::get_running_loop(py)?;
-        let handler = EventHanlder {
-            coro: call,
-            event_loop: event_loop.into_py(py),
-        };
+        let handler = EventHanlder::new(
+            call,
+            event_loop.into_py(py),
+            event.event,
+            Registrar::Driver(driver.clone(), self.registry.clone()),
+        )
+        .with_ssrcs(self.ssrcs.clone());
+        let subscription = handler.subscription();
 
         pyo3_asyncio::tokio::future_into_py(py, async move {
-            Ok(driver.lock().await.add_global_event(event.event, handler))
+            handler.register(&mut *driver.lock().await);
+            Ok(subscription)
         })
     }
 
+    /// Returns an ``EventStream`` of the ``VoiceData`` of every received voice packet.
+    ///
+    /// `capacity` is how many packets are kept until they are read and `overflow` decides
+    /// what happens to new packets when the stream is full.
+    #[args(capacity = "256", overflow = "None")]
+    #[pyo3(text_signature = "($self, capacity: int, overflow: Optional[OverflowPolicy])")]
+    fn voice_packets(&self, capacity: usize, overflow: Option<PyOverflowPolicy>) -> PyEventStream {
+        self.stream(
+            vec![Event::Core(CoreEvent::VoicePacket)],
+            false,
+            capacity,
+            overflow,
+        )
+    }
+
+    /// Returns an ``EventStream`` of ``AudioFrame`` that goes through a jitter buffer.
+    ///
+    /// Packets from each user are put back in order, duplicates are dropped and lost packets
+    /// are filled in by the Opus decoder, then every user's audio is played out in 20 ms
+    /// frames on a steady clock. Playback for a user starts once `depth` packets are
+    /// buffered, so a larger depth survives more jitter at the cost of latency. Frames stop
+    /// while a user is not sending audio.
+    ///
+    /// The driver's ``DecodeMode`` must not be ``Pass``. ``Decrypt`` is enough, because the
+    /// jitter buffer decodes the packets itself. See ``voice_packets`` for `capacity` and
+    /// `overflow`.
+    #[args(depth = "3", capacity = "256", overflow = "None")]
+    #[pyo3(
+        text_signature = "($self, depth: int, capacity: int, overflow: Optional[OverflowPolicy])"
+    )]
+    fn buffered_audio(
+        &self,
+        depth: usize,
+        capacity: usize,
+        overflow: Option<PyOverflowPolicy>,
+    ) -> PyEventStream {
+        let stream = PyEventStream::new(capacity, overflow);
+        let sink = StreamSink {
+            sender: stream.sender(),
+            ssrcs: self.ssrcs.clone(),
+        };
+        jitter::start(self, depth, sink);
+        stream
+    }
+
+    /// Returns an ``EventStream`` of ``SpeakingUpdateData``, see ``voice_packets``.
+    #[args(capacity = "256", overflow = "None")]
+    #[pyo3(text_signature = "($self, capacity: int, overflow: Optional[OverflowPolicy])")]
+    fn speaking_updates(
+        &self,
+        capacity: usize,
+        overflow: Option<PyOverflowPolicy>,
+    ) -> PyEventStream {
+        self.stream(
+            vec![Event::Core(CoreEvent::SpeakingUpdate)],
+            false,
+            capacity,
+            overflow,
+        )
+    }
+
+    /// Returns an ``EventStream`` of ``(event, data)`` tuples for each of `kinds`.
+    /// `data` is the same object an event handler for `event` would get.
+    ///
+    /// .. code-block:: python
+    ///
+    ///     async for event, data in driver.events(Event.DriverConnect, Event.DriverDisconnect):
+    ///         print(event, data)
+    #[args(kinds = "*", capacity = "256", overflow = "None")]
+    #[pyo3(
+        text_signature = "($self, *kinds: Event, capacity: int, overflow: Optional[OverflowPolicy])"
+    )]
+    fn events(
+        &self,
+        kinds: &PyTuple,
+        capacity: usize,
+        overflow: Option<PyOverflowPolicy>,
+    ) -> PyResult<PyEventStream> {
+        let kinds: Vec<PyEvent> = kinds.extract()?;
+        if kinds.is_empty() {
+            return Err(PyValueError::new_err("At least one event kind is required"));
+        }
+        Ok(self.stream(
+            kinds.into_iter().map(|kind| kind.event).collect(),
+            true,
+            capacity,
+            overflow,
+        ))
+    }
+
     fn remove_all_events<'p>(&'p self, py: Python<'p>) -> PyResult<&'p PyAny> {
         let driver = self.driver.clone();
+        let connection = self.connection.clone();
+        let ssrcs = self.ssrcs.clone();
+        let registry = self.registry.clone();
 
         pyo3_asyncio::tokio::future_into_py(py, async move {
-            Ok(driver.lock().await.remove_all_global_events())
+            let mut driver = driver.lock().await;
+            driver.remove_all_global_events();
+            registry.clear();
+            // The connection state and SSRC map are tracked with global events.
+            ConnectionTracker::register(&connection, &mut driver);
+            SsrcMap::register(&ssrcs, &mut driver);
+            Ok(())
         })
     }
 }
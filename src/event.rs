@@ -1,115 +1,415 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
 use std::time::Duration;
 
 use async_trait::async_trait;
+use flume::{Receiver, Sender};
 use log::warn;
+use once_cell::sync::Lazy;
 use pyo3::basic::CompareOp;
 use pyo3::exceptions::{PyNotImplementedError, PyTypeError};
 use pyo3::prelude::*;
-use pyo3::types::PyDict;
-use songbird::events::context_data::{ConnectData, DisconnectKind, DisconnectReason, VoiceData};
+use songbird::driver::Driver;
+use songbird::events::context_data::{
+    ConnectData, DisconnectData, DisconnectKind, DisconnectReason, VoiceData,
+};
 use songbird::model::SpeakingState;
+use songbird::tracks::{TrackHandle, TrackResult};
 use songbird::{CoreEvent, Event, EventContext, EventHandler, TrackEvent};
+use tokio::sync::Mutex as AsyncMutex;
 
+use crate::buffer::{bytes_to_py, pcm_to_py};
+use crate::jitter::PyAudioFrame;
+use crate::registry::Registry;
+use crate::rtcp::PyRtcpData;
+use crate::ssrc::SsrcMap;
 use crate::track_handle::{PyTrackHandle, PyTrackState};
 use crate::utils::unwrap_f64_to_duration;
+use crate::vad::PyUtterance;
 
 use discortp::rtp::{Rtp, RtpType};
 
+/// The coroutine function and event loop of an event handler registered from Python.
+struct Callback {
+    coro: PyObject,
+    event_loop: PyObject,
+}
+
+/// Where an event handler is registered, so it can be registered again with the `Event` its
+/// callback returns.
+#[derive(Clone)]
+pub enum Registrar {
+    Driver(Arc<AsyncMutex<Driver>>, Arc<Registry>),
+    Track(TrackHandle),
+}
+
+/// Shared by every registration of a handler and its `PyEventSubscription`.
+struct Subscription {
+    registry: Arc<Registry>,
+    /// The id of the current registration in `registry`.
+    id: AtomicU64,
+    /// Set when the subscription is removed or the callback returns ``Event.Cancel()``.
+    removed: AtomicBool,
+}
+
+impl Subscription {
+    fn is_removed(&self) -> bool {
+        self.removed.load(Ordering::SeqCst)
+    }
+
+    /// Removes the current registration, but not the subscription.
+    fn unregister(&self) {
+        self.registry.remove(self.id.load(Ordering::SeqCst));
+    }
+
+    fn remove(&self) {
+        self.removed.store(true, Ordering::SeqCst);
+        self.unregister();
+    }
+
+    /// Records the id of a new registration, which is removed again if the subscription
+    /// was removed while it was being registered.
+    fn registered(&self, id: u64) {
+        self.id.store(id, Ordering::SeqCst);
+        if self.is_removed() {
+            self.registry.remove(id);
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct EventHanlder {
-    pub coro: PyObject,
-    pub event_loop: PyObject,
+    callback: Arc<Callback>,
+    /// The event this registration of the handler fires on.
+    event: Event,
+    registrar: Registrar,
+    subscription: Arc<Subscription>,
+    /// Set when the handler has been registered again with another event, which ends this
+    /// registration but not the subscription.
+    replaced: Arc<AtomicBool>,
+    ssrcs: Option<Arc<SsrcMap>>,
 }
 
 impl EventHanlder {
-    pub fn new(coro: PyObject, event_loop: PyObject) -> Self {
-        Self { coro, event_loop }
+    pub fn new(coro: PyObject, event_loop: PyObject, event: Event, registrar: Registrar) -> Self {
+        let registry = match &registrar {
+            Registrar::Driver(_, registry) => registry.clone(),
+            Registrar::Track(track) => Registry::track(track),
+        };
+        Self {
+            callback: Arc::new(Callback { coro, event_loop }),
+            event,
+            registrar,
+            subscription: Arc::new(Subscription {
+                registry,
+                id: AtomicU64::new(0),
+                removed: AtomicBool::new(false),
+            }),
+            replaced: Arc::new(AtomicBool::new(false)),
+            ssrcs: None,
+        }
     }
 
-    fn call_event(&self, py: Python, ctx: &EventContext) -> Result<PyObject, PyErr> {
-        let asyncio = py.import("asyncio")?;
-        let ensure_future = asyncio.getattr("ensure_future")?;
+    /// Uses `ssrcs` to add user ids to voice packets and speaking updates.
+    pub fn with_ssrcs(mut self, ssrcs: Arc<SsrcMap>) -> Self {
+        self.ssrcs = Some(ssrcs);
+        self
+    }
 
-        let coro_wrapper: Py<PyAny> = PyModule::from_code(
-            py,
-            "def wrap(coro, args):
-                if not isinstance(args, tuple):
-                    args = (args,)
-                return coro(*args)
-            ",
-            "",
-            "",
-        )?
-        .getattr("wrap")?
-        .into();
+    /// Returns a subscription that can be used to remove this handler.
+    pub fn subscription(&self) -> PyEventSubscription {
+        PyEventSubscription {
+            subscription: self.subscription.clone(),
+        }
+    }
 
-        let coro = coro_wrapper.call1(py, (&self.coro, event_to_py(py, ctx)?))?;
+    /// Registers the handler as a global event handler of `driver`.
+    pub fn register(self, driver: &mut Driver) {
+        let subscription = self.subscription.clone();
+        let event = self.event;
+        subscription.registered(subscription.registry.add(driver, event, self));
+    }
 
-        let kwargs = PyDict::from_sequence(py, [("loop", &self.event_loop)].into_py(py))?;
+    /// Registers the handler with `track`.
+    pub fn register_track(self, track: &TrackHandle) -> TrackResult<()> {
+        let subscription = self.subscription.clone();
+        let event = self.event;
+        let id = subscription.registry.add_to_track(track, event, self)?;
+        subscription.registered(id);
+        Ok(())
+    }
 
-        let res = ensure_future.call((coro,), Some(kwargs))?;
+    /// Applies the `Event` returned by the callback. Any event other than the one the
+    /// handler fires on registers the handler again in place of this registration.
+    fn reschedule(&self, event: Event) {
+        if event == Event::Cancel {
+            self.subscription.remove();
+            return;
+        }
+        // Untimed and periodic events keep firing on their own, delayed events only once.
+        let keeps_firing = !matches!(self.event, Event::Delayed(_));
+        if (event == self.event && keeps_firing) || self.subscription.is_removed() {
+            return;
+        }
+        if self.replaced.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        // Timed registrations are dropped by songbird the next time they fire.
+        self.subscription.unregister();
 
-        Ok(PyObject::from(res))
+        let handler = Self {
+            event,
+            replaced: Arc::new(AtomicBool::new(false)),
+            ..self.clone()
+        };
+        match &self.registrar {
+            Registrar::Driver(driver, _) => {
+                let driver = driver.clone();
+                pyo3_asyncio::tokio::get_runtime().spawn(async move {
+                    handler.register(&mut *driver.lock().await);
+                });
+            }
+            Registrar::Track(track) => {
+                if let Err(err) = handler.register_track(track) {
+                    warn!("Could not reschedule a track event handler: {}", err);
+                }
+            }
+        }
     }
 }
 
 #[async_trait]
 impl EventHandler for EventHanlder {
+    /// Runs on songbird's event task, so this must never take the GIL or wait for the
+    /// callback. The event is copied into a `Payload` and handed to the dispatcher thread
+    /// instead, and the `Event` the callback returns is applied by `reschedule`.
     async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
-        Python::with_gil(|py| match self.call_event(py, ctx) {
-            Ok(_) => None,
+        // Handlers of timed events can only be dropped by cancelling them when they fire.
+        if self.subscription.is_removed() || self.replaced.load(Ordering::Acquire) {
+            return Some(Event::Cancel);
+        }
+
+        let _ = DISPATCHER.send(Dispatch {
+            callback: self.callback.clone(),
+            payload: Payload::from(ctx, self.ssrcs.as_deref()),
+            reply: EventReply {
+                handler: self.clone(),
+            },
+        });
+        None
+    }
+}
+
+/// Returned by ``add_event``. Removes the event handler it was returned for.
+///
+/// The handler is not called again once it has been removed.
+///
+/// .. code-block:: python
+///
+///     subscription = await driver.add_event(Event.VoicePacket, on_voice_packet)
+///     subscription.remove()
+///
+///     # Or remove the handler when the block exits.
+///     with await driver.add_event(Event.VoicePacket, on_voice_packet):
+///         await asyncio.sleep(10)
+#[pyclass(name = "EventSubscription")]
+pub struct PyEventSubscription {
+    subscription: Arc<Subscription>,
+}
+
+#[pymethods]
+impl PyEventSubscription {
+    /// Removes the event handler.
+    #[pyo3(text_signature = "($self)")]
+    fn remove(&self) {
+        self.subscription.remove()
+    }
+
+    /// Same as `remove`.
+    #[pyo3(text_signature = "($self)")]
+    fn cancel(&self) {
+        self.remove()
+    }
+
+    /// :data:`True` until the handler is removed or cancels itself.
+    #[getter]
+    fn active(&self) -> bool {
+        !self.subscription.is_removed()
+    }
+
+    fn __enter__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+
+    fn __exit__(&self, _exc_type: &PyAny, _exc_value: &PyAny, _traceback: &PyAny) {
+        self.remove()
+    }
+}
+
+/// An event waiting to be handed to the event loop of its handler.
+struct Dispatch {
+    callback: Arc<Callback>,
+    payload: PyResult<Payload>,
+    reply: EventReply,
+}
+
+impl Dispatch {
+    fn run(self, py: Python, schedule: &PyObject) -> PyResult<()> {
+        let args = self.payload?.into_py(py);
+        self.callback.event_loop.call_method1(
+            py,
+            "call_soon_threadsafe",
+            (schedule, &self.callback.coro, args, self.reply),
+        )?;
+        Ok(())
+    }
+}
+
+/// Done callback for the task running an event handler.
+/// Applies the `Event` returned by the handler.
+#[pyclass]
+struct EventReply {
+    handler: EventHanlder,
+}
+
+#[pymethods]
+impl EventReply {
+    fn __call__(&self, py: Python, task: &PyAny) {
+        let event = match task.call_method0("result") {
+            Ok(result) => result.extract::<PyEvent>().ok().map(|event| event.event),
             Err(e) => {
                 e.print_and_set_sys_last_vars(py);
                 None
             }
-        })
+        };
+        if let Some(event) = event {
+            self.handler.reschedule(event);
+        }
     }
 }
 
-fn event_to_py(py: Python, event: &EventContext) -> PyResult<PyObject> {
-    match event {
-        EventContext::Track(track_array) => Ok((
-            PyTrackState::from(*track_array[0].0),
-            PyTrackHandle::from(track_array[0].1.clone()),
-        )
-            .into_py(py)),
-        EventContext::SpeakingStateUpdate(speaking) => Ok(PySpeaking {
-            delay: speaking.delay,
-            speaking: PySpeakingState::from(speaking.speaking),
-            ssrc: speaking.ssrc,
-            user_id: match speaking.user_id {
-                Some(id) => Some(id.0),
-                None => None,
-            },
+/// Events are sent to a single thread that takes the GIL and schedules the handlers with
+/// `call_soon_threadsafe`. The event task only queues events and never waits for a handler
+/// to run or return, so a busy interpreter can not hold up the driver.
+static DISPATCHER: Lazy<Sender<Dispatch>> = Lazy::new(|| {
+    let (tx, rx) = flume::unbounded();
+    thread::Builder::new()
+        .name("songbird-py events".into())
+        .spawn(move || dispatch(rx))
+        .expect("Failed to start the event dispatcher thread");
+    tx
+});
+
+fn dispatch(rx: Receiver<Dispatch>) {
+    let schedule = Python::with_gil(|py| -> PyResult<PyObject> {
+        Ok(PyModule::from_code(
+            py,
+            "import asyncio
+
+def schedule(coro, args, reply):
+    if not isinstance(args, tuple):
+        args = (args,)
+    asyncio.ensure_future(coro(*args)).add_done_callback(reply)
+",
+            "",
+            "",
+        )?
+        .getattr("schedule")?
+        .into())
+    });
+
+    let schedule = match schedule {
+        Ok(schedule) => schedule,
+        Err(e) => {
+            warn!("Event handlers will not be called: {}", e);
+            return;
         }
-        .into_py(py)),
-        EventContext::VoicePacket(data) => Ok(PyVoiceData::from(data).into_py(py)),
-        EventContext::SpeakingUpdate(data) => Ok(PySpeakingUpdateData {
-            speaking: data.speaking,
-            ssrc: data.ssrc,
+    };
+
+    for job in rx.iter() {
+        Python::with_gil(|py| {
+            if let Err(e) = job.run(py, &schedule) {
+                e.print_and_set_sys_last_vars(py);
+            }
+        });
+    }
+}
+
+/// The data of an event, copied out of the `EventContext` so it can be sent across threads.
+pub(crate) enum Payload {
+    Track(PyTrackState, PyTrackHandle),
+    Speaking(PySpeaking),
+    VoicePacket(PyVoiceData),
+    SpeakingUpdate(PySpeakingUpdateData),
+    ClientDisconnect(u64),
+    Connect(PyConnectData),
+    Disconnect(PyDisconnectData),
+    Rtcp(PyRtcpData),
+    AudioFrame(PyAudioFrame),
+    Utterance(PyUtterance),
+}
+
+impl Payload {
+    /// `ssrcs` is used to look up the user ids for voice packets and speaking updates.
+    pub(crate) fn from(event: &EventContext, ssrcs: Option<&SsrcMap>) -> PyResult<Self> {
+        let user_id = |ssrc| ssrcs.and_then(|ssrcs| ssrcs.user_id(ssrc));
+        match event {
+            EventContext::Track(track_array) => Ok(Self::Track(
+                PyTrackState::from(*track_array[0].0),
+                PyTrackHandle::from(track_array[0].1.clone()),
+            )),
+            EventContext::SpeakingStateUpdate(speaking) => Ok(Self::Speaking(PySpeaking {
+                delay: speaking.delay,
+                speaking: PySpeakingState::from(speaking.speaking),
+                ssrc: speaking.ssrc,
+                user_id: match speaking.user_id {
+                    Some(id) => Some(id.0),
+                    None => None,
+                },
+            })),
+            EventContext::VoicePacket(data) => Ok(Self::VoicePacket(PyVoiceData::from(
+                data,
+                user_id(data.packet.ssrc),
+            ))),
+            EventContext::RtcpPacket(data) => Ok(Self::Rtcp(PyRtcpData::from(data))),
+            EventContext::SpeakingUpdate(data) => Ok(Self::SpeakingUpdate(PySpeakingUpdateData {
+                speaking: data.speaking,
+                ssrc: data.ssrc,
+                user_id: user_id(data.ssrc),
+            })),
+            EventContext::ClientDisconnect(disconnect) => {
+                Ok(Self::ClientDisconnect(disconnect.user_id.0))
+            }
+            EventContext::DriverConnect(connect) => Ok(Self::Connect(PyConnectData::from(connect))),
+            EventContext::DriverReconnect(connect) => {
+                Ok(Self::Connect(PyConnectData::from(connect)))
+            }
+            EventContext::DriverDisconnect(disconnect) => {
+                Ok(Self::Disconnect(PyDisconnectData::from(disconnect)))
+            }
+            _ => Err(PyNotImplementedError::new_err(format!(
+                "{:?} is not implemented or deprecated",
+                event
+            ))),
         }
-        .into_py(py)),
-        EventContext::ClientDisconnect(disconnect) => Ok(disconnect.user_id.0.into_py(py)),
-        EventContext::DriverConnect(connect) => Ok(PyConnectData::from(connect).into_py(py)),
-        EventContext::DriverReconnect(connect) => Ok(PyConnectData::from(connect).into_py(py)),
-        EventContext::DriverDisconnect(disconnect) => Ok(PyDisconnectData {
-            kind: PyDisconnectKind::from(disconnect.kind),
-            reason: match disconnect.reason {
-                Some(reason) => Some(PyDisconnectReason::from(reason)),
-                None => None,
-            },
-            channel_id: match disconnect.channel_id {
-                Some(id) => Some(id.0),
-                None => None,
-            },
-            guild_id: disconnect.guild_id.0,
-            session_id: disconnect.session_id.to_string(),
+    }
+}
+
+impl IntoPy<PyObject> for Payload {
+    fn into_py(self, py: Python) -> PyObject {
+        match self {
+            Self::Track(state, handle) => (state, handle).into_py(py),
+            Self::Speaking(speaking) => speaking.into_py(py),
+            Self::VoicePacket(data) => data.into_py(py),
+            Self::SpeakingUpdate(data) => data.into_py(py),
+            Self::ClientDisconnect(user_id) => user_id.into_py(py),
+            Self::Connect(connect) => connect.into_py(py),
+            Self::Disconnect(disconnect) => disconnect.into_py(py),
+            Self::Rtcp(data) => data.into_py(py),
+            Self::AudioFrame(frame) => frame.into_py(py),
+            Self::Utterance(utterance) => utterance.into_py(py),
         }
-        .into_py(py)),
-        _ => Err(PyNotImplementedError::new_err(format!(
-            "{:?} is not implemented or deprecated",
-            event
-        ))),
     }
 }
 
@@ -585,8 +885,7 @@ pub struct PyRtp {
     pub ssrc: u32,
     #[pyo3(get)]
     pub csrc_list: Vec<u32>,
-    #[pyo3(get)]
-    pub payload: Vec<u8>,
+    pub payload: Arc<Vec<u8>>,
 }
 
 impl PyRtp {
@@ -602,40 +901,70 @@ impl PyRtp {
             timestamp: packet.timestamp.into(),
             ssrc: packet.ssrc,
             csrc_list: packet.csrc_list.clone(),
-            payload: packet.payload.clone(),
+            payload: Arc::new(packet.payload.clone()),
         }
     }
 }
 
+#[pymethods]
+impl PyRtp {
+    /// The packet body as a numpy array of ``uint8`` if numpy is installed, otherwise as a
+    /// ``memoryview``. The data is not copied.
+    #[getter]
+    fn payload(&self, py: Python) -> PyResult<PyObject> {
+        bytes_to_py(py, self.payload.clone())
+    }
+}
+
 #[pyclass(name = "VoiceData")]
 pub struct PyVoiceData {
-    #[pyo3(get)]
-    pub audio: Option<Vec<i16>>,
+    pub audio: Option<Arc<Vec<i16>>>,
     #[pyo3(get)]
     pub packet: PyRtp,
     #[pyo3(get)]
     pub payload_offset: usize,
     #[pyo3(get)]
     pub payload_end_pad: usize,
+    /// The user id for the SSRC of the packet, if it is known.
+    #[pyo3(get)]
+    pub user_id: Option<u64>,
 }
 
 impl PyVoiceData {
-    fn from(data: &VoiceData) -> Self {
+    fn from(data: &VoiceData, user_id: Option<u64>) -> Self {
         Self {
-            audio: (*data.audio).clone(),
+            audio: data.audio.clone().map(Arc::new),
             packet: PyRtp::from(data.packet),
             payload_offset: data.payload_offset,
             payload_end_pad: data.payload_end_pad,
+            user_id,
         }
     }
 }
 
+#[pymethods]
+impl PyVoiceData {
+    /// The decoded 48kHz stereo audio as interleaved 16 bit samples, if the driver decodes
+    /// audio. This is a numpy array of ``int16`` if numpy is installed, otherwise a
+    /// ``memoryview``. The data is not copied.
+    #[getter]
+    fn audio(&self, py: Python) -> PyResult<Option<PyObject>> {
+        self.audio
+            .clone()
+            .map(|audio| pcm_to_py(py, audio))
+            .transpose()
+    }
+}
+
 #[pyclass(name = "SpeakingUpdateData")]
 pub struct PySpeakingUpdateData {
     #[pyo3(get)]
     pub speaking: bool,
     #[pyo3(get)]
     pub ssrc: u32,
+    /// The user id for `ssrc`, if it is known.
+    #[pyo3(get)]
+    pub user_id: Option<u64>,
 }
 
 #[pyclass(name = "ClientConnect")]
@@ -649,21 +978,22 @@ pub struct PyClientConnect {
 }
 
 #[pyclass(name = "ConnectData")]
+#[derive(Clone)]
 pub struct PyConnectData {
     #[pyo3(get)]
-    channel_id: Option<u64>,
+    pub channel_id: Option<u64>,
     #[pyo3(get)]
-    guild_id: u64,
+    pub guild_id: u64,
     #[pyo3(get)]
-    session_id: String,
+    pub session_id: String,
     #[pyo3(get)]
-    server: String,
+    pub server: String,
     #[pyo3(get)]
-    ssrc: u32,
+    pub ssrc: u32,
 }
 
 impl PyConnectData {
-    fn from(connect: &ConnectData) -> Self {
+    pub fn from(connect: &ConnectData) -> Self {
         Self {
             channel_id: match connect.channel_id {
                 Some(id) => Some(id.0),
@@ -678,6 +1008,7 @@ impl PyConnectData {
 }
 
 #[pyclass(name = "DisconnectData")]
+#[derive(Clone)]
 pub struct PyDisconnectData {
     #[pyo3(get)]
     kind: PyDisconnectKind,
@@ -691,6 +1022,24 @@ pub struct PyDisconnectData {
     session_id: String,
 }
 
+impl PyDisconnectData {
+    pub fn from(disconnect: &DisconnectData) -> Self {
+        Self {
+            kind: PyDisconnectKind::from(disconnect.kind),
+            reason: match disconnect.reason {
+                Some(reason) => Some(PyDisconnectReason::from(reason)),
+                None => None,
+            },
+            channel_id: match disconnect.channel_id {
+                Some(id) => Some(id.0),
+                None => None,
+            },
+            guild_id: disconnect.guild_id.0,
+            session_id: disconnect.session_id.to_string(),
+        }
+    }
+}
+
 #[pyclass(name = "DisconnectKind")]
 #[derive(Clone)]
 pub struct PyDisconnectKind {
@@ -769,3 +1118,162 @@ impl PyDisconnectReason {
         Self::from(DisconnectReason::WsClosed(None))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use songbird::model::id::UserId;
+    use songbird::model::payload::{ClientDisconnect, Speaking};
+    use songbird::Config;
+
+    use super::*;
+
+    const DISCONNECT: Event = Event::Core(CoreEvent::ClientDisconnect);
+    const SPEAKING: Event = Event::Core(CoreEvent::SpeakingStateUpdate);
+
+    /// A driver and a callback that records the user ids and SSRCs it is called with.
+    struct Setup {
+        driver: Arc<AsyncMutex<Driver>>,
+        registry: Arc<Registry>,
+        module: PyObject,
+    }
+
+    impl Setup {
+        /// The callback returns the events in `returns` one after another and then `None`.
+        fn new(returns: Vec<PyEvent>) -> Self {
+            pyo3::prepare_freethreaded_python();
+            let module = Python::with_gil(|py| -> PyResult<PyObject> {
+                let module = PyModule::from_code(
+                    py,
+                    "import asyncio
+
+calls = []
+returns = []
+loop = asyncio.new_event_loop()
+
+async def callback(data):
+    calls.append(data if isinstance(data, int) else data.ssrc)
+    return returns.pop(0) if returns else None
+
+def run(seconds):
+    loop.run_until_complete(asyncio.sleep(seconds))
+",
+                    "",
+                    "",
+                )?;
+                module
+                    .getattr("returns")?
+                    .call_method1("extend", (returns,))?;
+                Ok(module.into())
+            })
+            .unwrap();
+            let runtime = pyo3_asyncio::tokio::get_runtime();
+            let driver = runtime.block_on(async { Driver::new(Config::default()) });
+
+            Self {
+                driver: Arc::new(AsyncMutex::new(driver)),
+                registry: Registry::new(),
+                module,
+            }
+        }
+
+        fn add_event(&self, event: Event) -> PyEventSubscription {
+            let handler = Python::with_gil(|py| {
+                let module = self.module.as_ref(py);
+                EventHanlder::new(
+                    module.getattr("callback").unwrap().into(),
+                    module.getattr("loop").unwrap().into(),
+                    event,
+                    Registrar::Driver(self.driver.clone(), self.registry.clone()),
+                )
+            });
+            let subscription = handler.subscription();
+            let runtime = pyo3_asyncio::tokio::get_runtime();
+            runtime.block_on(async { handler.register(&mut *self.driver.lock().await) });
+            subscription
+        }
+
+        /// Fires `event` and runs the event loop long enough for the callback to run.
+        fn fire(&self, event: Event, ctx: EventContext) {
+            let runtime = pyo3_asyncio::tokio::get_runtime();
+            runtime.block_on(self.registry.fire(event, &ctx));
+            Python::with_gil(|py| self.module.call_method1(py, "run", (0.2,)).unwrap());
+        }
+
+        fn disconnect(&self, user_id: u64) {
+            let disconnect = ClientDisconnect {
+                user_id: UserId(user_id),
+            };
+            self.fire(DISCONNECT, EventContext::ClientDisconnect(disconnect));
+        }
+
+        fn speaking(&self, ssrc: u32) {
+            let speaking = Speaking {
+                delay: None,
+                speaking: SpeakingState::MICROPHONE,
+                ssrc,
+                user_id: None,
+            };
+            self.fire(SPEAKING, EventContext::SpeakingStateUpdate(speaking));
+        }
+
+        fn calls(&self) -> Vec<u64> {
+            Python::with_gil(|py| {
+                let calls = self.module.getattr(py, "calls").unwrap();
+                calls.extract(py).unwrap()
+            })
+        }
+    }
+
+    #[test]
+    fn removed_handlers_are_not_called() {
+        let setup = Setup::new(vec![]);
+        let subscription = setup.add_event(DISCONNECT);
+
+        setup.disconnect(1);
+        assert_eq!(setup.calls(), [1]);
+
+        subscription.remove();
+        assert!(!subscription.active());
+        setup.disconnect(2);
+        assert_eq!(setup.calls(), [1]);
+    }
+
+    #[test]
+    fn cancelled_handlers_are_not_called() {
+        let setup = Setup::new(vec![PyEvent::Cancel()]);
+        let subscription = setup.add_event(DISCONNECT);
+
+        setup.disconnect(1);
+        assert!(!subscription.active());
+        setup.disconnect(2);
+        assert_eq!(setup.calls(), [1]);
+    }
+
+    #[test]
+    fn rescheduled_handlers_fire_once_per_event() {
+        let returns = vec![PyEvent::SpeakingStateUpdate(), PyEvent::ClientDisconnect()];
+        let setup = Setup::new(returns);
+        let subscription = setup.add_event(DISCONNECT);
+        let registered = || (setup.registry.len(DISCONNECT), setup.registry.len(SPEAKING));
+
+        setup.disconnect(1);
+        assert_eq!(registered(), (0, 1));
+        setup.disconnect(2);
+        assert_eq!(setup.calls(), [1]);
+
+        setup.speaking(3);
+        assert_eq!(registered(), (1, 0));
+        setup.speaking(4);
+        assert_eq!(setup.calls(), [1, 3]);
+
+        setup.disconnect(5);
+        setup.speaking(6);
+        assert_eq!(setup.calls(), [1, 3, 5]);
+        assert_eq!(registered(), (1, 0));
+
+        subscription.remove();
+        assert_eq!(registered(), (0, 0));
+        setup.disconnect(7);
+        assert_eq!(setup.calls(), [1, 3, 5]);
+    }
+}
@@ -1,4 +1,7 @@
 use pyo3::create_exception;
+use pyo3::prelude::*;
+use songbird::error::ConnectionError;
+use songbird::events::context_data::DisconnectReason;
 
 // Base Exception for all songbird errors
 create_exception!(module, SongbirdError, pyo3::exceptions::PyException);
@@ -11,3 +14,67 @@ create_exception!(module, CouldNotConstructError, SongbirdError);
 create_exception!(module, YtdlError, SongbirdError);
 create_exception!(module, FfmpegError, SongbirdError);
 create_exception!(module, TrackError, SongbirdError);
+
+// Connection errors. These all inherit `CouldNotConnectToRTPError` so existing handlers keep working.
+create_exception!(module, ConnectionTimeoutError, CouldNotConnectToRTPError);
+create_exception!(module, AttemptDiscardedError, CouldNotConnectToRTPError);
+create_exception!(module, ConnectionCryptoError, CouldNotConnectToRTPError);
+create_exception!(module, CryptoModeRejectedError, CouldNotConnectToRTPError);
+create_exception!(module, CryptoModeInvalidError, CryptoModeRejectedError);
+create_exception!(module, CryptoModeUnavailableError, CryptoModeRejectedError);
+create_exception!(module, EndpointUrlError, CouldNotConnectToRTPError);
+create_exception!(module, HandshakeError, CouldNotConnectToRTPError);
+create_exception!(module, IpDiscoveryError, CouldNotConnectToRTPError);
+create_exception!(module, ConnectionIoError, CouldNotConnectToRTPError);
+create_exception!(module, ConnectionJsonError, CouldNotConnectToRTPError);
+create_exception!(module, InterconnectError, CouldNotConnectToRTPError);
+create_exception!(module, WebsocketError, CouldNotConnectToRTPError);
+create_exception!(module, WebsocketClosedError, WebsocketError);
+
+/// Converts a songbird `ConnectionError` to the matching python exception.
+///
+/// Every exception has `attempts`, `close_code` and `errno` attributes. `close_code` is only set
+/// for websocket closes and `errno` is only set for io errors.
+pub fn connection_error_to_py(err: &ConnectionError, attempts: usize) -> PyErr {
+    let message = format!("{:?}", err);
+
+    let (py_err, close_code, errno) = match err {
+        ConnectionError::AttemptDiscarded => (AttemptDiscardedError::new_err(message), None, None),
+        ConnectionError::Crypto(_) => (ConnectionCryptoError::new_err(message), None, None),
+        ConnectionError::CryptoModeInvalid => {
+            (CryptoModeInvalidError::new_err(message), None, None)
+        }
+        ConnectionError::CryptoModeUnavailable => {
+            (CryptoModeUnavailableError::new_err(message), None, None)
+        }
+        ConnectionError::EndpointUrl => (EndpointUrlError::new_err(message), None, None),
+        ConnectionError::ExpectedHandshake => (HandshakeError::new_err(message), None, None),
+        ConnectionError::IllegalDiscoveryResponse | ConnectionError::IllegalIp => {
+            (IpDiscoveryError::new_err(message), None, None)
+        }
+        ConnectionError::Io(io) => (ConnectionIoError::new_err(message), None, io.raw_os_error()),
+        ConnectionError::Json(_) => (ConnectionJsonError::new_err(message), None, None),
+        ConnectionError::InterconnectFailure(_) => {
+            (InterconnectError::new_err(message), None, None)
+        }
+        ConnectionError::Ws(_) => match DisconnectReason::from(err) {
+            DisconnectReason::WsClosed(Some(code)) => (
+                WebsocketClosedError::new_err(message),
+                Some(code as u16),
+                None,
+            ),
+            _ => (WebsocketError::new_err(message), None, None),
+        },
+        ConnectionError::TimedOut => (ConnectionTimeoutError::new_err(message), None, None),
+        _ => (CouldNotConnectToRTPError::new_err(message), None, None),
+    };
+
+    Python::with_gil(|py| {
+        let value = py_err.value(py);
+        let _ = value.setattr("attempts", attempts);
+        let _ = value.setattr("close_code", close_code);
+        let _ = value.setattr("errno", errno);
+    });
+
+    py_err
+}
@@ -0,0 +1,376 @@
+use std::io::{self, Seek, SeekFrom, Write};
+
+/// Samples per channel in a frame.
+const BLOCK_SIZE: usize = 4096;
+const BITS_PER_SAMPLE: u32 = 16;
+/// Largest Rice parameter of the 4 bit coding method, 15 is reserved as an escape code.
+const MAX_RICE_PARAMETER: u32 = 14;
+
+/// Position of the total sample count in the file, which is filled in by `finish`.
+const STREAMINFO_OFFSET: u64 = 8;
+
+/// Writes 16 bit PCM into a FLAC file.
+/// Every channel is compressed with the best fixed predictor, which gets most of the way
+/// to the reference encoder for speech without needing LPC analysis.
+pub struct FlacWriter<W: Write + Seek> {
+    inner: W,
+    sample_rate: u32,
+    channels: Vec<Vec<i32>>,
+    frame_number: u64,
+    total: u64,
+}
+
+impl<W: Write + Seek> FlacWriter<W> {
+    pub fn new(mut inner: W, sample_rate: u32, channels: usize) -> io::Result<Self> {
+        inner.write_all(b"fLaC")?;
+        // Last metadata block, STREAMINFO, 34 bytes long.
+        inner.write_all(&[0x80, 0, 0, 34])?;
+        inner.write_all(&streaminfo(sample_rate, channels, 0))?;
+
+        Ok(Self {
+            inner,
+            sample_rate,
+            channels: vec![Vec::with_capacity(BLOCK_SIZE); channels],
+            frame_number: 0,
+            total: 0,
+        })
+    }
+
+    /// Writes interleaved samples. The length of `samples` has to be a multiple of the
+    /// channel count.
+    pub fn write_samples(&mut self, samples: &[i16]) -> io::Result<()> {
+        let count = self.channels.len();
+        for frame in samples.chunks_exact(count) {
+            for (channel, sample) in self.channels.iter_mut().zip(frame) {
+                channel.push(i32::from(*sample));
+            }
+            if self.channels[0].len() == BLOCK_SIZE {
+                self.write_frame()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the remaining samples, fills in the length of the stream and returns the inner
+    /// writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        if !self.channels[0].is_empty() {
+            self.write_frame()?;
+        }
+
+        let end = self.inner.stream_position()?;
+        self.inner.seek(SeekFrom::Start(STREAMINFO_OFFSET))?;
+        self.inner.write_all(&streaminfo(
+            self.sample_rate,
+            self.channels.len(),
+            self.total,
+        ))?;
+        self.inner.seek(SeekFrom::Start(end))?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn write_frame(&mut self) -> io::Result<()> {
+        let len = self.channels[0].len();
+        let mut bits = BitWriter::default();
+
+        // Sync code and fixed block size.
+        bits.write(0xfff8, 16);
+        let block_code = if len == BLOCK_SIZE { 0b1100 } else { 0b0111 };
+        bits.write(block_code, 4);
+        bits.write(sample_rate_code(self.sample_rate), 4);
+        // Independent channels.
+        bits.write(self.channels.len() as u64 - 1, 4);
+        // 16 bits per sample and a reserved bit.
+        bits.write(0b1000, 4);
+        write_utf8(&mut bits, self.frame_number);
+        if block_code == 0b0111 {
+            bits.write(len as u64 - 1, 16);
+        }
+        let crc = crc8(&bits.bytes);
+        bits.write(u64::from(crc), 8);
+
+        for channel in &self.channels {
+            write_subframe(&mut bits, channel);
+        }
+        bits.align();
+        let crc = crc16(&bits.bytes);
+        bits.write(u64::from(crc), 16);
+
+        self.inner.write_all(&bits.bytes)?;
+        self.frame_number += 1;
+        self.total += len as u64;
+        self.channels.iter_mut().for_each(Vec::clear);
+        Ok(())
+    }
+}
+
+fn streaminfo(sample_rate: u32, channels: usize, total: u64) -> [u8; 34] {
+    let mut bits = BitWriter::default();
+    bits.write(BLOCK_SIZE as u64, 16);
+    bits.write(BLOCK_SIZE as u64, 16);
+    // The frame sizes are unknown.
+    bits.write(0, 24);
+    bits.write(0, 24);
+    bits.write(u64::from(sample_rate), 20);
+    bits.write(channels as u64 - 1, 3);
+    bits.write(u64::from(BITS_PER_SAMPLE) - 1, 5);
+    bits.write(total, 36);
+
+    let mut info = [0; 34];
+    // The MD5 signature is left out, which is allowed.
+    info[..18].copy_from_slice(&bits.bytes);
+    info
+}
+
+fn sample_rate_code(sample_rate: u32) -> u64 {
+    match sample_rate {
+        44_100 => 0b1001,
+        48_000 => 0b1010,
+        96_000 => 0b1011,
+        // Read it from STREAMINFO.
+        _ => 0b0000,
+    }
+}
+
+/// Writes the frame number with the same variable length coding as UTF-8.
+fn write_utf8(bits: &mut BitWriter, value: u64) {
+    if value < 0x80 {
+        bits.write(value, 8);
+        return;
+    }
+
+    let mut continuation = 1;
+    while value >= 1 << (5 * continuation + 6) {
+        continuation += 1;
+    }
+    let lead = (0xff00u64 >> (continuation + 1)) & 0xff;
+    bits.write(lead | (value >> (6 * continuation)), 8);
+    for i in (0..continuation).rev() {
+        bits.write(0x80 | ((value >> (6 * i)) & 0x3f), 8);
+    }
+}
+
+/// Returns the residual of the fixed predictor of `order`.
+fn residual(samples: &[i32], order: usize) -> Vec<i32> {
+    let s = samples;
+    (order..s.len())
+        .map(|n| match order {
+            0 => s[n],
+            1 => s[n] - s[n - 1],
+            2 => s[n] - 2 * s[n - 1] + s[n - 2],
+            3 => s[n] - 3 * s[n - 1] + 3 * s[n - 2] - s[n - 3],
+            _ => s[n] - 4 * s[n - 1] + 6 * s[n - 2] - 4 * s[n - 3] + s[n - 4],
+        })
+        .collect()
+}
+
+fn zigzag(value: i32) -> u32 {
+    ((value << 1) ^ (value >> 31)) as u32
+}
+
+/// Returns the Rice parameter that codes `residual` in the fewest bits, and that size.
+fn rice_parameter(residual: &[i32]) -> (u32, u64) {
+    (0..=MAX_RICE_PARAMETER)
+        .map(|parameter| {
+            let size = residual
+                .iter()
+                .map(|value| u64::from(zigzag(*value) >> parameter) + 1 + u64::from(parameter))
+                .sum();
+            (parameter, size)
+        })
+        .min_by_key(|(_, size)| *size)
+        .unwrap()
+}
+
+fn write_subframe(bits: &mut BitWriter, samples: &[i32]) {
+    let verbatim = samples.len() as u64 * u64::from(BITS_PER_SAMPLE);
+
+    let best = (0..=4)
+        .filter(|order| *order < samples.len())
+        .map(|order| {
+            let residual = residual(samples, order);
+            let (parameter, size) = rice_parameter(&residual);
+            let size = size + 6 + order as u64 * u64::from(BITS_PER_SAMPLE);
+            (order, residual, parameter, size)
+        })
+        .min_by_key(|(_, _, _, size)| *size);
+
+    match best {
+        Some((order, residual, parameter, size)) if size < verbatim => {
+            bits.write(0b0001_0000 | ((order as u64) << 1), 8);
+            for sample in &samples[..order] {
+                bits.write(*sample as u64, BITS_PER_SAMPLE);
+            }
+            // Rice coding with a 4 bit parameter and a single partition.
+            bits.write(0, 2);
+            bits.write(0, 4);
+            bits.write(u64::from(parameter), 4);
+            for value in residual {
+                let value = zigzag(value);
+                bits.write_unary(value >> parameter);
+                bits.write(u64::from(value), parameter);
+            }
+        }
+        _ => {
+            bits.write(0b0000_0010, 8);
+            for sample in samples {
+                bits.write(*sample as u64, BITS_PER_SAMPLE);
+            }
+        }
+    }
+}
+
+fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ (u16::from(byte) << 8), |crc, _| {
+            if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+/// Writes values into bytes, most significant bit first.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u64,
+    len: u32,
+}
+
+impl BitWriter {
+    /// Writes the lowest `count` bits of `value`.
+    fn write(&mut self, value: u64, count: u32) {
+        for bit in (0..count).rev() {
+            self.buffer = (self.buffer << 1) | ((value >> bit) & 1);
+            self.len += 1;
+            if self.len == 8 {
+                self.bytes.push(self.buffer as u8);
+                self.buffer = 0;
+                self.len = 0;
+            }
+        }
+    }
+
+    fn write_unary(&mut self, zeros: u32) {
+        for _ in 0..zeros {
+            self.write(0, 1);
+        }
+        self.write(1, 1);
+    }
+
+    /// Pads the last byte with zeros.
+    fn align(&mut self) {
+        if self.len > 0 {
+            self.write(0, 8 - self.len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use symphonia::core::audio::SampleBuffer;
+    use symphonia::core::codecs::DecoderOptions;
+    use symphonia::core::formats::{FormatOptions, FormatReader};
+    use symphonia::core::io::MediaSourceStream;
+    use symphonia::default::formats::FlacReader;
+
+    use super::*;
+
+    fn encode(samples: &[i16], sample_rate: u32, channels: usize) -> Vec<u8> {
+        let mut writer = FlacWriter::new(Cursor::new(Vec::new()), sample_rate, channels).unwrap();
+        // Uneven writes, so frames are filled from more than one call.
+        for chunk in samples.chunks(channels * 1000) {
+            writer.write_samples(chunk).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn decode(data: Vec<u8>) -> (Vec<i16>, u64) {
+        let stream = MediaSourceStream::new(Box::new(Cursor::new(data)), Default::default());
+        let mut reader = FlacReader::try_new(stream, &FormatOptions::default()).unwrap();
+        let params = reader.default_track().unwrap().codec_params.clone();
+        let mut decoder = symphonia::default::get_codecs()
+            .make(&params, &DecoderOptions { verify: true })
+            .unwrap();
+
+        let mut samples = Vec::new();
+        while let Ok(packet) = reader.next_packet() {
+            let decoded = decoder.decode(&packet).unwrap();
+            let mut buffer = SampleBuffer::<i16>::new(decoded.capacity() as u64, *decoded.spec());
+            buffer.copy_interleaved_ref(decoded);
+            samples.extend_from_slice(buffer.samples());
+        }
+        (samples, params.n_frames.unwrap())
+    }
+
+    /// A simple linear congruential generator, so the test does not depend on `rand`'s
+    /// output staying the same.
+    fn noise(seed: &mut u32) -> i16 {
+        *seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        (*seed >> 16) as i16
+    }
+
+    fn assert_round_trip(samples: &[i16], sample_rate: u32, channels: usize) {
+        let (decoded, frames) = decode(encode(samples, sample_rate, channels));
+        assert_eq!(frames, (samples.len() / channels) as u64);
+        assert_eq!(decoded.len(), samples.len());
+        assert!(decoded == samples, "decoded samples differ");
+    }
+
+    #[test]
+    fn stereo_tone_and_noise() {
+        let mut seed = 1;
+        let samples: Vec<i16> = (0..48_000 * 2 + 123)
+            .map(|i| {
+                let tone = 8000.0 * (i as f32 * 0.01).sin();
+                tone as i16 / 2 + noise(&mut seed) / 64
+            })
+            .flat_map(|sample| [sample, sample.wrapping_neg()])
+            .collect();
+        assert_round_trip(&samples, 48_000, 2);
+    }
+
+    #[test]
+    fn full_scale_noise() {
+        let mut seed = 7;
+        let samples: Vec<i16> = (0..BLOCK_SIZE * 3).map(|_| noise(&mut seed)).collect();
+        assert_round_trip(&samples, 44_100, 1);
+    }
+
+    #[test]
+    fn extremes_and_silence() {
+        let samples: Vec<i16> = (0..BLOCK_SIZE * 2 + 1)
+            .map(|i| match (i / 100) % 4 {
+                0 => 0,
+                1 => i16::MAX,
+                2 => i16::MIN,
+                _ => {
+                    if i % 2 == 0 {
+                        i16::MAX
+                    } else {
+                        i16::MIN
+                    }
+                }
+            })
+            .collect();
+        assert_round_trip(&samples, 16_000, 1);
+    }
+}
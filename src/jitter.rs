@@ -0,0 +1,405 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::convert::TryInto;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use pyo3::prelude::*;
+use songbird::driver::opus::coder::Decoder;
+use songbird::driver::opus::{Channels, SampleRate};
+use songbird::{CoreEvent, Event, EventContext, EventHandler};
+
+use crate::buffer::pcm_to_py;
+use crate::driver::PyDriver;
+use crate::event::Payload;
+use crate::recorder::opus_payload;
+use crate::registry::Registrations;
+use crate::ssrc::SsrcMap;
+use crate::stream::StreamSender;
+
+/// Interleaved stereo samples in 20 ms.
+pub const STEREO_FRAME_SIZE: usize = 1920;
+const FRAME_LENGTH: Duration = Duration::from_millis(20);
+/// Room for the longest Opus packet, 120 ms.
+const MAX_PACKET_SIZE: usize = 6 * STEREO_FRAME_SIZE;
+/// Packets this far ahead of the one being played restart the buffer.
+const MAX_SEQUENCE_JUMP: i64 = 250;
+/// Sources that have not sent a packet for this long are forgotten.
+const SOURCE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// 20 ms of decoded audio from one SSRC.
+pub struct Frame {
+    pub ssrc: u32,
+    /// The RTP timestamp of the first sample.
+    pub timestamp: u32,
+    /// Interleaved 48kHz stereo samples.
+    pub audio: Arc<Vec<i16>>,
+    /// Whether the audio was made up by packet loss concealment.
+    pub concealed: bool,
+}
+
+/// Receives the frames the jitter buffer plays every 20 ms.
+#[async_trait]
+pub trait FrameSink: Send + 'static {
+    /// Called on every tick, even if no source played anything.
+    /// Returns `false` to stop the jitter buffer.
+    async fn frames(&mut self, frames: Vec<Frame>) -> bool;
+}
+
+/// The buffered packets of one SSRC.
+struct Source {
+    decoder: Decoder,
+    /// Opus packets and their RTP timestamps by extended sequence number.
+    packets: BTreeMap<i64, (u32, Vec<u8>)>,
+    /// The sequence number of the newest packet, extended so that it does not wrap.
+    latest: Option<i64>,
+    /// The next packet to decode, or `None` while the buffer is filling up.
+    next: Option<i64>,
+    /// Decoded samples that have not been played yet.
+    pcm: VecDeque<i16>,
+    timestamp: u32,
+    last_packet: Instant,
+}
+
+impl Source {
+    fn new() -> Self {
+        Self {
+            decoder: Decoder::new(SampleRate::Hz48000, Channels::Stereo)
+                .expect("Failed to create opus decoder with known-good values."),
+            packets: BTreeMap::new(),
+            latest: None,
+            next: None,
+            pcm: VecDeque::new(),
+            timestamp: 0,
+            last_packet: Instant::now(),
+        }
+    }
+
+    fn insert(&mut self, sequence: u16, timestamp: u32, opus: &[u8]) {
+        self.last_packet = Instant::now();
+
+        let sequence = match self.latest {
+            Some(latest) => {
+                let offset = sequence.wrapping_sub(latest as u16) as i16;
+                latest + i64::from(offset)
+            }
+            None => i64::from(sequence),
+        };
+        if let Some(next) = self.next {
+            if sequence < next {
+                // Too late, its place has already been played.
+                return;
+            }
+            if sequence - next > MAX_SEQUENCE_JUMP {
+                // The source restarted its stream.
+                self.packets.clear();
+                self.next = None;
+            }
+        }
+
+        self.latest = Some(self.latest.map_or(sequence, |latest| latest.max(sequence)));
+        // Duplicates replace the first copy, which is the same packet.
+        self.packets.insert(sequence, (timestamp, opus.to_vec()));
+    }
+
+    /// Returns the next 20 ms of audio, or `None` if there is nothing to play.
+    fn play(&mut self, ssrc: u32, depth: usize) -> Option<Frame> {
+        let next = match self.next {
+            Some(next) => next,
+            None if self.packets.len() >= depth => {
+                let (&first, (timestamp, _)) = self.packets.iter().next()?;
+                self.timestamp = *timestamp;
+                first
+            }
+            None => return None,
+        };
+
+        // A steady clock plays slightly faster or slower than the sender, so packets pile up
+        // when the sender is faster.
+        let mut next = next;
+        while self.packets.len() > 2 * depth.max(1) {
+            let (skipped, _) = self.packets.pop_first()?;
+            next = next.max(skipped + 1);
+        }
+
+        let mut concealed = false;
+        while self.pcm.len() < STEREO_FRAME_SIZE {
+            if self.packets.is_empty() {
+                // The source stopped sending, which Discord clients do when they are silent.
+                self.next = None;
+                self.pcm.clear();
+                return None;
+            }
+
+            let mut out = vec![0; MAX_PACKET_SIZE];
+            let packet = self.packets.remove(&next);
+            concealed |= packet.is_none();
+            next += 1;
+
+            let decoded = match &packet {
+                Some((_, opus)) => opus.as_slice().try_into().and_then(|opus| {
+                    let out = (&mut out[..]).try_into()?;
+                    self.decoder.decode(Some(opus), out, false)
+                }),
+                // Without a packet the decoder fills the whole output, so it is cut to 20 ms.
+                None => (&mut out[..STEREO_FRAME_SIZE])
+                    .try_into()
+                    .and_then(|out| self.decoder.decode(None, out, false)),
+            };
+
+            match decoded {
+                Ok(samples) => self.pcm.extend(&out[..2 * samples]),
+                Err(err) => {
+                    log::warn!("Failed to decode packet from SSRC {}: {:?}", ssrc, err);
+                    concealed = true;
+                    self.pcm.resize(self.pcm.len() + STEREO_FRAME_SIZE, 0);
+                }
+            }
+        }
+        self.next = Some(next);
+
+        let frame = Frame {
+            ssrc,
+            timestamp: self.timestamp,
+            audio: Arc::new(self.pcm.drain(..STEREO_FRAME_SIZE).collect()),
+            concealed,
+        };
+        self.timestamp = self.timestamp.wrapping_add((STEREO_FRAME_SIZE / 2) as u32);
+        Some(frame)
+    }
+}
+
+struct Shared {
+    sources: Mutex<HashMap<u32, Source>>,
+    closed: AtomicBool,
+    registrations: Registrations,
+}
+
+struct PacketHandler(Arc<Shared>);
+
+#[async_trait]
+impl EventHandler for PacketHandler {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        if self.0.closed.load(Ordering::Acquire) {
+            return Some(Event::Cancel);
+        }
+        if let EventContext::VoicePacket(data) = ctx {
+            if let Some(opus) = opus_payload(data) {
+                let mut sources = self.0.sources.lock().unwrap();
+                sources
+                    .entry(data.packet.ssrc)
+                    .or_insert_with(Source::new)
+                    .insert(
+                        data.packet.sequence.into(),
+                        data.packet.timestamp.into(),
+                        opus,
+                    );
+            }
+        }
+        None
+    }
+}
+
+/// Starts a jitter buffer on `driver` that plays every SSRC into `sink`.
+/// Packets are reordered by their sequence number, duplicates are dropped and lost packets
+/// are concealed by the Opus decoder. Each source starts playing once `depth` packets have
+/// been buffered. The packet handler is removed from the driver once `sink` stops the
+/// jitter buffer.
+pub fn start(driver: &PyDriver, depth: usize, mut sink: impl FrameSink) {
+    let shared = Arc::new(Shared {
+        sources: Mutex::new(HashMap::new()),
+        closed: AtomicBool::new(false),
+        registrations: Registrations::new(),
+    });
+
+    let handler = PacketHandler(shared.clone());
+    let registry = driver.registry();
+    let driver = driver.driver();
+    let registered = shared.clone();
+    let runtime = pyo3_asyncio::tokio::get_runtime();
+    runtime.spawn(async move {
+        let mut driver = driver.lock().await;
+        let event = Event::Core(CoreEvent::VoicePacket);
+        let id = registry.add(&mut driver, event, handler);
+        registered.registrations.push(&registry, id);
+    });
+
+    runtime.spawn(async move {
+        let mut clock = tokio::time::interval(FRAME_LENGTH);
+        loop {
+            clock.tick().await;
+
+            let frames: Vec<Frame> = {
+                let mut sources = shared.sources.lock().unwrap();
+                sources.retain(|_, source| source.last_packet.elapsed() < SOURCE_TIMEOUT);
+                sources
+                    .iter_mut()
+                    .filter_map(|(ssrc, source)| source.play(*ssrc, depth))
+                    .collect()
+            };
+
+            if !sink.frames(frames).await {
+                shared.closed.store(true, Ordering::Release);
+                shared.registrations.remove();
+                break;
+            }
+        }
+    });
+}
+
+/// 20 ms of audio from a user that went through a jitter buffer.
+#[pyclass(name = "AudioFrame")]
+pub struct PyAudioFrame {
+    #[pyo3(get)]
+    pub ssrc: u32,
+    /// The user id for `ssrc`, if it is known.
+    #[pyo3(get)]
+    pub user_id: Option<u64>,
+    /// The RTP timestamp of the first sample.
+    #[pyo3(get)]
+    pub timestamp: u32,
+    /// Whether the audio was made up to cover a lost packet.
+    #[pyo3(get)]
+    pub concealed: bool,
+    audio: Arc<Vec<i16>>,
+}
+
+impl PyAudioFrame {
+    pub fn from(frame: &Frame, ssrcs: &SsrcMap) -> Self {
+        Self {
+            ssrc: frame.ssrc,
+            user_id: ssrcs.user_id(frame.ssrc),
+            timestamp: frame.timestamp,
+            concealed: frame.concealed,
+            audio: frame.audio.clone(),
+        }
+    }
+}
+
+#[pymethods]
+impl PyAudioFrame {
+    /// 48kHz stereo audio as interleaved 16 bit samples. This is a numpy array of ``int16``
+    /// if numpy is installed, otherwise a ``memoryview``. The data is not copied.
+    #[getter]
+    fn audio(&self, py: Python) -> PyResult<PyObject> {
+        pcm_to_py(py, self.audio.clone())
+    }
+}
+
+/// Feeds jitter buffered frames into an ``EventStream``.
+pub struct StreamSink {
+    pub sender: StreamSender,
+    pub ssrcs: Arc<SsrcMap>,
+}
+
+#[async_trait]
+impl FrameSink for StreamSink {
+    async fn frames(&mut self, frames: Vec<Frame>) -> bool {
+        if self.sender.is_closed() {
+            return false;
+        }
+        for frame in frames {
+            let frame = PyAudioFrame::from(&frame, &self.ssrcs);
+            if !self.sender.send(Payload::AudioFrame(frame)).await {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use songbird::driver::opus::coder::Encoder;
+    use songbird::driver::opus::Application;
+
+    use super::*;
+    use crate::ogg::SILENT_FRAME;
+
+    /// 20 ms of a loud square wave, which stands out from the silent packets.
+    fn loud() -> Vec<u8> {
+        let encoder =
+            Encoder::new(SampleRate::Hz48000, Channels::Stereo, Application::Audio).unwrap();
+        let pcm: Vec<i16> = (0..STEREO_FRAME_SIZE)
+            .map(|i| if i / 40 % 2 == 0 { 10_000 } else { -10_000 })
+            .collect();
+        let mut out = vec![0; 4000];
+        let len = encoder.encode(&pcm, &mut out).unwrap();
+        out.truncate(len);
+        out
+    }
+
+    fn insert(source: &mut Source, sequences: &[u16]) {
+        for &sequence in sequences {
+            let timestamp = u32::from(sequence).wrapping_mul(960);
+            source.insert(sequence, timestamp, &SILENT_FRAME);
+        }
+    }
+
+    /// Plays frames until the source stops, returning whether each one was concealed.
+    fn play(source: &mut Source, depth: usize) -> Vec<bool> {
+        std::iter::from_fn(|| source.play(1, depth))
+            .map(|frame| {
+                assert_eq!(frame.audio.len(), STEREO_FRAME_SIZE);
+                frame.concealed
+            })
+            .collect()
+    }
+
+    fn energy(frame: &Frame) -> i64 {
+        frame.audio.iter().map(|&s| i64::from(s).abs()).sum()
+    }
+
+    #[test]
+    fn out_of_order() {
+        let mut source = Source::new();
+        insert(&mut source, &[3, 1]);
+        assert!(source.play(1, 3).is_none());
+        source.insert(2, 1920, &loud());
+
+        let frames: Vec<Frame> = std::iter::from_fn(|| source.play(1, 3)).collect();
+        assert_eq!(frames.len(), 3);
+        assert!(frames.iter().all(|frame| !frame.concealed));
+        assert_eq!(frames[0].timestamp, 960);
+        assert_eq!(frames[1].timestamp, 1920);
+        assert_eq!(frames[2].timestamp, 2880);
+        // The second packet is played second even though it arrived last.
+        assert!(energy(&frames[1]) > 10 * energy(&frames[0]));
+        assert!(energy(&frames[1]) > energy(&frames[2]));
+    }
+
+    #[test]
+    fn duplicates() {
+        let mut source = Source::new();
+        insert(&mut source, &[1, 1, 2, 2]);
+        assert_eq!(source.packets.len(), 2);
+        assert_eq!(play(&mut source, 2), [false, false]);
+    }
+
+    #[test]
+    fn missing_packets_are_concealed() {
+        let mut source = Source::new();
+        insert(&mut source, &[1, 3]);
+        assert_eq!(play(&mut source, 2), [false, true, false]);
+    }
+
+    #[test]
+    fn late_packets_are_dropped() {
+        let mut source = Source::new();
+        insert(&mut source, &[1, 2]);
+        assert!(!source.play(1, 2).unwrap().concealed);
+        insert(&mut source, &[1]);
+        assert_eq!(source.packets.keys().copied().collect::<Vec<_>>(), [2]);
+        assert_eq!(play(&mut source, 2), [false]);
+    }
+
+    #[test]
+    fn sequence_wraparound() {
+        let mut source = Source::new();
+        insert(&mut source, &[65534, 0, 65535, 1]);
+        let keys: Vec<i64> = source.packets.keys().copied().collect();
+        assert_eq!(keys, [65534, 65535, 65536, 65537]);
+        assert_eq!(play(&mut source, 4), [false; 4]);
+    }
+}
@@ -3,18 +3,41 @@ use pyo3_log::{Logger, Caching};
 
 mod exceptions;
 use exceptions::{
-    ConsumedSourceError, CouldNotConnectToRTPError, CouldNotOpenFileError, FfmpegError,
-    SongbirdError, TrackError, UseAsyncConstructorError, YtdlError,
+    AttemptDiscardedError, ConnectionCryptoError, ConnectionIoError, ConnectionJsonError,
+    ConnectionTimeoutError, ConsumedSourceError, CouldNotConnectToRTPError, CouldNotOpenFileError,
+    CryptoModeInvalidError, CryptoModeRejectedError, CryptoModeUnavailableError, EndpointUrlError,
+    FfmpegError, HandshakeError, InterconnectError, IpDiscoveryError, SongbirdError, TrackError,
+    UseAsyncConstructorError, WebsocketClosedError, WebsocketError, YtdlError,
 };
 
+mod buffer;
 mod config;
+mod connection;
+mod decoder;
 mod driver;
 mod event;
+mod flac;
+mod jitter;
+mod manager;
+mod mixdown;
+mod ogg;
+mod opus;
+mod pcm;
+mod queue;
+mod reader;
+mod recorder;
+mod registry;
+mod render;
+mod rtcp;
 mod source;
+mod ssrc;
+mod stats;
+mod stream;
 mod seekable;
 mod track;
 mod track_handle;
 mod utils;
+mod vad;
 
 /// The Songbird Python/Rust bindings
 /// This module is written in Rust 🚀
@@ -23,7 +46,16 @@ fn songbird(py: Python, m: &PyModule) -> PyResult<()> {
     let _ = Logger::new(py, Caching::LoggersAndLevels)?.install();
 
     m.add_class::<driver::PyDriver>()?;
+    m.add_class::<manager::PyVoiceManager>()?;
+    m.add_class::<connection::PyConnectionState>()?;
+    m.add_class::<render::PyDryRunDriver>()?;
+    m.add_class::<queue::PyTrackQueue>()?;
+    m.add_class::<queue::PyRepeatMode>()?;
+    m.add_class::<recorder::PyVoiceRecorder>()?;
+    m.add_class::<mixdown::PyMixedRecorder>()?;
     m.add_class::<source::PySource>()?;
+    m.add_class::<pcm::PySampleFormat>()?;
+    m.add_class::<pcm::PyPcmFormat>()?;
     m.add_class::<seekable::PyRestartableSource>()?;
     m.add_class::<seekable::PyCompressedSource>()?;
 
@@ -48,16 +80,34 @@ fn songbird(py: Python, m: &PyModule) -> PyResult<()> {
 
     // Events
     m.add_class::<event::PyEvent>()?;
+    m.add_class::<event::PyEventSubscription>()?;
+    m.add_class::<stream::PyEventStream>()?;
+    m.add_class::<stream::PyOverflowPolicy>()?;
     m.add_class::<event::PySpeakingState>()?;
     m.add_class::<event::PySpeaking>()?;
     m.add_class::<event::PySpeakingUpdateData>()?;
     m.add_class::<event::PyClientConnect>()?;
     m.add_class::<event::PyConnectData>()?;
+    m.add_class::<event::PyDisconnectData>()?;
     m.add_class::<event::PyDisconnectKind>()?;
     m.add_class::<event::PyDisconnectReason>()?;
     m.add_class::<event::PyVoiceData>()?;
+    m.add_class::<buffer::PySharedBuffer>()?;
+    m.add_class::<jitter::PyAudioFrame>()?;
+    m.add_class::<vad::PyVoiceActivityDetector>()?;
+    m.add_class::<vad::PyUtterance>()?;
+    m.add_class::<stats::PySpeakingStats>()?;
+    m.add_class::<stats::PySpeakerStats>()?;
+    m.add_class::<stats::PySpeakingInterval>()?;
     m.add_class::<event::PyRtp>()?;
     m.add_class::<event::PyRtpType>()?;
+    m.add_class::<rtcp::PyRtcpData>()?;
+    m.add_class::<rtcp::PySenderReport>()?;
+    m.add_class::<rtcp::PyReceiverReport>()?;
+    m.add_class::<rtcp::PyReportBlock>()?;
+    m.add_class::<rtcp::PySourceDescription>()?;
+    m.add_class::<rtcp::PySdesChunk>()?;
+    m.add_class::<rtcp::PyGoodbye>()?;
 
     m.add("ConsumedSourceError", py.get_type::<ConsumedSourceError>())?;
     m.add(
@@ -77,5 +127,21 @@ fn songbird(py: Python, m: &PyModule) -> PyResult<()> {
     )?;
     m.add("YtdlError", py.get_type::<YtdlError>())?;
 
+    // Connection errors
+    m.add("AttemptDiscardedError", py.get_type::<AttemptDiscardedError>())?;
+    m.add("ConnectionCryptoError", py.get_type::<ConnectionCryptoError>())?;
+    m.add("ConnectionIoError", py.get_type::<ConnectionIoError>())?;
+    m.add("ConnectionJsonError", py.get_type::<ConnectionJsonError>())?;
+    m.add("ConnectionTimeoutError", py.get_type::<ConnectionTimeoutError>())?;
+    m.add("CryptoModeInvalidError", py.get_type::<CryptoModeInvalidError>())?;
+    m.add("CryptoModeRejectedError", py.get_type::<CryptoModeRejectedError>())?;
+    m.add("CryptoModeUnavailableError", py.get_type::<CryptoModeUnavailableError>())?;
+    m.add("EndpointUrlError", py.get_type::<EndpointUrlError>())?;
+    m.add("HandshakeError", py.get_type::<HandshakeError>())?;
+    m.add("InterconnectError", py.get_type::<InterconnectError>())?;
+    m.add("IpDiscoveryError", py.get_type::<IpDiscoveryError>())?;
+    m.add("WebsocketClosedError", py.get_type::<WebsocketClosedError>())?;
+    m.add("WebsocketError", py.get_type::<WebsocketError>())?;
+
     Ok(())
 }
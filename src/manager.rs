@@ -0,0 +1,425 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use log::warn;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use songbird::driver::Driver;
+use songbird::id::{ChannelId, GuildId, UserId};
+use songbird::{Config, ConnectionInfo};
+use tokio::sync::Mutex;
+
+use crate::config::PyConfig;
+use crate::driver::PyDriver;
+use crate::utils::{get_snowflake, get_string};
+
+/// The connection details for a guild that have been collected from the gateway so far.
+/// Modelled after songbird's `ConnectionProgress`.
+#[derive(Default)]
+struct Partial {
+    channel_id: Option<u64>,
+    session_id: Option<String>,
+    token: Option<String>,
+    endpoint: Option<String>,
+    /// Whether the driver has been connected with `token` and `endpoint`.
+    token_used: bool,
+}
+
+impl Partial {
+    fn info(&self, guild_id: u64, user_id: u64) -> Option<ConnectionInfo> {
+        match (
+            &self.channel_id,
+            &self.session_id,
+            &self.token,
+            &self.endpoint,
+        ) {
+            (Some(channel_id), Some(session_id), Some(token), Some(endpoint)) => {
+                Some(ConnectionInfo {
+                    channel_id: Some(ChannelId::from(*channel_id)),
+                    endpoint: endpoint.replace("wss://", ""),
+                    guild_id: GuildId::from(guild_id),
+                    session_id: session_id.clone(),
+                    token: token.clone(),
+                    user_id: UserId::from(user_id),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the info to connect with once it is complete, which uses up the token.
+    fn take_info(&mut self, guild_id: u64, user_id: u64) -> Option<ConnectionInfo> {
+        let info = self.info(guild_id, user_id)?;
+        self.token_used = true;
+        Some(info)
+    }
+
+    /// Applies a ``VOICE_STATE_UPDATE`` for a channel and returns whether the driver has to
+    /// connect again.
+    fn apply_state_update(&mut self, channel_id: u64, session_id: String) -> bool {
+        let moved = self.channel_id != Some(channel_id);
+        let new_session = self.session_id.as_ref() != Some(&session_id);
+        // A token belongs to one session, so a new session waits for the next
+        // VOICE_SERVER_UPDATE. A token that has not been used yet arrived before this update
+        // and is for the new session.
+        if new_session && self.token_used {
+            self.token = None;
+            self.endpoint = None;
+            self.token_used = false;
+        }
+        self.channel_id = Some(channel_id);
+        self.session_id = Some(session_id);
+        moved || new_session
+    }
+
+    /// Applies a ``VOICE_SERVER_UPDATE``.
+    fn apply_server_update(&mut self, token: String, endpoint: Option<String>) {
+        self.token = Some(token);
+        self.endpoint = endpoint;
+        self.token_used = false;
+    }
+}
+
+/// A voice connection to a single guild.
+struct Call {
+    driver: PyDriver,
+    progress: Partial,
+}
+
+impl Call {
+    fn new(config: Config) -> Self {
+        Self {
+            driver: PyDriver::from(Driver::new(config)),
+            progress: Partial::default(),
+        }
+    }
+
+    /// Starts a connection if every piece of connection info has been received.
+    fn try_connect(&mut self, guild_id: u64, user_id: u64) {
+        let info = match self.progress.take_info(guild_id, user_id) {
+            Some(info) => info,
+            None => return,
+        };
+
+        let connect = self.driver.connect_with(info);
+
+        tokio::spawn(async move {
+            if let Err(err) = connect.await {
+                warn!("Failed to connect to voice in guild {}: {}", guild_id, err);
+            }
+        });
+    }
+}
+
+struct VoiceStateUpdate {
+    guild_id: u64,
+    user_id: u64,
+    channel_id: Option<u64>,
+    session_id: String,
+}
+
+impl VoiceStateUpdate {
+    fn extract(payload: &PyDict) -> PyResult<Option<Self>> {
+        let (guild_id, user_id, session_id) = match (
+            get_snowflake(payload, "guild_id")?,
+            get_snowflake(payload, "user_id")?,
+            get_string(payload, "session_id")?,
+        ) {
+            (Some(guild_id), Some(user_id), Some(session_id)) => (guild_id, user_id, session_id),
+            _ => return Ok(None),
+        };
+
+        Ok(Some(Self {
+            guild_id,
+            user_id,
+            channel_id: get_snowflake(payload, "channel_id")?,
+            session_id,
+        }))
+    }
+}
+
+struct VoiceServerUpdate {
+    guild_id: u64,
+    token: String,
+    endpoint: Option<String>,
+}
+
+impl VoiceServerUpdate {
+    fn extract(payload: &PyDict) -> PyResult<Option<Self>> {
+        let (guild_id, token) = match (
+            get_snowflake(payload, "guild_id")?,
+            get_string(payload, "token")?,
+        ) {
+            (Some(guild_id), Some(token)) => (guild_id, token),
+            _ => return Ok(None),
+        };
+
+        Ok(Some(Self {
+            guild_id,
+            token,
+            endpoint: get_string(payload, "endpoint")?,
+        }))
+    }
+}
+
+/// Keeps one ``Driver`` per guild and connects them using raw gateway payloads.
+/// Any gateway library can be used by forwarding the ``VOICE_STATE_UPDATE`` and
+/// ``VOICE_SERVER_UPDATE`` dispatch data to this object.
+///
+/// .. code-block:: python
+///
+///     manager = VoiceManager(user_id=bot_id)
+///
+///     # In your gateway event handlers.
+///     await manager.on_voice_state_update(payload)
+///     await manager.on_voice_server_update(payload)
+///
+///     # After you have sent a voice state update (opcode 4) to join a channel.
+///     driver = await manager.get(guild_id)
+///
+/// .. note::
+///
+///     The manager does not send opcode 4 to the gateway, that still has to be done by your
+///     gateway library.
+#[pyclass(name = "VoiceManager")]
+#[pyo3(text_signature = "(user_id: int, config: Optional[Config])")]
+pub struct PyVoiceManager {
+    user_id: u64,
+    config: Config,
+    calls: Arc<Mutex<HashMap<u64, Call>>>,
+}
+
+#[pymethods]
+impl PyVoiceManager {
+    #[new]
+    #[args(config = "None")]
+    fn new(user_id: u64, config: Option<&PyConfig>) -> Self {
+        Self {
+            user_id,
+            config: match config {
+                Some(py_config) => py_config.config.clone(),
+                None => Config::default(),
+            },
+            calls: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// The user id of the current user.
+    #[getter]
+    fn user_id(&self) -> u64 {
+        self.user_id
+    }
+
+    /// Handles the data of a ``VOICE_STATE_UPDATE`` dispatch.
+    /// Updates for other users are ignored.
+    ///
+    /// Joining a channel and being moved to another channel (re)connect the guild's driver.
+    /// A new session waits for the ``VOICE_SERVER_UPDATE`` with its token. Leaving the
+    /// channel disconnects the driver.
+    #[pyo3(text_signature = "($self, payload: dict)")]
+    fn on_voice_state_update<'p>(&self, py: Python<'p>, payload: &PyDict) -> PyResult<&'p PyAny> {
+        let update = VoiceStateUpdate::extract(payload)?;
+        let user_id = self.user_id;
+        let config = self.config.clone();
+        let calls = self.calls.clone();
+
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let update = match update {
+                Some(update) if update.user_id == user_id => update,
+                _ => return Ok(()),
+            };
+
+            let mut calls = calls.lock().await;
+
+            match update.channel_id {
+                None => {
+                    if let Some(call) = calls.get_mut(&update.guild_id) {
+                        call.driver.driver().lock().await.leave();
+                        call.progress = Partial::default();
+                    }
+                }
+                Some(channel_id) => {
+                    let call = calls
+                        .entry(update.guild_id)
+                        .or_insert_with(|| Call::new(config));
+
+                    if call
+                        .progress
+                        .apply_state_update(channel_id, update.session_id)
+                    {
+                        call.try_connect(update.guild_id, user_id);
+                    }
+                }
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Handles the data of a ``VOICE_SERVER_UPDATE`` dispatch.
+    ///
+    /// A payload without an endpoint means Discord is allocating a new voice server, the
+    /// driver is reconnected once an update with an endpoint arrives.
+    #[pyo3(text_signature = "($self, payload: dict)")]
+    fn on_voice_server_update<'p>(&self, py: Python<'p>, payload: &PyDict) -> PyResult<&'p PyAny> {
+        let update = VoiceServerUpdate::extract(payload)?;
+        let user_id = self.user_id;
+        let config = self.config.clone();
+        let calls = self.calls.clone();
+
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let update = match update {
+                Some(update) => update,
+                None => return Ok(()),
+            };
+
+            let mut calls = calls.lock().await;
+            let call = calls
+                .entry(update.guild_id)
+                .or_insert_with(|| Call::new(config));
+
+            call.progress
+                .apply_server_update(update.token, update.endpoint);
+            call.try_connect(update.guild_id, user_id);
+
+            Ok(())
+        })
+    }
+
+    /// Returns the ``Driver`` for a guild or :data:`None` if the manager has not seen
+    /// any events for it.
+    #[pyo3(text_signature = "($self, guild_id: int)")]
+    fn get<'p>(&self, py: Python<'p>, guild_id: u64) -> PyResult<&'p PyAny> {
+        let calls = self.calls.clone();
+
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            Ok(calls
+                .lock()
+                .await
+                .get(&guild_id)
+                .map(|call| call.driver.clone()))
+        })
+    }
+
+    /// Returns the ``Driver`` for a guild, creating one if it does not exist yet.
+    /// The driver can be used to queue audio before the connection is made.
+    #[pyo3(text_signature = "($self, guild_id: int)")]
+    fn get_or_create<'p>(&self, py: Python<'p>, guild_id: u64) -> PyResult<&'p PyAny> {
+        let config = self.config.clone();
+        let calls = self.calls.clone();
+
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            Ok(calls
+                .lock()
+                .await
+                .entry(guild_id)
+                .or_insert_with(|| Call::new(config))
+                .driver
+                .clone())
+        })
+    }
+
+    /// Disconnects the driver for a guild and forgets about it.
+    /// This does not update your voice state to remove you from the voice channel.
+    #[pyo3(text_signature = "($self, guild_id: int)")]
+    fn remove<'p>(&self, py: Python<'p>, guild_id: u64) -> PyResult<&'p PyAny> {
+        let calls = self.calls.clone();
+
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            if let Some(call) = calls.lock().await.remove(&guild_id) {
+                call.driver.driver().lock().await.leave();
+            }
+            Ok(())
+        })
+    }
+
+    /// Returns the ids of every guild the manager has a driver for.
+    #[pyo3(text_signature = "($self)")]
+    fn guild_ids<'p>(&self, py: Python<'p>) -> PyResult<&'p PyAny> {
+        let calls = self.calls.clone();
+
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            Ok(calls.lock().await.keys().copied().collect::<Vec<u64>>())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUILD: u64 = 1;
+    const USER: u64 = 2;
+
+    /// Connects like `Call::try_connect` and returns the session, token and endpoint used.
+    fn connected(partial: &mut Partial) -> Option<String> {
+        let info = partial.take_info(GUILD, USER)?;
+        Some(format!(
+            "{} {} {}",
+            info.session_id, info.token, info.endpoint
+        ))
+    }
+
+    #[test]
+    fn info_needs_everything() {
+        let mut partial = Partial::default();
+        assert!(partial.info(GUILD, USER).is_none());
+        partial.apply_state_update(3, "session".into());
+        assert!(partial.info(GUILD, USER).is_none());
+        partial.apply_server_update("token".into(), None);
+        assert!(partial.info(GUILD, USER).is_none());
+        partial.apply_server_update("token".into(), Some("wss://voice.discord.gg".into()));
+
+        let info = partial.info(GUILD, USER).unwrap();
+        assert_eq!(info.endpoint, "voice.discord.gg");
+        assert_eq!(info.channel_id, Some(ChannelId::from(3)));
+        assert_eq!(info.guild_id, GuildId::from(GUILD));
+        assert_eq!(info.user_id, UserId::from(USER));
+    }
+
+    #[test]
+    fn state_update_first() {
+        let mut partial = Partial::default();
+        assert!(partial.apply_state_update(3, "a".into()));
+        assert_eq!(connected(&mut partial), None);
+        partial.apply_server_update("t".into(), Some("e".into()));
+        assert_eq!(connected(&mut partial).unwrap(), "a t e");
+    }
+
+    #[test]
+    fn server_update_first() {
+        let mut partial = Partial::default();
+        partial.apply_server_update("t".into(), Some("e".into()));
+        assert!(partial.apply_state_update(3, "a".into()));
+        assert_eq!(connected(&mut partial).unwrap(), "a t e");
+    }
+
+    #[test]
+    fn new_session_waits_for_a_token() {
+        let mut partial = Partial::default();
+        partial.apply_state_update(3, "a".into());
+        partial.apply_server_update("t".into(), Some("e".into()));
+        connected(&mut partial).unwrap();
+
+        // The same session again does not reconnect.
+        assert!(!partial.apply_state_update(3, "a".into()));
+
+        assert!(partial.apply_state_update(3, "b".into()));
+        assert_eq!(connected(&mut partial), None);
+        partial.apply_server_update("u".into(), Some("f".into()));
+        assert_eq!(connected(&mut partial).unwrap(), "b u f");
+    }
+
+    #[test]
+    fn move_reconnects() {
+        let mut partial = Partial::default();
+        partial.apply_state_update(3, "a".into());
+        partial.apply_server_update("t".into(), Some("e".into()));
+        connected(&mut partial).unwrap();
+
+        assert!(partial.apply_state_update(4, "a".into()));
+        let info = partial.info(GUILD, USER).unwrap();
+        assert_eq!(info.channel_id, Some(ChannelId::from(4)));
+        assert_eq!(info.token, "t");
+    }
+}
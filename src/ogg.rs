@@ -0,0 +1,372 @@
+use std::io::{self, Read, Write};
+
+/// Ogg flags in the page header. The writer never splits packets across pages, so it does
+/// not set the continued packet flag.
+const CONTINUED_PACKET: u8 = 0x01;
+const FIRST_PAGE: u8 = 0x02;
+const LAST_PAGE: u8 = 0x04;
+
+/// An Opus packet of 20 ms of silence, used to fill gaps in a stream.
+pub const SILENT_FRAME: [u8; 3] = [0xf8, 0xff, 0xfe];
+
+/// Audio pages are written once they hold this many packets, which is one second of 20 ms
+/// frames.
+const PACKETS_PER_PAGE: usize = 50;
+
+/// CRC-32 used by Ogg. Unlike the usual CRC-32 it is not reflected.
+fn crc32(data: &[u8]) -> u32 {
+    const fn table() -> [u32; 256] {
+        let mut table = [0u32; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut crc = (i as u32) << 24;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = if crc & 0x8000_0000 != 0 {
+                    (crc << 1) ^ 0x04c1_1db7
+                } else {
+                    crc << 1
+                };
+                bit += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+        table
+    }
+    const TABLE: [u32; 256] = table();
+
+    data.iter().fold(0, |crc, &byte| {
+        (crc << 8) ^ TABLE[usize::from((crc >> 24) as u8 ^ byte)]
+    })
+}
+
+/// Writes Opus packets into an Ogg container, as described in RFC 7845.
+pub struct OggOpusWriter<W: Write> {
+    inner: W,
+    serial: u32,
+    sequence: u32,
+    granule: u64,
+    segments: Vec<u8>,
+    data: Vec<u8>,
+    packets: usize,
+}
+
+impl<W: Write> OggOpusWriter<W> {
+    /// Writes the Opus headers and returns a writer for the audio packets.
+    /// `comments` are added to the ``OpusTags`` header as ``KEY=value`` pairs.
+    pub fn new(inner: W, serial: u32, channels: u8, comments: &[String]) -> io::Result<Self> {
+        let mut writer = Self {
+            inner,
+            serial,
+            sequence: 0,
+            granule: 0,
+            segments: Vec::new(),
+            data: Vec::new(),
+            packets: 0,
+        };
+
+        let mut head = b"OpusHead".to_vec();
+        head.push(1); // version
+        head.push(channels);
+        head.extend_from_slice(&0u16.to_le_bytes()); // pre-skip
+        head.extend_from_slice(&48_000u32.to_le_bytes());
+        head.extend_from_slice(&0i16.to_le_bytes()); // output gain
+        head.push(0); // channel mapping family
+        writer.add(&head);
+        writer.flush_page(FIRST_PAGE)?;
+
+        let vendor = concat!("songbird-py ", env!("CARGO_PKG_VERSION"));
+        let mut tags = b"OpusTags".to_vec();
+        tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+        tags.extend_from_slice(vendor.as_bytes());
+        tags.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+        for comment in comments {
+            tags.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+            tags.extend_from_slice(comment.as_bytes());
+        }
+        writer.add(&tags);
+        writer.flush_page(0)?;
+
+        Ok(writer)
+    }
+
+    /// Adds a packet. `granule` is the number of 48 kHz samples in the stream once this
+    /// packet has been played.
+    pub fn write_packet(&mut self, packet: &[u8], granule: u64) -> io::Result<()> {
+        // A page can hold at most 255 lacing values.
+        if self.segments.len() + packet.len() / 255 + 1 > 255 {
+            self.flush_page(0)?;
+        }
+        self.add(packet);
+        self.granule = granule;
+
+        if self.packets >= PACKETS_PER_PAGE {
+            self.flush_page(0)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the last page and returns the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.flush_page(LAST_PAGE)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    fn add(&mut self, packet: &[u8]) {
+        let full = self.segments.len() + packet.len() / 255;
+        self.segments.resize(full, 255);
+        self.segments.push((packet.len() % 255) as u8);
+        self.data.extend_from_slice(packet);
+        self.packets += 1;
+    }
+
+    fn flush_page(&mut self, flags: u8) -> io::Result<()> {
+        let mut page = Vec::with_capacity(27 + self.segments.len() + self.data.len());
+        page.extend_from_slice(b"OggS");
+        page.push(0); // version
+        page.push(flags);
+        page.extend_from_slice(&self.granule.to_le_bytes());
+        page.extend_from_slice(&self.serial.to_le_bytes());
+        page.extend_from_slice(&self.sequence.to_le_bytes());
+        page.extend_from_slice(&[0; 4]); // checksum
+        page.push(self.segments.len() as u8);
+        page.extend_from_slice(&self.segments);
+        page.extend_from_slice(&self.data);
+
+        let crc = crc32(&page);
+        page[22..26].copy_from_slice(&crc.to_le_bytes());
+
+        self.inner.write_all(&page)?;
+        self.sequence += 1;
+        self.segments.clear();
+        self.data.clear();
+        self.packets = 0;
+        Ok(())
+    }
+}
+
+/// Reads the packets of an Ogg stream. Pages of other logical streams are skipped, chained
+/// streams are read one after the other.
+pub struct OggPacketReader<R: Read> {
+    inner: R,
+    serial: Option<u32>,
+    /// Lacing values of the current page that have not been read.
+    segments: Vec<u8>,
+    segment: usize,
+    data: Vec<u8>,
+    position: usize,
+    /// A packet that continues on the next page.
+    packet: Vec<u8>,
+}
+
+impl<R: Read> OggPacketReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            serial: None,
+            segments: Vec::new(),
+            segment: 0,
+            data: Vec::new(),
+            position: 0,
+            packet: Vec::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Returns the next packet, or `None` at the end of the stream.
+    pub fn next_packet(&mut self) -> io::Result<Option<Vec<u8>>> {
+        loop {
+            while self.segment < self.segments.len() {
+                let len = usize::from(self.segments[self.segment]);
+                self.segment += 1;
+                self.packet
+                    .extend_from_slice(&self.data[self.position..self.position + len]);
+                self.position += len;
+                if len < 255 {
+                    return Ok(Some(std::mem::take(&mut self.packet)));
+                }
+            }
+            if !self.read_page()? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Reads the next page of the stream. Returns `false` at the end of the file.
+    fn read_page(&mut self) -> io::Result<bool> {
+        loop {
+            let mut header = [0; 27];
+            match self.inner.read_exact(&mut header) {
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(false),
+                result => result?,
+            }
+            if &header[..4] != b"OggS" || header[4] != 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Not an Ogg page.",
+                ));
+            }
+
+            let mut segments = vec![0; usize::from(header[26])];
+            self.inner.read_exact(&mut segments)?;
+            let mut data = vec![0; segments.iter().map(|len| usize::from(*len)).sum()];
+            self.inner.read_exact(&mut data)?;
+
+            let checksum = u32::from_le_bytes(header[22..26].try_into().unwrap());
+            header[22..26].fill(0);
+            let mut page = header.to_vec();
+            page.extend_from_slice(&segments);
+            page.extend_from_slice(&data);
+            if crc32(&page) != checksum {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Ogg page checksum mismatch.",
+                ));
+            }
+
+            let flags = header[5];
+            let serial = u32::from_le_bytes(header[14..18].try_into().unwrap());
+            match self.serial {
+                // A chained stream starts after the last one ended.
+                None if flags & FIRST_PAGE != 0 => self.serial = Some(serial),
+                None => continue,
+                Some(current) if current != serial => continue,
+                _ => {}
+            }
+            if flags & LAST_PAGE != 0 {
+                self.serial = None;
+            }
+            if flags & CONTINUED_PACKET == 0 {
+                // The rest of the packet was lost.
+                self.packet.clear();
+            }
+
+            self.segments = segments;
+            self.segment = 0;
+            self.data = data;
+            self.position = 0;
+            return Ok(true);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use symphonia::core::formats::{FormatOptions, FormatReader};
+    use symphonia::core::io::MediaSourceStream;
+    use symphonia::default::formats::OggReader;
+
+    use super::*;
+
+    /// 20 ms CELT packets of different lengths, some of which need more than one lacing value.
+    fn packets() -> Vec<Vec<u8>> {
+        (0..120)
+            .map(|i| {
+                let mut packet = vec![0xf8];
+                packet.extend((0..(i * 7) % 600).map(|j| (i + j) as u8));
+                packet
+            })
+            .collect()
+    }
+
+    fn write(packets: &[Vec<u8>]) -> Vec<u8> {
+        let mut writer = OggOpusWriter::new(Vec::new(), 1234, 2, &["TITLE=test".into()]).unwrap();
+        for (i, packet) in packets.iter().enumerate() {
+            writer.write_packet(packet, 960 * (i as u64 + 1)).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    /// Returns the flags, granule position and sequence number of every page.
+    fn pages(mut data: &[u8]) -> Vec<(u8, u64, u32)> {
+        let mut pages = Vec::new();
+        while !data.is_empty() {
+            assert_eq!(&data[..4], b"OggS");
+            let segments = usize::from(data[26]);
+            let body: usize = data[27..27 + segments]
+                .iter()
+                .map(|&len| usize::from(len))
+                .sum();
+            pages.push((
+                data[5],
+                u64::from_le_bytes(data[6..14].try_into().unwrap()),
+                u32::from_le_bytes(data[18..22].try_into().unwrap()),
+            ));
+            data = &data[27 + segments + body..];
+        }
+        pages
+    }
+
+    #[test]
+    fn crc32_matches_ogg() {
+        // The checksum of a page is computed with its checksum field set to zero, so a
+        // valid page checks out to the value stored in it.
+        let data = write(&packets());
+        let len = 27
+            + usize::from(data[26])
+            + data[27..27 + usize::from(data[26])]
+                .iter()
+                .map(|&len| usize::from(len))
+                .sum::<usize>();
+        let mut page = data[..len].to_vec();
+        let checksum = u32::from_le_bytes(page[22..26].try_into().unwrap());
+        page[22..26].fill(0);
+        assert_eq!(crc32(&page), checksum);
+        assert_eq!(crc32(b"123456789"), 0x89a1_897f);
+    }
+
+    #[test]
+    fn granule_positions() {
+        let pages = pages(&write(&packets()));
+        let expected = [
+            (FIRST_PAGE, 0),
+            (0, 0),
+            (0, 960 * 50),
+            (0, 960 * 100),
+            (LAST_PAGE, 960 * 120),
+        ];
+        assert_eq!(pages.len(), expected.len());
+        for (i, ((flags, granule, sequence), (expected_flags, expected_granule))) in
+            pages.iter().zip(expected).enumerate()
+        {
+            assert_eq!(*flags, expected_flags, "flags of page {}", i);
+            assert_eq!(*granule, expected_granule, "granule of page {}", i);
+            assert_eq!(*sequence, i as u32);
+        }
+    }
+
+    #[test]
+    fn read_back() {
+        let packets = packets();
+        let data = write(&packets);
+
+        let mut reader = OggPacketReader::new(Cursor::new(data.clone()));
+        let head = reader.next_packet().unwrap().unwrap();
+        assert!(head.starts_with(b"OpusHead"));
+        let tags = reader.next_packet().unwrap().unwrap();
+        assert!(tags.starts_with(b"OpusTags"));
+        for packet in &packets {
+            assert_eq!(reader.next_packet().unwrap().as_ref(), Some(packet));
+        }
+        assert_eq!(reader.next_packet().unwrap(), None);
+
+        // Symphonia checks the page checksums and works out timestamps from the granule
+        // positions.
+        let stream = MediaSourceStream::new(Box::new(Cursor::new(data)), Default::default());
+        let mut demuxer = OggReader::try_new(stream, &FormatOptions::default()).unwrap();
+        for (i, packet) in packets.iter().enumerate() {
+            let read = demuxer.next_packet().unwrap();
+            assert_eq!(&*read.data, &packet[..]);
+            assert_eq!(read.ts(), 960 * i as u64);
+            assert_eq!(read.dur(), 960);
+        }
+        assert!(demuxer.next_packet().is_err());
+    }
+}
@@ -0,0 +1,296 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use songbird::input::codec::OpusDecoderState;
+use songbird::input::reader::MediaSource;
+use songbird::input::{Codec, Container, Input, Reader};
+
+use crate::ogg::OggPacketReader;
+
+/// 20 ms at 48kHz, the only frame length songbird can pass through to Discord.
+const PASSTHROUGH_SAMPLES: usize = 960;
+
+/// Returns the number of 48kHz samples in an Opus packet from its TOC byte, see RFC 6716.
+fn packet_samples(packet: &[u8]) -> Option<usize> {
+    let toc = *packet.first()?;
+    let config = usize::from(toc >> 3);
+    let frame = match config {
+        // SILK
+        0..=11 => [480, 960, 1920, 2880][config % 4],
+        // Hybrid
+        12..=15 => [480, 960][config % 2],
+        // CELT
+        _ => [120, 240, 480, 960][config % 4],
+    };
+    let frames = match toc & 0x03 {
+        0 => 1,
+        1 | 2 => 2,
+        _ => usize::from(*packet.get(1)? & 0x3f),
+    };
+    Some(frame * frames)
+}
+
+fn is_header(packet: &[u8]) -> bool {
+    packet.starts_with(b"OpusHead") || packet.starts_with(b"OpusTags")
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn opus_codec(allow_passthrough: bool) -> io::Result<Codec> {
+    let mut state =
+        OpusDecoderState::new().map_err(|err| io::Error::other(format!("{:?}", err)))?;
+    state.allow_passthrough = allow_passthrough;
+    Ok(Codec::Opus(state))
+}
+
+/// Turns the packets of an Ogg Opus stream into DCA frames, `{ len: i16, payload }`, which
+/// is the framing songbird reads Opus from.
+struct OggDcaReader<R: Read + Seek> {
+    packets: Option<OggPacketReader<R>>,
+    frame: Vec<u8>,
+    position: usize,
+}
+
+impl<R: Read + Seek> Read for OggDcaReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.frame.len() {
+            let packet = match self.packets.as_mut().unwrap().next_packet()? {
+                Some(packet) => packet,
+                None => return Ok(0),
+            };
+            // Chained streams start with their own headers.
+            if packet.is_empty() || is_header(&packet) {
+                continue;
+            }
+
+            // DCA frames store their length as an i16.
+            let len = i16::try_from(packet.len())
+                .map_err(|_| invalid("Opus packets can not be longer than 32767 bytes."))?;
+            self.frame.clear();
+            self.frame.extend_from_slice(&len.to_le_bytes());
+            self.frame.extend_from_slice(&packet);
+            self.position = 0;
+        }
+
+        let available = &self.frame[self.position..];
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+impl<R: Read + Seek> Seek for OggDcaReader<R> {
+    /// Only rewinding is supported, songbird seeks forward by reading frames.
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        if pos != SeekFrom::Start(0) {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Ogg Opus sources can only be rewound.",
+            ));
+        }
+        let mut inner = self.packets.take().unwrap().into_inner();
+        let rewound = inner.seek(SeekFrom::Start(0));
+        self.packets = Some(OggPacketReader::new(inner));
+        self.frame.clear();
+        self.position = 0;
+        rewound
+    }
+}
+
+impl<R: Read + Seek + Send + Sync> MediaSource for OggDcaReader<R> {
+    fn is_seekable(&self) -> bool {
+        true
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        None
+    }
+}
+
+/// Opens an Ogg Opus file. Its packets are passed through to Discord without decoding them
+/// if every packet is 20 ms long, which means reading through the file once.
+///
+/// The pre-skip and output gain from the OpusHead header are ignored, so the encoder delay
+/// is played at the start and the volume is not adjusted.
+pub fn ogg_opus(path: impl AsRef<Path>) -> io::Result<Input> {
+    let mut packets = OggPacketReader::new(BufReader::new(File::open(path)?));
+
+    let head = packets
+        .next_packet()?
+        .filter(|packet| packet.starts_with(b"OpusHead") && packet.len() >= 19)
+        .ok_or_else(|| invalid("Not an Ogg Opus file."))?;
+    // Channel mapping family 0 is mono or stereo, the others need a multistream decoder.
+    if head[18] != 0 {
+        return Err(invalid(
+            "Only mono and stereo Ogg Opus files are supported.",
+        ));
+    }
+
+    // Songbird passes packets through without looking at them, so a single packet of another
+    // length would be sent to Discord as if it were 20 ms.
+    let mut allow_passthrough = true;
+    while let Some(packet) = packets.next_packet()? {
+        if !packet.is_empty()
+            && !is_header(&packet)
+            && packet_samples(&packet) != Some(PASSTHROUGH_SAMPLES)
+        {
+            allow_passthrough = false;
+            break;
+        }
+    }
+
+    let mut inner = packets.into_inner();
+    inner.seek(SeekFrom::Start(0))?;
+    let reader = OggDcaReader {
+        packets: Some(OggPacketReader::new(inner)),
+        frame: Vec::new(),
+        position: 0,
+    };
+
+    // The decoder always produces stereo, even for mono files.
+    Ok(Input::new(
+        true,
+        Reader::Extension(Box::new(reader)),
+        opus_codec(allow_passthrough)?,
+        Container::Dca { first_frame: 0 },
+        None,
+    ))
+}
+
+/// Opens a DCA0 file, which is DCA frames without a header.
+pub fn dca0(path: impl AsRef<Path>) -> io::Result<Input> {
+    let file = File::open(path)?;
+    Ok(Input::new(
+        true,
+        Reader::from_file(file),
+        opus_codec(true)?,
+        Container::Dca { first_frame: 0 },
+        None,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use songbird::driver::opus::coder::Encoder;
+    use songbird::driver::opus::{Application, Channels, SampleRate};
+
+    use crate::ogg::OggOpusWriter;
+
+    use super::*;
+
+    fn write(packets: &[Vec<u8>]) -> Vec<u8> {
+        let mut writer = OggOpusWriter::new(Vec::new(), 1, 2, &[]).unwrap();
+        for (i, packet) in packets.iter().enumerate() {
+            writer.write_packet(packet, 960 * (i as u64 + 1)).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    /// Writes `packets` to a file and opens it.
+    fn open(name: &str, packets: &[Vec<u8>]) -> Input {
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, write(packets)).unwrap();
+        let input = ogg_opus(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        input
+    }
+
+    /// CELT packets with the given TOC bytes.
+    fn celt(tocs: &[u8]) -> Vec<Vec<u8>> {
+        tocs.iter().map(|toc| vec![*toc, 0, 0]).collect()
+    }
+
+    /// 20 ms packets of a 440 Hz sine.
+    fn sine(packets: usize) -> Vec<Vec<u8>> {
+        let encoder =
+            Encoder::new(SampleRate::Hz48000, Channels::Stereo, Application::Audio).unwrap();
+        (0..packets)
+            .map(|packet| {
+                let pcm: Vec<i16> = (0..PASSTHROUGH_SAMPLES * 2)
+                    .map(|i| {
+                        let t = (packet * PASSTHROUGH_SAMPLES + i / 2) as f64 / 48_000.0;
+                        (10_000.0 * (2.0 * std::f64::consts::PI * 440.0 * t).sin()) as i16
+                    })
+                    .collect();
+                let mut out = vec![0; 4000];
+                let len = encoder.encode(&pcm, &mut out).unwrap();
+                out.truncate(len);
+                out
+            })
+            .collect()
+    }
+
+    fn passthrough(input: &Input) -> bool {
+        match &input.kind {
+            Codec::Opus(state) => state.allow_passthrough,
+            _ => panic!("not an Opus input"),
+        }
+    }
+
+    #[test]
+    fn packet_lengths() {
+        assert_eq!(packet_samples(&[0xf8]), Some(960));
+        assert_eq!(packet_samples(&[0xf0]), Some(480));
+        assert_eq!(packet_samples(&[0xf1]), Some(960));
+        assert_eq!(packet_samples(&[0x18]), Some(2880));
+        assert_eq!(packet_samples(&[0xfb, 0x03]), Some(2880));
+        assert_eq!(packet_samples(&[0xfb]), None);
+    }
+
+    #[test]
+    fn passthrough_needs_every_packet_to_be_20ms() {
+        assert!(passthrough(&open(
+            "songbird-py-20ms.opus",
+            &celt(&[0xf8; 10])
+        )));
+
+        let mut tocs = [0xf8; 10];
+        tocs[7] = 0xf0;
+        assert!(!passthrough(&open("songbird-py-mixed.opus", &celt(&tocs))));
+    }
+
+    /// Decodes the rest of `input` a frame at a time, as songbird's mixer does.
+    fn decode(input: &mut Input) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut frame = [0; PASSTHROUGH_SAMPLES * 2 * 4];
+        // The frame length at the end of the file can not be read.
+        while let Ok(len @ 1..) = input.read(&mut frame) {
+            out.extend(&frame[..len]);
+        }
+        out
+    }
+
+    #[test]
+    fn decode_and_rewind() {
+        let mut input = open("songbird-py-rewind.opus", &sine(10));
+        let first = decode(&mut input);
+        // 200 ms of stereo float samples.
+        assert_eq!(first.len(), 10 * PASSTHROUGH_SAMPLES * 2 * 4);
+        assert!(first.iter().any(|&byte| byte != 0));
+
+        assert_eq!(input.seek(SeekFrom::Start(0)).unwrap(), 0);
+        assert_eq!(decode(&mut input).len(), first.len());
+    }
+
+    #[test]
+    fn long_packets_are_rejected() {
+        let data = write(&[vec![0xf8; 100], vec![0xf8; 40_000]]);
+        let mut reader = OggDcaReader {
+            packets: Some(OggPacketReader::new(Cursor::new(data))),
+            frame: Vec::new(),
+            position: 0,
+        };
+
+        let mut frame = [0; 102];
+        reader.read_exact(&mut frame).unwrap();
+        assert_eq!(frame[..2], 100i16.to_le_bytes());
+        let err = reader.read(&mut frame).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,769 @@
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::f64::consts::PI;
+use std::io::{self, Read, Seek, SeekFrom};
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use songbird::input::reader::MediaSource;
+use songbird::input::{Input, Reader};
+
+const OUTPUT_RATE: u64 = 48_000;
+/// Output frames produced per call to the inner reader at most.
+const BLOCK_SIZE: usize = 1024;
+/// How much is read from the inner reader at once.
+const READ_SIZE: usize = 16 * 1024;
+
+/// Zero crossings of the sinc on each side of the resampling filter.
+const ZERO_CROSSINGS: usize = 32;
+/// Filter table entries per zero crossing.
+const RESOLUTION: usize = 256;
+/// Passband as a fraction of the lower Nyquist frequency.
+const CUTOFF: f64 = 0.97;
+const KAISER_BETA: f64 = 9.0;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SampleFormat {
+    S16Le,
+    S16Be,
+    S32Le,
+    S32Be,
+    F32Le,
+    F32Be,
+}
+
+impl SampleFormat {
+    fn size(self) -> usize {
+        match self {
+            SampleFormat::S16Le | SampleFormat::S16Be => 2,
+            _ => 4,
+        }
+    }
+
+    /// Converts one sample to a float in `-1.0..1.0`.
+    fn decode(self, bytes: &[u8]) -> f32 {
+        match self {
+            SampleFormat::S16Le => {
+                f32::from(i16::from_le_bytes(bytes.try_into().unwrap())) / 32768.0
+            }
+            SampleFormat::S16Be => {
+                f32::from(i16::from_be_bytes(bytes.try_into().unwrap())) / 32768.0
+            }
+            SampleFormat::S32Le => {
+                i32::from_le_bytes(bytes.try_into().unwrap()) as f32 / 2_147_483_648.0
+            }
+            SampleFormat::S32Be => {
+                i32::from_be_bytes(bytes.try_into().unwrap()) as f32 / 2_147_483_648.0
+            }
+            SampleFormat::F32Le => f32::from_le_bytes(bytes.try_into().unwrap()),
+            SampleFormat::F32Be => f32::from_be_bytes(bytes.try_into().unwrap()),
+        }
+    }
+}
+
+/// How the samples of raw PCM audio are stored.
+#[pyclass(name = "SampleFormat")]
+#[derive(Clone)]
+pub struct PySampleFormat {
+    format: SampleFormat,
+}
+
+impl PySampleFormat {
+    fn from(format: SampleFormat) -> Self {
+        Self { format }
+    }
+}
+
+#[allow(non_snake_case)]
+#[pymethods]
+impl PySampleFormat {
+    /// Signed 16 bit integers, little-endian.
+    #[classattr]
+    fn S16LE() -> Self {
+        Self::from(SampleFormat::S16Le)
+    }
+    /// Signed 16 bit integers, big-endian.
+    #[classattr]
+    fn S16BE() -> Self {
+        Self::from(SampleFormat::S16Be)
+    }
+    /// Signed 32 bit integers, little-endian.
+    #[classattr]
+    fn S32LE() -> Self {
+        Self::from(SampleFormat::S32Le)
+    }
+    /// Signed 32 bit integers, big-endian.
+    #[classattr]
+    fn S32BE() -> Self {
+        Self::from(SampleFormat::S32Be)
+    }
+    /// 32 bit floats, little-endian.
+    #[classattr]
+    fn F32LE() -> Self {
+        Self::from(SampleFormat::F32Le)
+    }
+    /// 32 bit floats, big-endian.
+    #[classattr]
+    fn F32BE() -> Self {
+        Self::from(SampleFormat::F32Be)
+    }
+
+    fn __str__(&self) -> &str {
+        match self.format {
+            SampleFormat::S16Le => "<SampleFormat.S16LE>",
+            SampleFormat::S16Be => "<SampleFormat.S16BE>",
+            SampleFormat::S32Le => "<SampleFormat.S32LE>",
+            SampleFormat::S32Be => "<SampleFormat.S32BE>",
+            SampleFormat::F32Le => "<SampleFormat.F32LE>",
+            SampleFormat::F32Be => "<SampleFormat.F32BE>",
+        }
+    }
+}
+
+/// The layout of raw PCM audio: how samples are stored, the sample rate and the number of
+/// interleaved channels.
+///
+/// Audio that is not 48kHz is resampled, and audio with more than two channels is mixed down
+/// to stereo. Channels are assumed to be in the usual WAV order, for example front left,
+/// front right, center, LFE, back left, back right for 5.1.
+///
+/// .. code-block:: python
+///
+///     # 24kHz mono audio from a TTS engine
+///     source = Source.pcm(audio, PcmFormat(SampleFormat.S16LE, 24000, 1))
+#[pyclass(name = "PcmFormat")]
+#[pyo3(text_signature = "(sample_format: SampleFormat, sample_rate: int, channels: int)")]
+#[derive(Clone)]
+pub struct PyPcmFormat {
+    format: SampleFormat,
+    /// Frames per second.
+    #[pyo3(get)]
+    sample_rate: u32,
+    #[pyo3(get)]
+    channels: usize,
+}
+
+#[pymethods]
+impl PyPcmFormat {
+    #[new]
+    #[args(sample_rate = "48000", channels = "2")]
+    fn new(sample_format: &PySampleFormat, sample_rate: u32, channels: usize) -> PyResult<Self> {
+        if sample_rate == 0 {
+            return Err(PyValueError::new_err("The sample rate must be above 0."));
+        }
+        if channels == 0 {
+            return Err(PyValueError::new_err("There must be at least one channel."));
+        }
+        Ok(Self {
+            format: sample_format.format,
+            sample_rate,
+            channels,
+        })
+    }
+
+    #[getter]
+    fn sample_format(&self) -> PySampleFormat {
+        PySampleFormat::from(self.format)
+    }
+}
+
+impl PyPcmFormat {
+    /// Little-endian float samples, which is what decoders produce.
+    pub fn float(sample_rate: u32, channels: usize) -> Self {
+        Self {
+            format: SampleFormat::F32Le,
+            sample_rate,
+            channels,
+        }
+    }
+
+    /// Whether the audio is played as stereo, which it is unless it is mono.
+    fn stereo(&self) -> bool {
+        self.channels > 1
+    }
+
+    /// Creates an input that plays the audio from `reader` in this format.
+    pub fn input(&self, reader: Box<dyn MediaSource + Send>) -> Input {
+        if self.format == SampleFormat::F32Le
+            && u64::from(self.sample_rate) == OUTPUT_RATE
+            && self.channels <= 2
+        {
+            // This is already what songbird plays.
+            return Input::float_pcm(self.stereo(), Reader::Extension(reader));
+        }
+        Input::float_pcm(
+            self.stereo(),
+            Reader::Extension(Box::new(PcmReader::new(reader, self))),
+        )
+    }
+}
+
+/// The left and right gain of each input channel when mixing down to stereo.
+fn downmix_matrix(channels: usize) -> Vec<[f32; 2]> {
+    const SIDE: f32 = std::f32::consts::FRAC_1_SQRT_2;
+    let center = [SIDE, SIDE];
+    let lfe = [0.0, 0.0];
+    let mut matrix = match channels {
+        3 => vec![[1.0, 0.0], [0.0, 1.0], center],
+        4 => vec![[1.0, 0.0], [0.0, 1.0], [SIDE, 0.0], [0.0, SIDE]],
+        5 => vec![[1.0, 0.0], [0.0, 1.0], center, [SIDE, 0.0], [0.0, SIDE]],
+        6 => vec![
+            [1.0, 0.0],
+            [0.0, 1.0],
+            center,
+            lfe,
+            [SIDE, 0.0],
+            [0.0, SIDE],
+        ],
+        8 => vec![
+            [1.0, 0.0],
+            [0.0, 1.0],
+            center,
+            lfe,
+            [SIDE, 0.0],
+            [0.0, SIDE],
+            [SIDE, 0.0],
+            [0.0, SIDE],
+        ],
+        _ => (0..channels)
+            .map(|channel| {
+                if channel % 2 == 0 {
+                    [1.0, 0.0]
+                } else {
+                    [0.0, 1.0]
+                }
+            })
+            .collect(),
+    };
+
+    // Every output channel is scaled so that the mix can not clip.
+    for side in 0..2 {
+        let total: f32 = matrix.iter().map(|gains| gains[side]).sum();
+        for gains in &mut matrix {
+            gains[side] /= total;
+        }
+    }
+    matrix
+}
+
+/// The modified Bessel function of the first kind of order zero.
+fn bessel_i0(x: f64) -> f64 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    for k in 1..100 {
+        term *= (x / 2.0 / k as f64).powi(2);
+        sum += term;
+        if term < sum * 1e-12 {
+            break;
+        }
+    }
+    sum
+}
+
+/// A Kaiser windowed sinc low-pass filter for resampling.
+struct Filter {
+    /// The filter at `1 / RESOLUTION` steps from 0 to `ZERO_CROSSINGS`.
+    table: Vec<f32>,
+    /// The cutoff relative to the input Nyquist frequency.
+    scale: f64,
+    /// Input frames on each side of the output frame that are used.
+    width: u64,
+}
+
+impl Filter {
+    fn new(input_rate: u64) -> Self {
+        let scale = CUTOFF * (OUTPUT_RATE as f64 / input_rate as f64).min(1.0);
+        let norm = bessel_i0(KAISER_BETA);
+        let table = (0..=ZERO_CROSSINGS * RESOLUTION + 1)
+            .map(|i| {
+                let x = i as f64 / RESOLUTION as f64;
+                let ratio = x / ZERO_CROSSINGS as f64;
+                if ratio >= 1.0 {
+                    return 0.0;
+                }
+                let sinc = if x == 0.0 {
+                    1.0
+                } else {
+                    (PI * x).sin() / (PI * x)
+                };
+                let window = bessel_i0(KAISER_BETA * (1.0 - ratio * ratio).sqrt()) / norm;
+                (sinc * window) as f32
+            })
+            .collect();
+
+        Self {
+            table,
+            scale,
+            width: (ZERO_CROSSINGS as f64 / scale).ceil() as u64,
+        }
+    }
+
+    /// The weight of an input frame `distance` input frames away from the output frame.
+    fn weight(&self, distance: f64) -> f32 {
+        let position = distance.abs() * self.scale * RESOLUTION as f64;
+        let index = position as usize;
+        if index + 1 >= self.table.len() {
+            return 0.0;
+        }
+        let fraction = (position - index as f64) as f32;
+        let value = self.table[index] * (1.0 - fraction) + self.table[index + 1] * fraction;
+        value * self.scale as f32
+    }
+}
+
+/// Converts raw PCM in any format to the 48kHz float PCM songbird plays.
+struct PcmReader {
+    inner: Box<dyn MediaSource + Send>,
+    format: SampleFormat,
+    input_rate: u64,
+    input_channels: usize,
+    output_channels: usize,
+    /// `None` if the input is 48kHz already.
+    filter: Option<Filter>,
+    downmix: Vec<[f32; 2]>,
+    /// Bytes from the inner reader that are not a whole frame yet.
+    partial: Vec<u8>,
+    /// Input frames with `output_channels` interleaved channels, starting at frame `base`.
+    input: VecDeque<f32>,
+    base: u64,
+    eof: bool,
+    /// The next output frame.
+    frame: u64,
+    /// Converted bytes that have not been read yet.
+    output: Vec<u8>,
+    output_read: usize,
+}
+
+impl PcmReader {
+    fn new(inner: Box<dyn MediaSource + Send>, format: &PyPcmFormat) -> Self {
+        let input_rate = u64::from(format.sample_rate);
+        Self {
+            inner,
+            format: format.format,
+            input_rate,
+            input_channels: format.channels,
+            output_channels: format.channels.min(2),
+            filter: (input_rate != OUTPUT_RATE).then(|| Filter::new(input_rate)),
+            downmix: if format.channels > 2 {
+                downmix_matrix(format.channels)
+            } else {
+                Vec::new()
+            },
+            partial: Vec::new(),
+            input: VecDeque::new(),
+            base: 0,
+            eof: false,
+            frame: 0,
+            output: Vec::new(),
+            output_read: 0,
+        }
+    }
+
+    fn input_frame_size(&self) -> usize {
+        self.format.size() * self.input_channels
+    }
+
+    fn output_frame_size(&self) -> usize {
+        std::mem::size_of::<f32>() * self.output_channels
+    }
+
+    /// Input frames that have been read and not dropped yet.
+    fn buffered(&self) -> u64 {
+        (self.input.len() / self.output_channels) as u64
+    }
+
+    /// Reads more input frames, or sets `eof`.
+    fn fill(&mut self) -> io::Result<()> {
+        let start = self.partial.len();
+        self.partial.resize(start + READ_SIZE, 0);
+        let read = loop {
+            match self.inner.read(&mut self.partial[start..]) {
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                result => break result,
+            }
+        };
+        let read = match read {
+            Ok(read) => read,
+            Err(err) => {
+                self.partial.truncate(start);
+                return Err(err);
+            }
+        };
+        self.partial.truncate(start + read);
+        if read == 0 {
+            self.eof = true;
+            return Ok(());
+        }
+
+        let frame_size = self.input_frame_size();
+        let sample_size = self.format.size();
+        let whole = self.partial.len() / frame_size * frame_size;
+        for frame in self.partial[..whole].chunks_exact(frame_size) {
+            let samples = frame
+                .chunks_exact(sample_size)
+                .map(|sample| self.format.decode(sample));
+            match self.input_channels {
+                1 | 2 => self.input.extend(samples),
+                _ => {
+                    let mut mixed = [0.0; 2];
+                    for (sample, gains) in samples.zip(&self.downmix) {
+                        mixed[0] += sample * gains[0];
+                        mixed[1] += sample * gains[1];
+                    }
+                    self.input.extend(mixed);
+                }
+            }
+        }
+        self.partial.drain(..whole);
+        Ok(())
+    }
+
+    /// Converts up to `BLOCK_SIZE` frames into `output`. Produces nothing at the end.
+    fn produce(&mut self) -> io::Result<()> {
+        self.output.clear();
+        self.output_read = 0;
+
+        let filter = match self.filter.take() {
+            Some(filter) => filter,
+            None => {
+                while self.input.is_empty() && !self.eof {
+                    self.fill()?;
+                }
+                let frames = (self.buffered() as usize).min(BLOCK_SIZE);
+                for sample in self.input.drain(..frames * self.output_channels) {
+                    self.output.extend_from_slice(&sample.to_le_bytes());
+                }
+                self.base += frames as u64;
+                self.frame += frames as u64;
+                return Ok(());
+            }
+        };
+
+        let result = self.resample(&filter);
+        self.filter = Some(filter);
+        result
+    }
+
+    fn resample(&mut self, filter: &Filter) -> io::Result<()> {
+        let channels = self.output_channels;
+        let mut mixed = [0.0f32; 2];
+        for _ in 0..BLOCK_SIZE {
+            // The position of the output frame in input frames, kept exact so it does not drift.
+            let position = self.frame * self.input_rate;
+            let index = position / OUTPUT_RATE;
+            let fraction = (position % OUTPUT_RATE) as f64 / OUTPUT_RATE as f64;
+
+            while !self.eof && self.base + self.buffered() <= index + filter.width {
+                self.fill()?;
+            }
+            if self.eof && index >= self.base + self.buffered() {
+                break;
+            }
+
+            mixed[..channels].fill(0.0);
+            let first = index.saturating_sub(filter.width - 1).max(self.base);
+            let last = (index + filter.width).min(self.base + self.buffered().max(1) - 1);
+            for input in first..=last {
+                let weight = filter.weight(input as f64 - index as f64 - fraction);
+                let offset = (input - self.base) as usize * channels;
+                for (channel, sample) in mixed[..channels].iter_mut().enumerate() {
+                    *sample += weight * self.input.get(offset + channel).copied().unwrap_or(0.0);
+                }
+            }
+            for sample in &mixed[..channels] {
+                self.output.extend_from_slice(&sample.to_le_bytes());
+            }
+            self.frame += 1;
+
+            // Frames before the filter of the next output frame are not needed anymore.
+            let keep = (index + 1).saturating_sub(filter.width);
+            if keep > self.base {
+                let drop = ((keep - self.base).min(self.buffered())) as usize;
+                self.input.drain(..drop * channels);
+                self.base += drop as u64;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Read for PcmReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.output_read == self.output.len() {
+            self.produce()?;
+        }
+        let available = &self.output[self.output_read..];
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.output_read += len;
+        Ok(len)
+    }
+}
+
+impl Seek for PcmReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let frame_size = self.output_frame_size() as u64;
+        let target = match pos {
+            SeekFrom::Start(target) => target,
+            SeekFrom::Current(offset) => {
+                let unread = (self.output.len() - self.output_read) as u64;
+                (self.frame * frame_size - unread).saturating_add_signed(offset)
+            }
+            SeekFrom::End(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "Raw PCM can not be seeked from the end.",
+                ))
+            }
+        };
+
+        let frame = target / frame_size;
+        let index = frame * self.input_rate / OUTPUT_RATE;
+        // The filter needs the frames before the new position as well.
+        let width = self.filter.as_ref().map_or(0, |filter| filter.width);
+        let start = index.saturating_sub(width);
+        self.inner
+            .seek(SeekFrom::Start(start * self.input_frame_size() as u64))?;
+
+        self.partial.clear();
+        self.input.clear();
+        self.base = start;
+        self.eof = false;
+        self.frame = frame;
+        self.output.clear();
+        self.output_read = 0;
+        Ok(frame * frame_size)
+    }
+}
+
+impl MediaSource for PcmReader {
+    fn is_seekable(&self) -> bool {
+        self.inner.is_seekable()
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    const SIDE: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+    fn reader(data: Vec<u8>, format: SampleFormat, rate: u32, channels: usize) -> PcmReader {
+        let format = PyPcmFormat {
+            format,
+            sample_rate: rate,
+            channels,
+        };
+        PcmReader::new(Box::new(Cursor::new(data)), &format)
+    }
+
+    /// Reads the rest of the output `size` bytes at a time.
+    fn read(reader: &mut PcmReader, size: usize) -> Vec<f32> {
+        let mut bytes = Vec::new();
+        let mut buf = vec![0; size];
+        loop {
+            match reader.read(&mut buf).unwrap() {
+                0 => break,
+                len => bytes.extend(&buf[..len]),
+            }
+        }
+        bytes
+            .chunks_exact(4)
+            .map(|sample| f32::from_le_bytes(sample.try_into().unwrap()))
+            .collect()
+    }
+
+    fn f32_le(samples: impl IntoIterator<Item = f32>) -> Vec<u8> {
+        samples.into_iter().flat_map(f32::to_le_bytes).collect()
+    }
+
+    /// 16 bit samples counting up from 0, `channels` to a frame.
+    fn ramp(frames: usize, channels: usize) -> Vec<u8> {
+        (0..frames * channels)
+            .flat_map(|i| ((i / channels) as i16).to_le_bytes())
+            .collect()
+    }
+
+    fn sine(frequency: f64, rate: u32, seconds: f64) -> Vec<u8> {
+        let frames = (f64::from(rate) * seconds) as usize;
+        f32_le((0..frames).map(|i| {
+            let t = i as f64 / f64::from(rate);
+            (0.5 * (2.0 * PI * frequency * t).sin()) as f32
+        }))
+    }
+
+    #[test]
+    fn sample_formats() {
+        // 0.5 and -0.25 in every format.
+        let formats = [
+            (SampleFormat::S16Le, vec![0x00, 0x40, 0x00, 0xe0]),
+            (SampleFormat::S16Be, vec![0x40, 0x00, 0xe0, 0x00]),
+            (SampleFormat::S32Le, vec![0, 0, 0, 0x40, 0, 0, 0, 0xe0]),
+            (SampleFormat::S32Be, vec![0x40, 0, 0, 0, 0xe0, 0, 0, 0]),
+            (SampleFormat::F32Le, vec![0, 0, 0, 0x3f, 0, 0, 0x80, 0xbe]),
+            (SampleFormat::F32Be, vec![0x3f, 0, 0, 0, 0xbe, 0x80, 0, 0]),
+        ];
+        for (format, data) in formats {
+            let mut reader = reader(data, format, 48_000, 2);
+            let name = PySampleFormat::from(format).__str__().to_owned();
+            assert_eq!(read(&mut reader, 4096), [0.5, -0.25], "{}", name);
+        }
+    }
+
+    #[test]
+    fn resampled_length() {
+        for rate in [22_050, 24_000, 96_000] {
+            // One second of audio is one second at 48kHz.
+            let data = ramp(rate as usize, 1);
+            let mut reader = reader(data, SampleFormat::S16Le, rate, 1);
+            assert_eq!(read(&mut reader, 4096).len(), 48_000, "{} Hz", rate);
+        }
+    }
+
+    #[test]
+    fn resampled_sine() {
+        let mut reader = reader(sine(1000.0, 24_000, 1.0), SampleFormat::F32Le, 24_000, 1);
+        let output = read(&mut reader, 4096);
+        assert_eq!(output.len(), 48_000);
+
+        // The edges are left out, the filter has no input on one side there.
+        let middle = &output[4800..43_200];
+        let crossings = middle
+            .windows(2)
+            .filter(|pair| (pair[0] < 0.0) != (pair[1] < 0.0))
+            .count();
+        // 0.8 seconds of 1 kHz crosses zero 1600 times.
+        assert!(
+            (1599..=1601).contains(&crossings),
+            "{} crossings",
+            crossings
+        );
+        let peak = middle.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        assert!((peak - 0.5).abs() < 0.005, "peak of {}", peak);
+    }
+
+    /// Plays a frame where only `channel` is 1.0 and returns the stereo output.
+    fn downmix(channels: usize, channel: usize) -> Vec<f32> {
+        let frame = (0..channels).map(|i| if i == channel { 1.0 } else { 0.0 });
+        let mut reader = reader(f32_le(frame), SampleFormat::F32Le, 48_000, channels);
+        read(&mut reader, 4096)
+    }
+
+    fn assert_close(actual: Vec<f32>, expected: [f32; 2]) {
+        assert!(
+            actual
+                .iter()
+                .zip(expected)
+                .all(|(a, b)| (a - b).abs() < 1e-6),
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn downmix_5_1() {
+        // Front, center and back add up to 1 + 2 * SIDE on each side.
+        let total = 1.0 + 2.0 * SIDE;
+        let expected = [
+            [1.0 / total, 0.0],
+            [0.0, 1.0 / total],
+            [SIDE / total, SIDE / total],
+            [0.0, 0.0],
+            [SIDE / total, 0.0],
+            [0.0, SIDE / total],
+        ];
+        for (channel, expected) in expected.into_iter().enumerate() {
+            assert_close(downmix(6, channel), expected);
+        }
+    }
+
+    #[test]
+    fn downmix_7_1() {
+        let total = 1.0 + 3.0 * SIDE;
+        let expected = [
+            [1.0 / total, 0.0],
+            [0.0, 1.0 / total],
+            [SIDE / total, SIDE / total],
+            [0.0, 0.0],
+            [SIDE / total, 0.0],
+            [0.0, SIDE / total],
+            [SIDE / total, 0.0],
+            [0.0, SIDE / total],
+        ];
+        for (channel, expected) in expected.into_iter().enumerate() {
+            assert_close(downmix(8, channel), expected);
+        }
+    }
+
+    #[test]
+    fn seek() {
+        let mut reader = reader(ramp(10_000, 2), SampleFormat::S16Le, 48_000, 2);
+        let frame_size = 8;
+        let sample = |frame: u64| frame as f32 / 32768.0;
+
+        assert_eq!(
+            reader.seek(SeekFrom::Start(100 * frame_size)).unwrap(),
+            100 * frame_size
+        );
+        let mut buf = [0; 24];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(
+            f32::from_le_bytes(buf[..4].try_into().unwrap()),
+            sample(100)
+        );
+
+        // Three frames have been read, some of the block is still unread.
+        let position = reader
+            .seek(SeekFrom::Current(10 * frame_size as i64))
+            .unwrap();
+        assert_eq!(position, 113 * frame_size);
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(
+            f32::from_le_bytes(buf[..4].try_into().unwrap()),
+            sample(113)
+        );
+
+        let position = reader
+            .seek(SeekFrom::Current(-(frame_size as i64)))
+            .unwrap();
+        assert_eq!(position, 115 * frame_size);
+        assert!(reader.seek(SeekFrom::End(0)).is_err());
+    }
+
+    #[test]
+    fn seek_resampled() {
+        let data = sine(440.0, 22_050, 0.5);
+        let full = read(
+            &mut reader(data.clone(), SampleFormat::F32Le, 22_050, 1),
+            4096,
+        );
+
+        // The filter is filled with the frames before the new position too.
+        let mut reader = reader(data, SampleFormat::F32Le, 22_050, 1);
+        assert_eq!(reader.seek(SeekFrom::Start(5000 * 4)).unwrap(), 5000 * 4);
+        let seeked = read(&mut reader, 4096);
+        assert_eq!(seeked.len(), full.len() - 5000);
+        for (a, b) in seeked.iter().zip(&full[5000..]) {
+            assert!((a - b).abs() < 1e-6, "{} != {}", a, b);
+        }
+    }
+
+    #[test]
+    fn partial_frame_reads() {
+        let data = sine(1000.0, 24_000, 0.1);
+        let whole = read(
+            &mut reader(data.clone(), SampleFormat::F32Le, 24_000, 1),
+            4096,
+        );
+        // A buffer that does not hold a whole number of frames still gets every byte.
+        let odd = read(&mut reader(data, SampleFormat::F32Le, 24_000, 1), 7);
+        assert_eq!(odd, whole);
+    }
+}
@@ -0,0 +1,854 @@
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use pyo3::basic::CompareOp;
+use pyo3::exceptions::{PyIndexError, PyTypeError};
+use pyo3::prelude::*;
+use rand::seq::SliceRandom;
+use songbird::driver::Driver;
+use songbird::events::EventData;
+use songbird::tracks::{create_player, Queued, Track, TrackHandle, TrackQueue};
+use songbird::{Event, EventContext, EventHandler, TrackEvent};
+use tokio::sync::Mutex as AsyncMutex;
+
+use crate::driver::PyDriver;
+use crate::exceptions::TrackError;
+use crate::seekable::Replay;
+use crate::source::PySource;
+use crate::track::PyTrack;
+use crate::track_handle::{handle_track_result, PyTrackHandle};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Repeat {
+    Off,
+    One,
+    All,
+}
+
+/// What a TrackQueue does when the current track finishes.
+#[pyclass(name = "RepeatMode")]
+#[derive(Clone)]
+pub struct PyRepeatMode {
+    repeat: Repeat,
+}
+
+impl PyRepeatMode {
+    fn from(repeat: Repeat) -> Self {
+        Self { repeat }
+    }
+}
+
+#[allow(non_snake_case)]
+#[pymethods]
+impl PyRepeatMode {
+    #[classattr]
+    /// Play the next track. The finished track is moved to the history.
+    fn Off() -> Self {
+        Self::from(Repeat::Off)
+    }
+    #[classattr]
+    /// Play the current track again.
+    fn One() -> Self {
+        Self::from(Repeat::One)
+    }
+    #[classattr]
+    /// Play the next track and move the finished track to the end of the queue.
+    fn All() -> Self {
+        Self::from(Repeat::All)
+    }
+
+    fn __str__(&self) -> &str {
+        match self.repeat {
+            Repeat::Off => "<RepeatMode.Off>",
+            Repeat::One => "<RepeatMode.One>",
+            Repeat::All => "<RepeatMode.All>",
+        }
+    }
+
+    fn __richcmp__(&self, other: Self, op: CompareOp) -> PyResult<PyObject> {
+        Python::with_gil(|py| match op {
+            CompareOp::Eq => PyResult::Ok((self.repeat == other.repeat).into_py(py)),
+            _ => PyResult::Err(PyTypeError::new_err(
+                "Only __eq__ is implemented for this type",
+            )),
+        })
+    }
+}
+
+/// A track that has been played.
+#[derive(Clone)]
+struct Played {
+    handle: TrackHandle,
+    replay: Option<Replay>,
+    volume: f32,
+}
+
+struct State {
+    repeat: Repeat,
+    history: VecDeque<Played>,
+    history_size: usize,
+    /// Tracks stopped by the queue that should not be added to the history when they end.
+    retired: Vec<TrackHandle>,
+    /// Tracks stopped by `skip`, which `RepeatMode.One` does not play again.
+    skipped: Vec<TrackHandle>,
+}
+
+impl State {
+    fn record(&mut self, played: Played) {
+        if self.history_size == 0 {
+            return;
+        }
+        while self.history.len() >= self.history_size {
+            self.history.pop_front();
+        }
+        self.history.push_back(played);
+    }
+
+    fn retire(&mut self, handle: TrackHandle) {
+        self.retired.push(handle.clone());
+        if handle.stop().is_err() {
+            self.retired.pop();
+        }
+    }
+}
+
+/// Removes `handle` from `handles` and returns `true` if it was in it.
+fn take(handles: &mut Vec<TrackHandle>, handle: &TrackHandle) -> bool {
+    match handles
+        .iter()
+        .position(|other| other.uuid() == handle.uuid())
+    {
+        Some(index) => {
+            handles.swap_remove(index);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Plays the first track in the queue that can still be played.
+fn play_front(tracks: &mut VecDeque<Queued>) {
+    while let Some(next) = tracks.front() {
+        if next.play().is_ok() {
+            break;
+        }
+        tracks.pop_front();
+    }
+}
+
+/// Pauses `before` and plays the new front of the queue if the front has changed.
+fn switch_front(tracks: &mut VecDeque<Queued>, before: Option<TrackHandle>) {
+    if tracks.front().map(|track| track.uuid()) != before.as_ref().map(|track| track.uuid()) {
+        if let Some(before) = before {
+            let _ = before.pause();
+        }
+        play_front(tracks);
+    }
+}
+
+/// Puts a track back to the start and pauses it.
+fn rewind(track: &TrackHandle) {
+    let _ = track.pause();
+    let _ = track.seek_time(Duration::ZERO);
+}
+
+#[derive(Clone)]
+struct Shared {
+    queue: TrackQueue,
+    state: Arc<Mutex<State>>,
+    driver: Arc<AsyncMutex<Driver>>,
+}
+
+impl Shared {
+    fn new(driver: Arc<AsyncMutex<Driver>>, history_size: usize) -> Self {
+        Self {
+            queue: TrackQueue::new(),
+            state: Arc::new(Mutex::new(State {
+                repeat: Repeat::Off,
+                history: VecDeque::new(),
+                history_size,
+                retired: Vec::new(),
+                skipped: Vec::new(),
+            })),
+            driver,
+        }
+    }
+
+    /// Adds a track to the end of the queue.
+    ///
+    /// The queue's `End` handler is registered before the one songbird's queue adds, so it
+    /// runs first and moves the queue along itself. songbird's handler then finds another
+    /// track at the front and does nothing.
+    fn add(&self, mut track: Track, replay: Option<Replay>, driver: &mut Driver) -> TrackHandle {
+        let handle = track.handle.clone();
+        if let Some(events) = track.events.as_mut() {
+            events.add_event(
+                EventData::new(
+                    Event::Track(TrackEvent::End),
+                    Ended {
+                        shared: self.clone(),
+                        replay,
+                    },
+                ),
+                Duration::ZERO,
+            );
+        }
+        self.queue.add(track, driver);
+        handle
+    }
+
+    /// Adds a new track made from the source of `played`. It is played straight away at the
+    /// front of the queue if `front` is set, otherwise it is added to the end.
+    /// Returns `None` if the source can not be played again.
+    async fn play_again(&self, played: &Played, front: bool) -> Option<TrackHandle> {
+        let input = match played.replay.as_ref()?.input().await {
+            Ok(input) => input,
+            Err(err) => {
+                log::warn!("Could not play a track again: {:?}", err);
+                return None;
+            }
+        };
+        let (mut track, _) = create_player(input);
+        track.set_volume(played.volume);
+        let handle = self.add(track, played.replay.clone(), &mut *self.driver.lock().await);
+
+        if front {
+            self.queue.modify_queue(|tracks| {
+                let index = tracks
+                    .iter()
+                    .position(|track| track.uuid() == handle.uuid());
+                if let Some(track) = index.and_then(|index| tracks.remove(index)) {
+                    if let Some(current) = tracks.front() {
+                        let _ = current.pause();
+                    }
+                    tracks.push_front(track);
+                    play_front(tracks);
+                }
+            });
+        }
+        Some(handle)
+    }
+
+    /// Plays the previous track, see `PyTrackQueue::previous`.
+    async fn previous(&self) -> PyResult<Option<TrackHandle>> {
+        let repeat = self.state.lock().unwrap().repeat;
+        if repeat == Repeat::All {
+            // Finished tracks are added back to the end of the queue.
+            return Ok(self.queue.modify_queue(|tracks| {
+                let last = tracks.pop_back()?;
+                rewind(tracks.front().unwrap_or(&last));
+                let handle = last.handle();
+                tracks.push_front(last);
+                play_front(tracks);
+                Some(handle)
+            }));
+        }
+
+        let played = match self.state.lock().unwrap().history.pop_back() {
+            Some(played) => played,
+            None => return Ok(None),
+        };
+        if played.replay.is_none() {
+            return Err(TrackError::new_err(
+                "The previous track has finished and can not be played again",
+            ));
+        }
+        if let Some(current) = self.queue.current() {
+            rewind(&current);
+        }
+        match self.play_again(&played, true).await {
+            Some(handle) => Ok(Some(handle)),
+            None => Err(TrackError::new_err(
+                "The source of the previous track could not be started again",
+            )),
+        }
+    }
+
+    /// Removes the track at the index returned by `find` and stops it.
+    fn remove(&self, find: impl FnOnce(&VecDeque<Queued>) -> Option<usize>) -> Option<TrackHandle> {
+        self.queue.modify_queue(|tracks| {
+            let index = find(tracks)?;
+            let removed = tracks.remove(index)?.handle();
+            // A removed track can not be added back to the driver, so it is stopped.
+            self.state.lock().unwrap().retire(removed.clone());
+            // songbird only moves the queue along when the track at the front ends, which
+            // the removed track no longer is.
+            if index == 0 {
+                play_front(tracks);
+            }
+            Some(removed)
+        })
+    }
+}
+
+/// Fired when a queued track has ended. Adds it to the history and plays the next track,
+/// or a new track from the same source if it is repeated.
+struct Ended {
+    shared: Shared,
+    replay: Option<Replay>,
+}
+
+#[async_trait]
+impl EventHandler for Ended {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        let (track_state, handle) = match ctx {
+            EventContext::Track(tracks) => tracks.first()?,
+            _ => return None,
+        };
+        let played = Played {
+            handle: (*handle).clone(),
+            replay: self.replay.clone(),
+            volume: track_state.volume,
+        };
+
+        let repeat = {
+            let mut state = self.shared.state.lock().unwrap();
+            if take(&mut state.retired, handle) {
+                return None;
+            }
+            let skipped = take(&mut state.skipped, handle);
+            if !track_state.play_time.is_zero() {
+                state.record(played.clone());
+            }
+            match state.repeat {
+                Repeat::One if skipped => Repeat::Off,
+                repeat => repeat,
+            }
+        };
+        let repeat_one = repeat == Repeat::One && self.replay.is_some();
+
+        let was_front = self.shared.queue.modify_queue(|tracks| {
+            if tracks.front().map(|track| track.uuid()) != Some(handle.uuid()) {
+                return false;
+            }
+            tracks.pop_front();
+            if !repeat_one {
+                play_front(tracks);
+            }
+            true
+        });
+        if !was_front {
+            return None;
+        }
+
+        if repeat_one {
+            // The next track was left paused in case the source can not be started again.
+            if self.shared.play_again(&played, true).await.is_none() {
+                self.shared.queue.modify_queue(play_front);
+            }
+        } else if repeat == Repeat::All {
+            self.shared.play_again(&played, false).await;
+        }
+        None
+    }
+}
+
+/// A queue of tracks that plays the next track when the current one ends.
+/// The queue is advanced inside the driver, so nothing needs to run in Python between tracks.
+///
+/// Queued tracks are added to the driver straight away but paused until they reach the front
+/// of the queue.
+///
+/// Tracks made from a ``RestartableSource`` or ``CompressedSource`` are repeated and played
+/// again from the history by creating a new track from the same source, so they get a new
+/// TrackHandle each time. Other tracks can only be played once, they are skipped by
+/// ``RepeatMode.One`` and ``RepeatMode.All`` and can not be played again with :meth:`previous`.
+///
+/// .. code-block:: python
+///
+///     queue = TrackQueue(driver)
+///     await queue.enqueue_source(await ytdl("https://www.youtube.com/watch?v=r25MAkzkTF4"))
+///     await queue.enqueue_source(await ffmpeg("song.mp3"))
+///
+///     queue.repeat = RepeatMode.All
+///     queue.skip()
+#[pyclass(name = "TrackQueue")]
+#[pyo3(text_signature = "(driver: Driver, history_size: int)")]
+pub struct PyTrackQueue {
+    shared: Shared,
+}
+
+#[pymethods]
+impl PyTrackQueue {
+    #[new]
+    #[args(history_size = "20")]
+    fn new(driver: &PyDriver, history_size: usize) -> Self {
+        Self {
+            shared: Shared::new(driver.driver(), history_size),
+        }
+    }
+
+    /// Adds a Source to the end of the queue.
+    ///
+    /// Raises
+    /// ------
+    /// ConsumedSourceError
+    ///     Source was already played or used to create a track object.
+    #[pyo3(text_signature = "($self, source: Source)")]
+    fn enqueue_source<'p>(
+        &'p self,
+        py: Python<'p>,
+        source: &'p mut PySource,
+    ) -> PyResult<&'p PyAny> {
+        source.raise_if_consumed()?;
+
+        let shared = self.shared.clone();
+        let replay = source.replay.clone();
+        let source = source.source.clone();
+
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let old = mem::take(&mut *source.lock().await);
+            let (track, _) = create_player(old.unwrap());
+            let handle = shared.add(track, replay, &mut *shared.driver.lock().await);
+            Ok(PyTrackHandle::from(handle))
+        })
+    }
+
+    /// Adds a Track to the end of the queue. This makes the Track object unuseable.
+    #[pyo3(text_signature = "($self, track: Track)")]
+    fn enqueue<'p>(&'p self, py: Python<'p>, track: &'p PyTrack) -> PyResult<&'p PyAny> {
+        let shared = self.shared.clone();
+        let replay = track.replay.clone();
+        let track = track.track.clone();
+
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let old = mem::take(&mut *track.lock().await);
+            let handle = shared.add(old.unwrap(), replay, &mut *shared.driver.lock().await);
+            Ok(PyTrackHandle::from(handle))
+        })
+    }
+
+    /// What the queue does when the current track finishes.
+    #[getter]
+    fn repeat(&self) -> PyRepeatMode {
+        PyRepeatMode::from(self.shared.state.lock().unwrap().repeat)
+    }
+
+    #[setter]
+    fn set_repeat(&self, repeat: PyRepeatMode) {
+        self.shared.state.lock().unwrap().repeat = repeat.repeat;
+    }
+
+    /// Plays the next track in the queue. The repeat mode decides what happens to the current
+    /// track, except that ``RepeatMode.One`` moves it to the history like ``RepeatMode.Off``.
+    #[pyo3(text_signature = "($self)")]
+    fn skip(&self) -> PyResult<()> {
+        let current = match self.shared.queue.current() {
+            Some(current) => current,
+            None => return Ok(()),
+        };
+
+        // The track ends like it would at the end of its source.
+        let mut state = self.shared.state.lock().unwrap();
+        state.skipped.push(current.clone());
+        let result = current.stop();
+        if result.is_err() {
+            state.skipped.pop();
+        }
+        handle_track_result(result)
+    }
+
+    /// Plays the last track in the history again. The current track is moved back to the
+    /// start and becomes the next track. With ``RepeatMode.All`` the last track in the queue
+    /// is played instead.
+    ///
+    /// Returns the TrackHandle of the track that is now playing or :data:`None` if there is
+    /// no previous track.
+    ///
+    /// Raises
+    /// ------
+    /// TrackError
+    ///     The previous track was not made from a ``RestartableSource`` or
+    ///     ``CompressedSource``, or its source could not be started again.
+    #[pyo3(text_signature = "($self)")]
+    fn previous<'p>(&'p self, py: Python<'p>) -> PyResult<&'p PyAny> {
+        let shared = self.shared.clone();
+
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            Ok(shared.previous().await?.map(PyTrackHandle::from))
+        })
+    }
+
+    /// Stops every track in the queue and clears it. The history is kept.
+    #[pyo3(text_signature = "($self)")]
+    fn stop(&self) {
+        self.shared.queue.modify_queue(|tracks| {
+            let mut state = self.shared.state.lock().unwrap();
+            for track in tracks.drain(..) {
+                state.retire(track.handle());
+            }
+        })
+    }
+
+    /// Pauses the current track.
+    #[pyo3(text_signature = "($self)")]
+    fn pause(&self) -> PyResult<()> {
+        handle_track_result(self.shared.queue.pause())
+    }
+
+    /// Resumes the current track.
+    #[pyo3(text_signature = "($self)")]
+    fn resume(&self) -> PyResult<()> {
+        handle_track_result(self.shared.queue.resume())
+    }
+
+    /// Returns the TrackHandle for the current track.
+    #[pyo3(text_signature = "($self)")]
+    fn current(&self) -> Option<PyTrackHandle> {
+        self.shared.queue.current().map(PyTrackHandle::from)
+    }
+
+    /// Removes the track at `index` from the queue and stops it.
+    /// Index 0 is the current track, removing it plays the next track.
+    #[pyo3(text_signature = "($self, index: int)")]
+    fn dequeue(&self, index: usize) -> Option<PyTrackHandle> {
+        self.shared.remove(|_| Some(index)).map(PyTrackHandle::from)
+    }
+
+    /// Removes the track with the given uuid from the queue and stops it.
+    #[pyo3(text_signature = "($self, uuid: str)")]
+    fn remove(&self, uuid: &str) -> Option<PyTrackHandle> {
+        self.shared
+            .remove(|tracks| {
+                tracks
+                    .iter()
+                    .position(|track| track.uuid().to_string() == uuid)
+            })
+            .map(PyTrackHandle::from)
+    }
+
+    /// Moves the track at index `source` to index `destination`.
+    /// Moving a track to or from index 0 changes the track that is playing.
+    #[pyo3(text_signature = "($self, source: int, destination: int)")]
+    fn r#move(&self, source: usize, destination: usize) -> PyResult<()> {
+        self.shared.queue.modify_queue(|tracks| {
+            if source >= tracks.len() || destination >= tracks.len() {
+                return Err(PyIndexError::new_err("queue index out of range"));
+            }
+            let before = tracks.front().map(|track| track.handle());
+            let track = tracks.remove(source).unwrap();
+            tracks.insert(destination, track);
+            switch_front(tracks, before);
+            Ok(())
+        })
+    }
+
+    /// Shuffles the queue in place. The current track keeps playing.
+    #[pyo3(text_signature = "($self)")]
+    fn shuffle(&self) {
+        self.shared.queue.modify_queue(|tracks| {
+            if tracks.len() > 2 {
+                tracks.make_contiguous()[1..].shuffle(&mut rand::thread_rng());
+            }
+        })
+    }
+
+    /// Returns the TrackHandles of every track in the queue, including the current track.
+    #[pyo3(text_signature = "($self)")]
+    fn tracks(&self) -> Vec<PyTrackHandle> {
+        self.shared
+            .queue
+            .current_queue()
+            .into_iter()
+            .map(PyTrackHandle::from)
+            .collect()
+    }
+
+    /// Returns the TrackHandles of the tracks that have been played, oldest first.
+    /// Only the last `history_size` tracks are kept.
+    #[pyo3(text_signature = "($self)")]
+    fn history(&self) -> Vec<PyTrackHandle> {
+        self.shared
+            .state
+            .lock()
+            .unwrap()
+            .history
+            .iter()
+            .map(|played| PyTrackHandle::from(played.handle.clone()))
+            .collect()
+    }
+
+    /// Clears the history.
+    #[pyo3(text_signature = "($self)")]
+    fn clear_history(&self) {
+        self.shared.state.lock().unwrap().history.clear();
+    }
+
+    /// Returns :data:`True` if the queue is empty.
+    #[pyo3(text_signature = "($self)")]
+    fn is_empty(&self) -> bool {
+        self.shared.queue.is_empty()
+    }
+
+    fn __len__(&self) -> usize {
+        self.shared.queue.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+
+    use songbird::driver::Bitrate;
+    use songbird::input::cached::Compressed;
+    use songbird::input::{Input, Reader};
+    use songbird::tracks::{LoopState, PlayMode, TrackState};
+    use songbird::Config;
+
+    use super::*;
+
+    // songbird only fires track events from a connected driver, so the tests fire `End`
+    // themselves the way songbird does when a track finishes.
+
+    fn run(test: impl Future<Output = ()>) {
+        pyo3_asyncio::tokio::get_runtime().block_on(test)
+    }
+
+    fn queue() -> PyTrackQueue {
+        let driver = Driver::new(Config::default());
+        PyTrackQueue {
+            shared: Shared::new(Arc::new(AsyncMutex::new(driver)), 20),
+        }
+    }
+
+    /// A second of silence.
+    fn input() -> Input {
+        Input::float_pcm(true, Reader::from_memory(vec![0; 48_000 * 2 * 4]))
+    }
+
+    fn replay() -> Option<Replay> {
+        let compressed = Compressed::new(input(), Bitrate::BitsPerSecond(32_000)).unwrap();
+        Some(Replay::Compressed(Box::new(compressed)))
+    }
+
+    async fn enqueue(queue: &PyTrackQueue, replay: Option<Replay>) -> TrackHandle {
+        let (track, _) = create_player(input());
+        let shared = &queue.shared;
+        shared.add(track, replay, &mut *shared.driver.lock().await)
+    }
+
+    /// Fires `End` for a track that played for a second.
+    async fn end(queue: &PyTrackQueue, handle: &TrackHandle, replay: Option<Replay>) {
+        let state = TrackState {
+            playing: PlayMode::End,
+            volume: 0.5,
+            position: Duration::from_secs(1),
+            play_time: Duration::from_secs(1),
+            loops: LoopState::Finite(0),
+        };
+        let ended = Ended {
+            shared: queue.shared.clone(),
+            replay,
+        };
+        ended.act(&EventContext::Track(&[(&state, handle)])).await;
+    }
+
+    fn uuids(queue: &PyTrackQueue) -> Vec<String> {
+        let tracks = queue.shared.queue.current_queue();
+        tracks
+            .iter()
+            .map(|track| track.uuid().to_string())
+            .collect()
+    }
+
+    fn history(queue: &PyTrackQueue) -> Vec<String> {
+        let state = queue.shared.state.lock().unwrap();
+        let played = state.history.iter();
+        played
+            .map(|played| played.handle.uuid().to_string())
+            .collect()
+    }
+
+    fn ids(handles: &[&TrackHandle]) -> Vec<String> {
+        handles
+            .iter()
+            .map(|handle| handle.uuid().to_string())
+            .collect()
+    }
+
+    fn set_repeat(queue: &PyTrackQueue, repeat: Repeat) {
+        queue.set_repeat(PyRepeatMode::from(repeat));
+    }
+
+    #[test]
+    fn end_plays_the_next_track() {
+        run(async {
+            let queue = queue();
+            let a = enqueue(&queue, None).await;
+            let b = enqueue(&queue, None).await;
+
+            end(&queue, &a, None).await;
+            assert_eq!(uuids(&queue), ids(&[&b]));
+            assert_eq!(history(&queue), ids(&[&a]));
+
+            // A track that is not at the front ending does not move the queue along.
+            end(&queue, &a, None).await;
+            assert_eq!(uuids(&queue), ids(&[&b]));
+        });
+    }
+
+    #[test]
+    fn dequeue_the_current_track() {
+        run(async {
+            let queue = queue();
+            let a = enqueue(&queue, None).await;
+            let b = enqueue(&queue, None).await;
+            let c = enqueue(&queue, None).await;
+
+            assert!(queue.dequeue(0).is_some());
+            assert_eq!(uuids(&queue), ids(&[&b, &c]));
+
+            // The dequeued track is stopped, which is not a finished track.
+            end(&queue, &a, None).await;
+            assert_eq!(uuids(&queue), ids(&[&b, &c]));
+            assert!(history(&queue).is_empty());
+
+            assert!(queue.dequeue(2).is_none());
+        });
+    }
+
+    #[test]
+    fn remove_by_uuid() {
+        run(async {
+            let queue = queue();
+            let a = enqueue(&queue, None).await;
+            let b = enqueue(&queue, None).await;
+
+            assert!(queue.remove(&b.uuid().to_string()).is_some());
+            assert_eq!(uuids(&queue), ids(&[&a]));
+            assert!(queue.remove(&b.uuid().to_string()).is_none());
+        });
+    }
+
+    #[test]
+    fn move_across_the_front() {
+        run(async {
+            let queue = queue();
+            let a = enqueue(&queue, None).await;
+            let b = enqueue(&queue, None).await;
+            let c = enqueue(&queue, None).await;
+
+            queue.r#move(2, 0).unwrap();
+            assert_eq!(uuids(&queue), ids(&[&c, &a, &b]));
+
+            // The old front track ending while it waits does not move the queue along.
+            end(&queue, &a, None).await;
+            assert_eq!(uuids(&queue), ids(&[&c, &a, &b]));
+
+            queue.r#move(0, 2).unwrap();
+            assert_eq!(uuids(&queue), ids(&[&a, &b, &c]));
+            assert!(queue.r#move(0, 3).is_err());
+        });
+    }
+
+    #[test]
+    fn skip_under_one() {
+        run(async {
+            let queue = queue();
+            set_repeat(&queue, Repeat::One);
+            let a = enqueue(&queue, replay()).await;
+            let b = enqueue(&queue, None).await;
+
+            queue.skip().unwrap();
+            end(&queue, &a, replay()).await;
+            assert_eq!(uuids(&queue), ids(&[&b]));
+            assert_eq!(history(&queue), ids(&[&a]));
+        });
+    }
+
+    #[test]
+    fn one_plays_the_track_again() {
+        run(async {
+            let queue = queue();
+            set_repeat(&queue, Repeat::One);
+            let a = enqueue(&queue, replay()).await;
+            let b = enqueue(&queue, None).await;
+
+            end(&queue, &a, replay()).await;
+            let tracks = uuids(&queue);
+            assert_eq!(tracks.len(), 2);
+            assert!(!ids(&[&a, &b]).contains(&tracks[0]));
+            assert_eq!(tracks[1], b.uuid().to_string());
+
+            // Tracks that can not be played again are skipped.
+            let queue = self::queue();
+            set_repeat(&queue, Repeat::One);
+            let a = enqueue(&queue, None).await;
+            let b = enqueue(&queue, None).await;
+
+            end(&queue, &a, None).await;
+            assert_eq!(uuids(&queue), ids(&[&b]));
+        });
+    }
+
+    #[test]
+    fn all_adds_the_track_again() {
+        run(async {
+            let queue = queue();
+            set_repeat(&queue, Repeat::All);
+            let a = enqueue(&queue, replay()).await;
+            let b = enqueue(&queue, None).await;
+            let c = enqueue(&queue, replay()).await;
+
+            end(&queue, &a, replay()).await;
+            let tracks = uuids(&queue);
+            assert_eq!(tracks.len(), 3);
+            assert_eq!(tracks[..2], ids(&[&b, &c]));
+            assert_ne!(tracks[2], a.uuid().to_string());
+
+            // Tracks that can not be played again are dropped.
+            end(&queue, &b, None).await;
+            assert_eq!(uuids(&queue), tracks[1..]);
+            assert_eq!(history(&queue), ids(&[&a, &b]));
+        });
+    }
+
+    #[test]
+    fn previous_plays_the_history_again() {
+        run(async {
+            let queue = queue();
+            let a = enqueue(&queue, replay()).await;
+            let b = enqueue(&queue, None).await;
+
+            end(&queue, &a, replay()).await;
+            let again = queue.shared.previous().await.unwrap().unwrap();
+            assert_ne!(again.uuid(), a.uuid());
+            assert_eq!(uuids(&queue), ids(&[&again, &b]));
+            assert!(history(&queue).is_empty());
+
+            assert!(queue.shared.previous().await.unwrap().is_none());
+        });
+    }
+
+    #[test]
+    fn previous_without_a_replay() {
+        run(async {
+            let queue = queue();
+            let a = enqueue(&queue, None).await;
+            let b = enqueue(&queue, None).await;
+
+            end(&queue, &a, None).await;
+            assert!(queue.shared.previous().await.is_err());
+            assert_eq!(uuids(&queue), ids(&[&b]));
+            assert!(history(&queue).is_empty());
+        });
+    }
+
+    #[test]
+    fn previous_under_all() {
+        run(async {
+            let queue = queue();
+            set_repeat(&queue, Repeat::All);
+            let a = enqueue(&queue, None).await;
+            let b = enqueue(&queue, None).await;
+            let c = enqueue(&queue, None).await;
+
+            let previous = queue.shared.previous().await.unwrap().unwrap();
+            assert_eq!(previous.uuid(), c.uuid());
+            assert_eq!(uuids(&queue), ids(&[&c, &a, &b]));
+        });
+    }
+}
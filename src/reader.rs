@@ -0,0 +1,409 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+use pyo3::buffer::PyBuffer;
+use pyo3::exceptions::{PyStopAsyncIteration, PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3_asyncio::TaskLocals;
+use songbird::input::reader::MediaSource;
+
+/// How much is asked for per call to `read()`.
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// The Python object audio is read from.
+enum Upstream {
+    /// A file-like object with `read(size)` and maybe `seek(offset, whence)`.
+    File(PyObject),
+    /// An async iterator of bytes, which runs on the event loop it was created in.
+    Iterator {
+        iterator: PyObject,
+        locals: TaskLocals,
+    },
+}
+
+impl Upstream {
+    /// Returns the next chunk of data, or `None` at the end of the stream.
+    fn next(&self) -> PyResult<Option<Vec<u8>>> {
+        let chunk = match self {
+            Upstream::File(file) => {
+                Python::with_gil(|py| file.call_method1(py, "read", (CHUNK_SIZE,)))?
+            }
+            Upstream::Iterator { iterator, locals } => {
+                let next = Python::with_gil(|py| {
+                    let awaitable = iterator.as_ref(py).call_method0("__anext__")?;
+                    pyo3_asyncio::into_future_with_locals(locals, awaitable)
+                })?;
+                match pyo3_asyncio::tokio::get_runtime().block_on(next) {
+                    Ok(chunk) => chunk,
+                    Err(err)
+                        if Python::with_gil(|py| {
+                            err.is_instance_of::<PyStopAsyncIteration>(py)
+                        }) =>
+                    {
+                        return Ok(None)
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+        };
+
+        let chunk = Python::with_gil(|py| PyBuffer::<u8>::get(chunk.as_ref(py))?.to_vec(py))?;
+        // An empty chunk only ends a file, iterators end with `StopAsyncIteration`.
+        match self {
+            Upstream::File(_) if chunk.is_empty() => Ok(None),
+            _ => Ok(Some(chunk)),
+        }
+    }
+
+    fn seek(&self, pos: SeekFrom) -> PyResult<u64> {
+        let (offset, whence) = match pos {
+            SeekFrom::Start(offset) => (offset as i64, 0),
+            SeekFrom::Current(offset) => (offset, 1),
+            SeekFrom::End(offset) => (offset, 2),
+        };
+        match self {
+            Upstream::File(file) => {
+                Python::with_gil(|py| file.call_method1(py, "seek", (offset, whence))?.extract(py))
+            }
+            Upstream::Iterator { .. } => Err(PyTypeError::new_err(
+                "Async iterators do not support seeking.",
+            )),
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    /// Data that has been read ahead.
+    chunks: VecDeque<Vec<u8>>,
+    /// How much of the first chunk has been consumed.
+    consumed: usize,
+    /// Bytes in `chunks` that have not been consumed.
+    buffered: usize,
+    eof: bool,
+    error: Option<String>,
+    /// A seek that is waiting for the reader thread.
+    seek: Option<SeekFrom>,
+    /// The result of the last seek.
+    seeked: Option<io::Result<u64>>,
+    closed: bool,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    changed: Condvar,
+    /// How many bytes are read ahead at most.
+    capacity: usize,
+}
+
+enum Action {
+    Read,
+    Seek(SeekFrom),
+}
+
+/// Reads from `upstream` until the buffer is full and waits for it to be consumed.
+/// Python objects are only touched on this thread, so songbird's mixer never waits for the GIL
+/// while there is data in the buffer.
+fn read_ahead(upstream: Upstream, shared: Arc<Shared>) {
+    loop {
+        let action = {
+            let mut state = shared.state.lock().unwrap();
+            loop {
+                if state.closed {
+                    break None;
+                }
+                if let Some(pos) = state.seek.take() {
+                    // The Python object is ahead of the consumer by what has been buffered.
+                    let pos = match pos {
+                        SeekFrom::Current(offset) => {
+                            SeekFrom::Current(offset - state.buffered as i64)
+                        }
+                        pos => pos,
+                    };
+                    break Some(Action::Seek(pos));
+                }
+                if !state.eof && state.buffered < shared.capacity {
+                    break Some(Action::Read);
+                }
+                state = shared.changed.wait(state).unwrap();
+            }
+        };
+
+        match action {
+            Some(Action::Read) => {
+                let result = upstream.next();
+                let mut state = shared.state.lock().unwrap();
+                match result {
+                    Ok(Some(chunk)) => {
+                        state.buffered += chunk.len();
+                        state.chunks.push_back(chunk);
+                    }
+                    Ok(None) => state.eof = true,
+                    Err(err) => {
+                        log::error!("Failed to read from Python object: {}", err);
+                        state.error = Some(err.to_string());
+                        state.eof = true;
+                    }
+                }
+            }
+            Some(Action::Seek(pos)) => {
+                let result = upstream
+                    .seek(pos)
+                    .map_err(|err| io::Error::other(err.to_string()));
+                let mut state = shared.state.lock().unwrap();
+                state.chunks.clear();
+                state.consumed = 0;
+                state.buffered = 0;
+                state.eof = false;
+                state.error = None;
+                state.seeked = Some(result);
+            }
+            None => break,
+        }
+        shared.changed.notify_all();
+    }
+
+    Python::with_gil(|_| drop(upstream));
+}
+
+/// A songbird reader for a Python file-like object or async iterator of bytes.
+///
+/// A thread reads ahead up to `buffer_size` bytes, which songbird then reads from without
+/// calling into Python.
+pub struct PyReader {
+    shared: Arc<Shared>,
+    seekable: bool,
+}
+
+impl PyReader {
+    /// Wraps `obj`, which has to either have `read(size)` or be an async iterator.
+    /// Async iterators run on the running event loop, so this has to be called from it.
+    pub fn new(py: Python, obj: &PyAny, seekable: bool, buffer_size: usize) -> PyResult<Self> {
+        let upstream = if obj.hasattr("__aiter__")? {
+            Upstream::Iterator {
+                iterator: obj.call_method0("__aiter__")?.into(),
+                locals: TaskLocals::new(pyo3_asyncio::get_running_loop(py)?),
+            }
+        } else if obj.hasattr("read")? {
+            Upstream::File(obj.into())
+        } else {
+            return Err(PyTypeError::new_err(
+                "Expected a file-like object with `read()` or an async iterator of bytes.",
+            ));
+        };
+
+        if seekable && !(matches!(upstream, Upstream::File(_)) && obj.hasattr("seek")?) {
+            return Err(PyValueError::new_err(
+                "Only file-like objects with `seek()` can be seekable.",
+            ));
+        }
+
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State::default()),
+            changed: Condvar::new(),
+            capacity: buffer_size.max(1),
+        });
+        let thread_shared = shared.clone();
+        thread::Builder::new()
+            .name("songbird-py-reader".to_string())
+            .spawn(move || read_ahead(upstream, thread_shared))?;
+
+        Ok(Self { shared, seekable })
+    }
+}
+
+impl Read for PyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            let consumed = state.consumed;
+            if let Some(chunk) = state.chunks.front() {
+                let available = &chunk[consumed..];
+                let len = available.len().min(buf.len());
+                buf[..len].copy_from_slice(&available[..len]);
+
+                if consumed + len == chunk.len() {
+                    state.chunks.pop_front();
+                    state.consumed = 0;
+                } else {
+                    state.consumed += len;
+                }
+                state.buffered -= len;
+                self.shared.changed.notify_all();
+                return Ok(len);
+            }
+
+            if let Some(error) = &state.error {
+                return Err(io::Error::other(error.clone()));
+            }
+            if state.eof {
+                return Ok(0);
+            }
+            state = self.shared.changed.wait(state).unwrap();
+        }
+    }
+}
+
+impl Seek for PyReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        if !self.seekable {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "The source was not created with `seekable=True`.",
+            ));
+        }
+
+        let mut state = self.shared.state.lock().unwrap();
+        state.seek = Some(pos);
+        state.seeked = None;
+        self.shared.changed.notify_all();
+        loop {
+            if let Some(result) = state.seeked.take() {
+                return result;
+            }
+            state = self.shared.changed.wait(state).unwrap();
+        }
+    }
+}
+
+impl MediaSource for PyReader {
+    fn is_seekable(&self) -> bool {
+        self.seekable
+    }
+
+    fn byte_len(&self) -> Option<u64> {
+        None
+    }
+}
+
+impl Drop for PyReader {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().closed = true;
+        self.shared.changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use pyo3::types::PyBytes;
+
+    use super::*;
+
+    /// More than two chunks, so reads cross chunk boundaries.
+    fn data() -> Vec<u8> {
+        (0..2 * CHUNK_SIZE + 1000)
+            .map(|i| (i % 251) as u8)
+            .collect()
+    }
+
+    fn bytes_io(py: Python, data: &[u8]) -> PyObject {
+        let io = py.import("io").unwrap();
+        let file = io
+            .call_method1("BytesIO", (PyBytes::new(py, data),))
+            .unwrap();
+        file.into()
+    }
+
+    fn reader(file: &PyObject, seekable: bool, buffer_size: usize) -> PyReader {
+        Python::with_gil(|py| PyReader::new(py, file.as_ref(py), seekable, buffer_size).unwrap())
+    }
+
+    /// Reads everything that is left `size` bytes at a time.
+    fn read_all(reader: &mut PyReader, size: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut buf = vec![0; size];
+        loop {
+            match reader.read(&mut buf).unwrap() {
+                0 => return out,
+                len => out.extend(&buf[..len]),
+            }
+        }
+    }
+
+    #[test]
+    fn read_across_chunks() {
+        pyo3::prepare_freethreaded_python();
+        let data = data();
+        let file = Python::with_gil(|py| bytes_io(py, &data));
+        let mut reader = reader(&file, false, CHUNK_SIZE);
+
+        assert_eq!(read_all(&mut reader, 7000), data);
+        // The end of the stream is reported again on later reads.
+        assert_eq!(reader.read(&mut [0; 16]).unwrap(), 0);
+        assert!(reader.seek(SeekFrom::Start(0)).is_err());
+    }
+
+    #[test]
+    fn seek() {
+        pyo3::prepare_freethreaded_python();
+        let data = data();
+        let file = Python::with_gil(|py| bytes_io(py, &data));
+        let mut reader = reader(&file, true, 4 * CHUNK_SIZE);
+
+        let mut buf = [0; 100];
+        reader.read_exact(&mut buf).unwrap();
+        // The reader thread is ahead of what has been read, which the seek accounts for.
+        assert_eq!(reader.seek(SeekFrom::Current(50)).unwrap(), 150);
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf[..], data[150..250]);
+        assert_eq!(reader.seek(SeekFrom::Current(-200)).unwrap(), 50);
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf[..], data[50..150]);
+
+        assert_eq!(
+            reader.seek(SeekFrom::Start(CHUNK_SIZE as u64)).unwrap(),
+            CHUNK_SIZE as u64
+        );
+        assert_eq!(read_all(&mut reader, 1000), data[CHUNK_SIZE..]);
+        assert_eq!(
+            reader.seek(SeekFrom::End(-10)).unwrap(),
+            data.len() as u64 - 10
+        );
+        assert_eq!(read_all(&mut reader, 1000), data[data.len() - 10..]);
+    }
+
+    #[test]
+    fn read_errors() {
+        pyo3::prepare_freethreaded_python();
+        let file = Python::with_gil(|py| {
+            let module = PyModule::from_code(
+                py,
+                "class Broken:\n    def read(self, size):\n        raise OSError('broken pipe')\n",
+                "broken.py",
+                "broken",
+            )
+            .unwrap();
+            module.getattr("Broken").unwrap().call0().unwrap().into()
+        });
+        let mut reader = reader(&file, false, CHUNK_SIZE);
+
+        let err = reader.read(&mut [0; 16]).unwrap_err();
+        assert!(err.to_string().contains("broken pipe"), "{}", err);
+    }
+
+    #[test]
+    fn drop_stops_the_thread() {
+        pyo3::prepare_freethreaded_python();
+        let file = Python::with_gil(|py| bytes_io(py, &data()));
+        // Counted once the temporary references from creating the file are gone.
+        let refs = Python::with_gil(|py| file.get_refcnt(py));
+        let mut reader = reader(&file, false, 1);
+        reader.read_exact(&mut [0; 16]).unwrap();
+        drop(reader);
+
+        // The thread lets go of the file once it stops.
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Python::with_gil(|py| file.get_refcnt(py)) != refs {
+            assert!(Instant::now() < deadline, "the reader thread did not stop");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
@@ -0,0 +1,454 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use pyo3::prelude::*;
+use songbird::driver::opus::{packet, SampleRate};
+use songbird::events::context_data::VoiceData;
+use songbird::{CoreEvent, Event, EventContext, EventHandler};
+
+use crate::driver::PyDriver;
+use crate::exceptions::CouldNotOpenFileError;
+use crate::ogg::{OggOpusWriter, SILENT_FRAME};
+use crate::registry::Registrations;
+use crate::ssrc::SsrcMap;
+
+/// Samples per channel in a 20 ms frame at 48kHz.
+const FRAME_SIZE: u32 = 960;
+const SAMPLE_RATE: u64 = 48_000;
+/// RTP timestamps that disagree with the time between packets by more than this are ignored.
+const MAX_CLOCK_DRIFT: u64 = SAMPLE_RATE;
+
+/// Returns the Opus packet in a voice packet, or `None` if the packet was not decrypted.
+pub(crate) fn opus_payload<'a>(data: &VoiceData<'a>) -> Option<&'a [u8]> {
+    let payload = &data.packet.payload;
+    let end = payload.len().checked_sub(data.payload_end_pad)?;
+    let body = payload.get(data.payload_offset..end)?;
+
+    if data.packet.extension == 0 {
+        return Some(body);
+    }
+    let words = u16::from_be_bytes([*body.get(2)?, *body.get(3)?]);
+    body.get(4 + 4 * usize::from(words)..)
+}
+
+fn samples_since(instant: Instant) -> u64 {
+    (instant.elapsed().as_micros() as u64) * SAMPLE_RATE / 1_000_000
+}
+
+/// A file being written for one SSRC.
+struct Stream {
+    writer: OggOpusWriter<BufWriter<File>>,
+    path: PathBuf,
+    /// The RTP timestamp right after the last packet.
+    timestamp: u32,
+    last_packet: Instant,
+    granule: u64,
+}
+
+impl Stream {
+    fn create(path: PathBuf, ssrc: u32, user_id: Option<u64>) -> io::Result<Self> {
+        let mut comments = vec![format!("SSRC={}", ssrc)];
+        if let Some(user_id) = user_id {
+            comments.push(format!("DISCORD_USER_ID={}", user_id));
+        }
+
+        let file = BufWriter::new(File::create(&path)?);
+        Ok(Self {
+            writer: OggOpusWriter::new(file, ssrc, 2, &comments)?,
+            path,
+            timestamp: 0,
+            last_packet: Instant::now(),
+            granule: 0,
+        })
+    }
+
+    fn silence(&mut self, frames: u64) -> io::Result<()> {
+        for _ in 0..frames {
+            self.granule += u64::from(FRAME_SIZE);
+            self.writer.write_packet(&SILENT_FRAME, self.granule)?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, timestamp: u32, opus: &[u8]) -> io::Result<()> {
+        let gap = timestamp.wrapping_sub(self.timestamp);
+        if gap >= 1 << 31 {
+            // Late or repeated packet, its place in the file has already been filled.
+            return Ok(());
+        }
+
+        // The RTP clock of a client can jump, for example when it restarts its stream.
+        // The time between the packets is used instead when that happens.
+        let elapsed = samples_since(self.last_packet);
+        let gap = if u64::from(gap).abs_diff(elapsed) > MAX_CLOCK_DRIFT {
+            elapsed
+        } else {
+            u64::from(gap)
+        };
+        self.silence(gap / u64::from(FRAME_SIZE))?;
+
+        let samples = packet::Packet::try_from(opus)
+            .and_then(|opus| packet::nb_samples(opus, SampleRate::Hz48000))
+            .map_or(FRAME_SIZE, |samples| samples as u32);
+
+        self.granule += u64::from(samples);
+        self.writer.write_packet(opus, self.granule)?;
+        self.timestamp = timestamp.wrapping_add(samples);
+        self.last_packet = Instant::now();
+        Ok(())
+    }
+
+    fn finish(self) -> io::Result<String> {
+        self.writer.finish()?;
+        Ok(self.path.to_string_lossy().into_owned())
+    }
+}
+
+struct State {
+    directory: PathBuf,
+    /// When the current files were started, or `None` if the recorder is stopped.
+    started: Option<(Instant, SystemTime)>,
+    streams: HashMap<u32, Stream>,
+}
+
+impl State {
+    fn packet(&mut self, data: &VoiceData, ssrcs: &SsrcMap) {
+        let (started, time) = match self.started {
+            Some(started) => started,
+            None => return,
+        };
+        let opus = match opus_payload(data) {
+            Some(opus) => opus,
+            None => return,
+        };
+        let ssrc = data.packet.ssrc;
+        let timestamp: u32 = data.packet.timestamp.into();
+
+        let res = match self.streams.get_mut(&ssrc) {
+            Some(stream) => stream.write(timestamp, opus),
+            None => {
+                let user_id = ssrcs.user_id(ssrc);
+                let path = self.directory.join(file_name(ssrc, user_id, time));
+
+                Stream::create(path, ssrc, user_id).and_then(|mut stream| {
+                    // Leading silence lines up the files that were started together.
+                    stream.silence(samples_since(started) / u64::from(FRAME_SIZE))?;
+                    stream.timestamp = timestamp;
+                    stream.last_packet = Instant::now();
+                    stream.write(timestamp, opus)?;
+                    self.streams.insert(ssrc, stream);
+                    Ok(())
+                })
+            }
+        };
+
+        if let Err(err) = res {
+            log::error!("Failed to record audio for SSRC {}: {:?}", ssrc, err);
+            if let Some(stream) = self.streams.remove(&ssrc) {
+                let _ = stream.finish();
+            }
+        }
+    }
+
+    /// Finishes every file and returns their paths.
+    fn finish(&mut self) -> PyResult<Vec<String>> {
+        let mut paths = Vec::new();
+        let mut error = None;
+
+        for (_, stream) in self.streams.drain() {
+            match stream.finish() {
+                Ok(path) => paths.push(path),
+                Err(err) => error = Some(err),
+            }
+        }
+
+        match error {
+            Some(err) => Err(CouldNotOpenFileError::new_err(format!("{:?}", err))),
+            None => Ok(paths),
+        }
+    }
+}
+
+fn file_name(ssrc: u32, user_id: Option<u64>, time: SystemTime) -> String {
+    let millis = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_millis();
+    match user_id {
+        Some(user_id) => format!("{}-{}.opus", user_id, millis),
+        None => format!("ssrc-{}-{}.opus", ssrc, millis),
+    }
+}
+
+struct Shared {
+    state: Mutex<State>,
+    ssrcs: Arc<SsrcMap>,
+    closed: AtomicBool,
+    registrations: Registrations,
+}
+
+struct RecorderHandler(Arc<Shared>);
+
+#[async_trait]
+impl EventHandler for RecorderHandler {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        if self.0.closed.load(Ordering::Acquire) {
+            return Some(Event::Cancel);
+        }
+        if let EventContext::VoicePacket(data) = ctx {
+            self.0.state.lock().unwrap().packet(data, &self.0.ssrcs);
+        }
+        None
+    }
+}
+
+/// Records the audio of every user in the voice channel to a separate Ogg Opus file.
+/// The packets from Discord are stored as they are, so there is no re-encoding and no
+/// Python code runs per packet.
+///
+/// Files are named ``{user_id}-{start}.opus``, or ``ssrc-{ssrc}-{start}.opus`` if the user
+/// is not known yet, where ``start`` is the unix time in milliseconds when recording
+/// started. Gaps in a user's audio are filled with silence and every file starts at the
+/// same time, so the files stay in sync with each other.
+///
+/// The driver has to decrypt packets for this to work, so its :class:`DecodeMode` must not
+/// be ``Pass``. The recorder's handler is removed from the driver when the recorder is
+/// dropped, and removing all of the driver's events also stops the recorder.
+///
+/// .. code-block:: python
+///
+///     recorder = VoiceRecorder(driver, "recordings")
+///     recorder.start()
+///     ...
+///     files = recorder.stop()
+#[pyclass(name = "VoiceRecorder")]
+#[pyo3(text_signature = "(driver: Driver, directory: str)")]
+pub struct PyVoiceRecorder {
+    shared: Arc<Shared>,
+}
+
+#[pymethods]
+impl PyVoiceRecorder {
+    #[new]
+    fn new(driver: &PyDriver, directory: String) -> Self {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                directory: PathBuf::from(directory),
+                started: None,
+                streams: HashMap::new(),
+            }),
+            ssrcs: driver.ssrcs(),
+            closed: AtomicBool::new(false),
+            registrations: Registrations::new(),
+        });
+
+        let handler = RecorderHandler(shared.clone());
+        let registry = driver.registry();
+        let driver = driver.driver();
+        let registered = shared.clone();
+        pyo3_asyncio::tokio::get_runtime().spawn(async move {
+            let mut driver = driver.lock().await;
+            let event = Event::Core(CoreEvent::VoicePacket);
+            let id = registry.add(&mut driver, event, handler);
+            registered.registrations.push(&registry, id);
+        });
+
+        Self { shared }
+    }
+
+    /// The directory the files are written to.
+    #[getter]
+    fn directory(&self) -> String {
+        let state = self.shared.state.lock().unwrap();
+        state.directory.to_string_lossy().into_owned()
+    }
+
+    /// Whether the recorder is running.
+    #[getter]
+    fn recording(&self) -> bool {
+        self.shared.state.lock().unwrap().started.is_some()
+    }
+
+    /// Starts recording. Does nothing if the recorder is already running.
+    ///
+    /// Raises
+    /// ------
+    /// CouldNotOpenFileError
+    ///     The directory could not be created.
+    #[pyo3(text_signature = "($self)")]
+    fn start(&self) -> PyResult<()> {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.started.is_none() {
+            create_dir(&state.directory)?;
+            state.started = Some((Instant::now(), SystemTime::now()));
+        }
+        Ok(())
+    }
+
+    /// Stops recording and returns the paths of the finished files.
+    ///
+    /// Raises
+    /// ------
+    /// CouldNotOpenFileError
+    ///     A file could not be finished.
+    #[pyo3(text_signature = "($self) -> List[str]")]
+    fn stop(&self) -> PyResult<Vec<String>> {
+        let mut state = self.shared.state.lock().unwrap();
+        state.started = None;
+        state.finish()
+    }
+
+    /// Finishes the current files and starts new ones without missing any audio.
+    /// Returns the paths of the finished files.
+    ///
+    /// Raises
+    /// ------
+    /// CouldNotOpenFileError
+    ///     A file could not be finished.
+    #[pyo3(text_signature = "($self) -> List[str]")]
+    fn rotate(&self) -> PyResult<Vec<String>> {
+        let mut state = self.shared.state.lock().unwrap();
+        if state.started.is_some() {
+            state.started = Some((Instant::now(), SystemTime::now()));
+        }
+        state.finish()
+    }
+}
+
+fn create_dir(directory: &Path) -> PyResult<()> {
+    fs::create_dir_all(directory)
+        .map_err(|err| CouldNotOpenFileError::new_err(format!("{:?}", err)))
+}
+
+impl Drop for PyVoiceRecorder {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Release);
+        self.shared.registrations.remove();
+        let _ = self.shared.state.lock().unwrap().finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use songbird::driver::Driver;
+    use songbird::Config;
+    use symphonia::core::formats::{FormatOptions, FormatReader};
+    use symphonia::core::io::MediaSourceStream;
+    use symphonia::default::formats::OggReader;
+
+    use super::*;
+    use crate::ogg::OggPacketReader;
+
+    const EVENT: Event = Event::Core(CoreEvent::VoicePacket);
+
+    /// A 20 ms stereo CELT packet.
+    fn opus(i: u8) -> Vec<u8> {
+        vec![0xf8, i]
+    }
+
+    fn recorder(name: &str) -> (PyVoiceRecorder, PyDriver) {
+        let _guard = pyo3_asyncio::tokio::get_runtime().enter();
+        let driver = PyDriver::from(Driver::new(Config::default()));
+        let directory = std::env::temp_dir().join(format!("songbird-py-{}", name));
+        let recorder = PyVoiceRecorder::new(&driver, directory.to_string_lossy().into_owned());
+
+        // The handler is added from a task once the driver is free.
+        let registry = driver.registry();
+        while registry.len(EVENT) == 0 {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        (recorder, driver)
+    }
+
+    #[test]
+    fn gaps_are_filled_with_silence() {
+        let path = std::env::temp_dir().join("songbird-py-recorder-gaps.opus");
+        let mut stream = Stream::create(path.clone(), 1, Some(2)).unwrap();
+        stream.timestamp = 1000;
+        stream.write(1000, &opus(1)).unwrap();
+        stream.write(1960, &opus(2)).unwrap();
+        // Two frames are missing.
+        stream.write(1960 + 960 * 3, &opus(3)).unwrap();
+        // Late and repeated packets are dropped.
+        stream.write(1960, &opus(4)).unwrap();
+        stream.write(1960 + 960 * 3, &opus(5)).unwrap();
+        stream.finish().unwrap();
+
+        let data = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let expected = [
+            opus(1),
+            opus(2),
+            SILENT_FRAME.to_vec(),
+            SILENT_FRAME.to_vec(),
+            opus(3),
+        ];
+
+        let mut reader = OggPacketReader::new(Cursor::new(data.clone()));
+        assert!(reader
+            .next_packet()
+            .unwrap()
+            .unwrap()
+            .starts_with(b"OpusHead"));
+        let tags = reader.next_packet().unwrap().unwrap();
+        assert!(tags.windows(6).any(|tag| tag == b"SSRC=1"));
+        for packet in &expected {
+            assert_eq!(reader.next_packet().unwrap().as_ref(), Some(packet));
+        }
+        assert_eq!(reader.next_packet().unwrap(), None);
+
+        // The silence moves the granule positions of the packets after it.
+        let source = MediaSourceStream::new(Box::new(Cursor::new(data)), Default::default());
+        let mut demuxer = OggReader::try_new(source, &FormatOptions::default()).unwrap();
+        for (i, packet) in expected.iter().enumerate() {
+            let read = demuxer.next_packet().unwrap();
+            assert_eq!(&*read.data, &packet[..]);
+            assert_eq!(read.ts(), 960 * i as u64);
+            assert_eq!(read.dur(), 960);
+        }
+        assert!(demuxer.next_packet().is_err());
+    }
+
+    #[test]
+    fn rotate() {
+        let (recorder, _driver) = recorder("recorder-rotate");
+        assert_eq!(recorder.rotate().unwrap(), Vec::<String>::new());
+        assert!(!recorder.recording());
+
+        recorder.start().unwrap();
+        let path = Path::new(&recorder.directory()).join("rotate.opus");
+        {
+            let mut state = recorder.shared.state.lock().unwrap();
+            let mut stream = Stream::create(path.clone(), 1, None).unwrap();
+            stream.write(0, &opus(1)).unwrap();
+            state.streams.insert(1, stream);
+        }
+
+        let paths = recorder.rotate().unwrap();
+        assert_eq!(paths, [path.to_string_lossy().into_owned()]);
+        assert!(recorder.recording());
+        assert!(recorder.shared.state.lock().unwrap().streams.is_empty());
+        assert!(fs::metadata(&path).unwrap().len() > 0);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(recorder.stop().unwrap(), Vec::<String>::new());
+        assert!(!recorder.recording());
+    }
+
+    #[test]
+    fn drop_removes_the_handler() {
+        let (recorder, driver) = recorder("recorder-drop");
+        assert_eq!(driver.registry().len(EVENT), 1);
+        drop(recorder);
+        assert_eq!(driver.registry().len(EVENT), 0);
+    }
+}
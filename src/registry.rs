@@ -0,0 +1,265 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+
+use async_trait::async_trait;
+use once_cell::sync::Lazy;
+use songbird::driver::Driver;
+use songbird::tracks::{TrackHandle, TrackResult};
+use songbird::{Event, EventContext, EventHandler};
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// The registries of tracks by their uuid. A registry lives as long as the handlers it has
+/// registered with its track.
+static TRACKS: Lazy<Mutex<HashMap<u128, Weak<Registry>>>> = Lazy::new(Default::default);
+
+type Handlers = Vec<(u64, Arc<dyn EventHandler>)>;
+type Ids = Vec<(Arc<Registry>, u64)>;
+
+/// Event handlers of a driver or track that can be removed one at a time.
+///
+/// songbird ignores the `Event` returned by a handler of an untimed event unless it is the
+/// event the handler was registered with, so those handlers can never cancel themselves.
+/// A registry registers a single `Dispatcher` per untimed event instead, which runs the
+/// handlers added for that event until they are removed. Timed events are passed straight
+/// to songbird, which drops them when they return `Event::Cancel`.
+#[derive(Default)]
+pub struct Registry {
+    /// The handlers of each event a `Dispatcher` has been registered for.
+    handlers: Mutex<HashMap<Event, Handlers>>,
+}
+
+impl Registry {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Returns the registry of `track`, which is shared by every handle of the track.
+    pub fn track(track: &TrackHandle) -> Arc<Self> {
+        let mut tracks = TRACKS.lock().unwrap();
+        tracks.retain(|_, registry| registry.strong_count() > 0);
+        let registry = tracks.get(&track.uuid().as_u128()).and_then(Weak::upgrade);
+        registry.unwrap_or_else(|| {
+            let registry = Self::new();
+            tracks.insert(track.uuid().as_u128(), Arc::downgrade(&registry));
+            registry
+        })
+    }
+
+    /// Adds a global event handler to `driver` and returns the id that removes it.
+    pub fn add(
+        self: &Arc<Self>,
+        driver: &mut Driver,
+        event: Event,
+        handler: impl EventHandler + 'static,
+    ) -> u64 {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        if !matches!(event, Event::Core(_) | Event::Track(_)) {
+            driver.add_global_event(event, handler);
+        } else if self.insert(id, event, Arc::new(handler)) {
+            driver.add_global_event(event, self.dispatcher(event));
+        }
+        id
+    }
+
+    /// Adds an event handler to `track` and returns the id that removes it.
+    pub fn add_to_track(
+        self: &Arc<Self>,
+        track: &TrackHandle,
+        event: Event,
+        handler: impl EventHandler + 'static,
+    ) -> TrackResult<u64> {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        if !matches!(event, Event::Core(_) | Event::Track(_)) {
+            track.add_event(event, handler)?;
+            return Ok(id);
+        }
+
+        let mut handlers = self.handlers.lock().unwrap();
+        if !handlers.contains_key(&event) {
+            track.add_event(event, self.dispatcher(event))?;
+        }
+        handlers
+            .entry(event)
+            .or_default()
+            .push((id, Arc::new(handler)));
+        Ok(id)
+    }
+
+    /// Removes the handler with `id`. Does nothing for handlers of timed events.
+    pub fn remove(&self, id: u64) {
+        for handlers in self.handlers.lock().unwrap().values_mut() {
+            handlers.retain(|(other, _)| *other != id);
+        }
+    }
+
+    /// Forgets every handler. This has to be done when songbird drops the dispatchers.
+    pub fn clear(&self) {
+        self.handlers.lock().unwrap().clear();
+    }
+
+    /// Returns `true` if a handler still needs a `Dispatcher` to be registered.
+    fn insert(&self, id: u64, event: Event, handler: Arc<dyn EventHandler>) -> bool {
+        let mut handlers = self.handlers.lock().unwrap();
+        let dispatched = handlers.contains_key(&event);
+        handlers.entry(event).or_default().push((id, handler));
+        !dispatched
+    }
+
+    fn dispatcher(self: &Arc<Self>, event: Event) -> Dispatcher {
+        Dispatcher {
+            registry: self.clone(),
+            event,
+        }
+    }
+
+    /// The number of handlers registered for `event`.
+    #[cfg(test)]
+    pub(crate) fn len(&self, event: Event) -> usize {
+        let handlers = self.handlers.lock().unwrap();
+        handlers.get(&event).map_or(0, Vec::len)
+    }
+
+    fn contains(&self, event: Event, id: u64) -> bool {
+        let handlers = self.handlers.lock().unwrap();
+        let handlers = handlers.get(&event).map(Vec::as_slice).unwrap_or_default();
+        handlers.iter().any(|(other, _)| *other == id)
+    }
+
+    /// Runs the handlers of `event`. A handler that returns `Event::Cancel` is removed.
+    pub(crate) async fn fire(&self, event: Event, ctx: &EventContext<'_>) {
+        let handlers = match self.handlers.lock().unwrap().get(&event) {
+            Some(handlers) => handlers.clone(),
+            None => return,
+        };
+        for (id, handler) in handlers {
+            // An earlier handler can remove a later one.
+            if !self.contains(event, id) {
+                continue;
+            }
+            if handler.act(ctx).await == Some(Event::Cancel) {
+                self.remove(id);
+            }
+        }
+    }
+}
+
+/// The handlers of an object that are removed together when it is closed. Handlers are often
+/// registered from a task once the driver is free, so a handler that is only added after
+/// `remove` is removed straight away.
+pub struct Registrations {
+    /// `None` once the handlers have been removed.
+    ids: Mutex<Option<Ids>>,
+}
+
+impl Registrations {
+    pub fn new() -> Self {
+        Self {
+            ids: Mutex::new(Some(Vec::new())),
+        }
+    }
+
+    pub fn push(&self, registry: &Arc<Registry>, id: u64) {
+        match self.ids.lock().unwrap().as_mut() {
+            Some(ids) => ids.push((registry.clone(), id)),
+            None => registry.remove(id),
+        }
+    }
+
+    pub fn remove(&self) {
+        let ids = self.ids.lock().unwrap().take();
+        for (registry, id) in ids.into_iter().flatten() {
+            registry.remove(id);
+        }
+    }
+}
+
+/// The handler songbird runs for an untimed event of a `Registry`.
+struct Dispatcher {
+    registry: Arc<Registry>,
+    event: Event,
+}
+
+#[async_trait]
+impl EventHandler for Dispatcher {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        self.registry.fire(self.event, ctx).await;
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use songbird::model::id::UserId;
+    use songbird::model::payload::ClientDisconnect;
+    use songbird::{Config, CoreEvent};
+
+    use super::*;
+
+    const EVENT: Event = Event::Core(CoreEvent::ClientDisconnect);
+
+    struct Counter {
+        calls: Arc<AtomicUsize>,
+        cancel: bool,
+    }
+
+    #[async_trait]
+    impl EventHandler for Counter {
+        async fn act(&self, _ctx: &EventContext<'_>) -> Option<Event> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            self.cancel.then(|| Event::Cancel)
+        }
+    }
+
+    fn counter(cancel: bool) -> (Counter, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let handler = Counter {
+            calls: calls.clone(),
+            cancel,
+        };
+        (handler, calls)
+    }
+
+    fn fire(registry: &Registry) {
+        let disconnect = ClientDisconnect { user_id: UserId(1) };
+        let ctx = EventContext::ClientDisconnect(disconnect);
+        pyo3_asyncio::tokio::get_runtime().block_on(registry.fire(EVENT, &ctx));
+    }
+
+    #[test]
+    fn remove() {
+        let _guard = pyo3_asyncio::tokio::get_runtime().enter();
+        let mut driver = Driver::new(Config::default());
+        let registry = Registry::new();
+        let (first, first_calls) = counter(false);
+        let (second, second_calls) = counter(false);
+        let first = registry.add(&mut driver, EVENT, first);
+        registry.add(&mut driver, EVENT, second);
+
+        fire(&registry);
+        registry.remove(first);
+        fire(&registry);
+        assert_eq!(first_calls.load(Ordering::SeqCst), 1);
+        assert_eq!(second_calls.load(Ordering::SeqCst), 2);
+
+        registry.clear();
+        fire(&registry);
+        assert_eq!(second_calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn cancel() {
+        let _guard = pyo3_asyncio::tokio::get_runtime().enter();
+        let mut driver = Driver::new(Config::default());
+        let registry = Registry::new();
+        let (handler, calls) = counter(true);
+        registry.add(&mut driver, EVENT, handler);
+
+        fire(&registry);
+        fire(&registry);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,586 @@
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::mem;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+
+use flume::{Receiver, Sender};
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use songbird::driver::bench_internals::mixer::Mixer;
+use songbird::driver::bench_internals::task_message::{
+    CoreMessage, EventMessage, Interconnect, MixerConnection, MixerMessage, UdpRxMessage,
+    UdpTxMessage,
+};
+use songbird::driver::bench_internals::CryptoState;
+use songbird::driver::opus::coder::Decoder;
+use songbird::driver::opus::{Channels, SampleRate};
+use songbird::tracks::{create_player, LoopState, PlayMode, Track};
+use songbird::Config;
+use tokio::sync::Mutex;
+use xsalsa20poly1305::aead::{AeadInPlace, NewAead};
+use xsalsa20poly1305::{Key, Nonce, Tag, XSalsa20Poly1305 as Cipher, KEY_SIZE, TAG_SIZE};
+
+use crate::config::PyConfig;
+use crate::exceptions::CouldNotOpenFileError;
+use crate::ogg::{OggOpusWriter, SILENT_FRAME};
+use crate::source::PySource;
+use crate::track::PyTrack;
+use crate::track_handle::PyTrackHandle;
+
+const RTP_HEADER_SIZE: usize = 12;
+const STEREO_FRAME_SIZE: usize = 1920;
+const FRAME_LENGTH: f64 = 0.02;
+
+/// Runs songbird's mixer without a voice connection.
+/// The packets the mixer would send to Discord are decrypted and decoded instead.
+struct Render {
+    mixer: Mixer,
+    cipher: Cipher,
+    decoder: Decoder,
+    packets: Receiver<UdpTxMessage>,
+    events: Receiver<EventMessage>,
+    // The mixer fails if the other ends of its channels are dropped.
+    _core: Receiver<CoreMessage>,
+    _mix_tx: Sender<MixerMessage>,
+    _udp_rx: Receiver<UdpRxMessage>,
+}
+
+impl Render {
+    fn new(config: Config) -> Self {
+        let (core_tx, core_rx) = flume::unbounded();
+        let (events_tx, events_rx) = flume::unbounded();
+        let (mix_tx, mix_rx) = flume::unbounded();
+        let (udp_tx, packets) = flume::unbounded();
+        let (udp_rx_tx, udp_rx) = flume::unbounded();
+
+        let interconnect = Interconnect {
+            core: core_tx,
+            events: events_tx,
+            mixer: mix_tx.clone(),
+        };
+
+        let handle = pyo3_asyncio::tokio::get_runtime().handle().clone();
+        let mut mixer = Mixer::new(mix_rx, handle, interconnect, config);
+
+        // The key never leaves this struct, so a fixed one is fine.
+        let cipher = Cipher::new(Key::from_slice(&[0u8; KEY_SIZE]));
+
+        mixer.conn_active = Some(MixerConnection {
+            cipher: cipher.clone(),
+            crypto_state: CryptoState::Normal,
+            udp_rx: udp_rx_tx,
+            udp_tx,
+        });
+
+        Self {
+            mixer,
+            cipher,
+            decoder: Decoder::new(SampleRate::Hz48000, Channels::Stereo)
+                .expect("Failed to create opus decoder with known-good values."),
+            packets,
+            events: events_rx,
+            _core: core_rx,
+            _mix_tx: mix_tx,
+            _udp_rx: udp_rx,
+        }
+    }
+
+    fn play(&mut self, track: Track, only: bool) {
+        if only {
+            self.mixer.tracks.clear();
+        }
+        self.mixer.tracks.push(track);
+    }
+
+    fn is_done(&self) -> bool {
+        self.mixer
+            .tracks
+            .iter()
+            .all(|track| track.playing().is_done())
+    }
+
+    /// Returns `true` if a track that has not ended is paused or loops forever, so
+    /// rendering until every track has ended would never stop.
+    fn is_endless(&self) -> bool {
+        self.mixer.tracks.iter().any(|track| {
+            let playing = track.playing();
+            !playing.is_done() && (playing == PlayMode::Pause || track.loops == LoopState::Infinite)
+        })
+    }
+
+    /// Mixes the next 20ms of audio and returns the Opus packet the mixer made for it,
+    /// or `None` if the mixer sent nothing because there was only silence.
+    fn render_frame(&mut self) -> PyResult<Option<Vec<u8>>> {
+        self.mixer
+            .cycle()
+            .map_err(|err| PyRuntimeError::new_err(format!("{:?}", err)))?;
+
+        // Track events can not be fired without a driver.
+        while self.events.try_recv().is_ok() {}
+
+        match self.packets.try_recv() {
+            Ok(UdpTxMessage::Packet(mut packet)) => Ok(Some(self.decrypt(&mut packet)?.to_vec())),
+            _ => Ok(None),
+        }
+    }
+
+    /// Decrypts a packet made with `CryptoMode::Normal` and returns the opus payload.
+    fn decrypt<'a>(&self, packet: &'a mut [u8]) -> PyResult<&'a [u8]> {
+        let (header, body) = packet.split_at_mut(RTP_HEADER_SIZE);
+        if body.len() < TAG_SIZE {
+            return Err(PyRuntimeError::new_err("Mixer produced a truncated packet."));
+        }
+
+        let mut nonce = Nonce::default();
+        nonce[..RTP_HEADER_SIZE].copy_from_slice(header);
+
+        let (tag, data) = body.split_at_mut(TAG_SIZE);
+        self.cipher
+            .decrypt_in_place_detached(&nonce, b"", data, Tag::from_slice(tag))
+            .map_err(|_| PyRuntimeError::new_err("Could not decrypt mixed packet."))?;
+
+        Ok(data)
+    }
+
+    /// Mixes `frames` frames, or until every track has ended if `None`, and passes the Opus
+    /// packet of each frame to `write`. Frames the mixer sent nothing for are `None`.
+    fn render(
+        &mut self,
+        frames: Option<usize>,
+        realtime: bool,
+        mut write: impl FnMut(&mut Decoder, Option<&[u8]>) -> PyResult<()>,
+    ) -> PyResult<()> {
+        self.mixer.skip_sleep = !realtime;
+        self.mixer.deadline = Instant::now();
+
+        let mut rendered = 0;
+
+        loop {
+            match frames {
+                Some(frames) if rendered >= frames => break,
+                None if self.is_done() => break,
+                // Checked on every frame because a track can be paused or set to loop
+                // with its handle while it is rendered.
+                None if self.is_endless() => {
+                    return Err(PyValueError::new_err(
+                        "A track is paused or loops forever, so a duration is required.",
+                    ))
+                }
+                _ => {}
+            }
+            let opus = self.render_frame()?;
+            write(&mut self.decoder, opus.as_deref())?;
+            rendered += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Renders interleaved stereo samples.
+    fn render_pcm(&mut self, frames: Option<usize>, realtime: bool) -> PyResult<Vec<i16>> {
+        let mut out = Vec::with_capacity(frames.unwrap_or(0) * STEREO_FRAME_SIZE);
+        self.render(frames, realtime, |decoder, opus| {
+            let start = out.len();
+            out.resize(start + STEREO_FRAME_SIZE, 0);
+
+            if let Some(opus) = opus {
+                let samples = decoder
+                    .decode(
+                        Some(opus.try_into().map_err(opus_error)?),
+                        (&mut out[start..]).try_into().map_err(opus_error)?,
+                        false,
+                    )
+                    .map_err(opus_error)?;
+                out.truncate(start + 2 * samples);
+            }
+            Ok(())
+        })?;
+        Ok(out)
+    }
+
+    /// Writes the Opus packets made by the mixer into an Ogg Opus file without decoding them.
+    fn render_ogg(&mut self, frames: Option<usize>, realtime: bool, path: &str) -> PyResult<()> {
+        let file_error = |err: io::Error| CouldNotOpenFileError::new_err(err.to_string());
+
+        let file = BufWriter::new(File::create(path).map_err(file_error)?);
+        let mut writer = OggOpusWriter::new(file, rand::random(), 2, &[]).map_err(file_error)?;
+        let mut granule = 0;
+        self.render(frames, realtime, |_, opus| {
+            granule += (STEREO_FRAME_SIZE / 2) as u64;
+            writer
+                .write_packet(opus.unwrap_or(&SILENT_FRAME), granule)
+                .map_err(file_error)
+        })?;
+        writer.finish().map_err(file_error)?;
+        Ok(())
+    }
+}
+
+fn opus_error<E: std::fmt::Debug>(err: E) -> PyErr {
+    PyRuntimeError::new_err(format!("{:?}", err))
+}
+
+fn duration_to_frames(duration: Option<f64>) -> Option<usize> {
+    duration.map(|duration| (duration / FRAME_LENGTH).ceil() as usize)
+}
+
+/// A driver that mixes its tracks into a file or buffer instead of sending them to Discord.
+/// Volume, loops and seeks set on a ``Track`` are applied the same way as in a ``Driver``.
+///
+/// .. code-block:: python
+///
+///     driver = DryRunDriver()
+///
+///     track, handle = await create_player(Source.bytes(data, True))
+///     await track.set_volume(0.5)
+///     await driver.play(track)
+///
+///     pcm = await driver.render()
+///
+/// .. note::
+///
+///     Track events are not fired. Changes made with a ``TrackHandle`` are applied from the
+///     next rendered frame on, but queries like ``TrackHandle.get_info`` are only answered
+///     while a render is running, so they have to be awaited from another task.
+#[pyclass(name = "DryRunDriver")]
+#[pyo3(text_signature = "(config: Optional[Config])")]
+pub struct PyDryRunDriver {
+    render: Arc<Mutex<Render>>,
+}
+
+#[pymethods]
+impl PyDryRunDriver {
+    #[new]
+    #[args(config = "None")]
+    fn new(config: Option<&PyConfig>) -> Self {
+        let config = match config {
+            Some(py_config) => py_config.config.clone(),
+            None => Config::default(),
+        };
+
+        Self {
+            render: Arc::new(Mutex::new(Render::new(config))),
+        }
+    }
+
+    /// Plays a Source object.
+    ///
+    /// Raises
+    /// ------
+    /// ConsumedSourceError
+    ///     Source was already played or used to create a track object.
+    fn play_source<'p>(&'p self, py: Python<'p>, source: &'p mut PySource) -> PyResult<&'p PyAny> {
+        source.raise_if_consumed()?;
+
+        let render = self.render.clone();
+        let source = source.source.clone();
+
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let old = mem::take(&mut *source.lock().await);
+            let (track, handle) = create_player(old.unwrap());
+            render.lock().await.play(track, false);
+            Ok(PyTrackHandle::from(handle))
+        })
+    }
+
+    /// Same as `play_source` but stops all other sources from playing.
+    ///
+    /// Raises
+    /// ------
+    /// ConsumedSourceError
+    ///     Source was already played or used to create a track object.
+    fn play_only_source<'p>(
+        &'p self,
+        py: Python<'p>,
+        source: &'p mut PySource,
+    ) -> PyResult<&'p PyAny> {
+        source.raise_if_consumed()?;
+
+        let render = self.render.clone();
+        let source = source.source.clone();
+
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let old = mem::take(&mut *source.lock().await);
+            let (track, handle) = create_player(old.unwrap());
+            render.lock().await.play(track, true);
+            Ok(PyTrackHandle::from(handle))
+        })
+    }
+
+    /// Plays a Track object. This makes the Track object unuseable.
+    fn play<'p>(&'p self, py: Python<'p>, track: &'p PyTrack) -> PyResult<&'p PyAny> {
+        let render = self.render.clone();
+        let handle = PyTrackHandle::from(track.handle.clone());
+        let track = track.track.clone();
+
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let old = mem::take(&mut *track.lock().await);
+            render.lock().await.play(old.unwrap(), false);
+            Ok(handle)
+        })
+    }
+
+    /// Same as `play` but stops all other tracks from playing.
+    fn play_only<'p>(&'p self, py: Python<'p>, track: &'p PyTrack) -> PyResult<&'p PyAny> {
+        let render = self.render.clone();
+        let handle = PyTrackHandle::from(track.handle.clone());
+        let track = track.track.clone();
+
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let old = mem::take(&mut *track.lock().await);
+            render.lock().await.play(old.unwrap(), true);
+            Ok(handle)
+        })
+    }
+
+    /// Stops playing audio from all tracks.
+    fn stop<'p>(&'p self, py: Python<'p>) -> PyResult<&'p PyAny> {
+        let render = self.render.clone();
+
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            render.lock().await.mixer.tracks.clear();
+            Ok(())
+        })
+    }
+
+    /// Mixes the playing tracks and returns the audio as 48kHz stereo signed 16 bit
+    /// little endian PCM.
+    ///
+    /// Args:
+    ///     duration: Seconds of audio to render. Renders until every track has ended if
+    ///         :data:`None`.
+    ///     realtime: Mix at the same pace as a connected driver instead of as fast as possible.
+    ///
+    /// Raises
+    /// ------
+    /// ValueError
+    ///     `duration` is :data:`None` and a track is paused or loops forever.
+    #[args(duration = "None", realtime = "false")]
+    #[pyo3(text_signature = "($self, duration: Optional[float], realtime: bool)")]
+    fn render<'p>(
+        &'p self,
+        py: Python<'p>,
+        duration: Option<f64>,
+        realtime: bool,
+    ) -> PyResult<&'p PyAny> {
+        let render = self.render.clone();
+        let frames = duration_to_frames(duration);
+
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            let samples = tokio::task::spawn_blocking(move || {
+                render.blocking_lock().render_pcm(frames, realtime)
+            })
+            .await
+            .map_err(|err| PyRuntimeError::new_err(err.to_string()))??;
+
+            let bytes: Vec<u8> = samples
+                .iter()
+                .flat_map(|sample| sample.to_le_bytes())
+                .collect();
+
+            Ok(Python::with_gil(|py| {
+                PyObject::from(PyBytes::new(py, &bytes))
+            }))
+        })
+    }
+
+    /// Mixes the playing tracks into a file. Paths ending in ``.ogg`` or ``.opus`` get the
+    /// Opus packets the mixer made in an Ogg Opus file, other paths get a 48kHz stereo
+    /// 16 bit WAV file.
+    ///
+    /// Args:
+    ///     path: The file to write.
+    ///     duration: Seconds of audio to render. Renders until every track has ended if
+    ///         :data:`None`.
+    ///     realtime: Mix at the same pace as a connected driver instead of as fast as possible.
+    ///
+    /// Raises
+    /// ------
+    /// CouldNotOpenFileError
+    ///     The file could not be written.
+    /// ValueError
+    ///     `duration` is :data:`None` and a track is paused or loops forever.
+    #[args(duration = "None", realtime = "false")]
+    #[pyo3(text_signature = "($self, path: str, duration: Optional[float], realtime: bool)")]
+    fn render_to_file<'p>(
+        &'p self,
+        py: Python<'p>,
+        path: String,
+        duration: Option<f64>,
+        realtime: bool,
+    ) -> PyResult<&'p PyAny> {
+        let render = self.render.clone();
+        let frames = duration_to_frames(duration);
+
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            tokio::task::spawn_blocking(move || {
+                let mut render = render.blocking_lock();
+                if is_ogg(&path) {
+                    render.render_ogg(frames, realtime, &path)
+                } else {
+                    let samples = render.render_pcm(frames, realtime)?;
+                    write_wav(&path, &samples)
+                        .map_err(|err| CouldNotOpenFileError::new_err(format!("{:?}", err)))
+                }
+            })
+            .await
+            .map_err(|err| PyRuntimeError::new_err(err.to_string()))?
+        })
+    }
+}
+
+fn is_ogg(path: &str) -> bool {
+    matches!(
+        Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str()),
+        Some("ogg" | "opus")
+    )
+}
+
+fn write_wav(path: &str, samples: &[i16]) -> Result<(), hound::Error> {
+    let spec = hound::WavSpec {
+        channels: 2,
+        sample_rate: 48000,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+
+    let mut writer = hound::WavWriter::create(path, spec)?;
+    for sample in samples {
+        writer.write_sample(*sample)?;
+    }
+    writer.finalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::PI;
+    use std::time::Duration;
+
+    use songbird::input::{Input, Reader};
+
+    use super::*;
+    use crate::ogg::OggPacketReader;
+
+    /// A 440Hz stereo sine wave at half of full scale.
+    fn tone(seconds: f32) -> Track {
+        let bytes = (0..(48_000.0 * seconds) as usize)
+            .map(|i| 0.5 * (i as f32 * 2.0 * PI * 440.0 / 48_000.0).sin())
+            .flat_map(|sample| [sample, sample])
+            .flat_map(f32::to_le_bytes)
+            .collect();
+        create_player(Input::float_pcm(true, Reader::from_memory(bytes))).0
+    }
+
+    fn rms(samples: &[i16]) -> f64 {
+        let sum: f64 = samples.iter().map(|&s| f64::from(s).powi(2)).sum();
+        (sum / samples.len() as f64).sqrt() / f64::from(i16::MAX)
+    }
+
+    fn assert_near(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < expected * 0.1,
+            "expected about {}, got {}",
+            expected,
+            actual
+        );
+    }
+
+    /// Frames rendered until a track of `seconds` ends, which can take a few more frames
+    /// than the track is long because of the Opus encoder's delay.
+    fn assert_frames(samples: &[i16], seconds: f64) {
+        let frames = samples.len() / STEREO_FRAME_SIZE;
+        let expected = (seconds / FRAME_LENGTH) as usize;
+        assert!(
+            (expected..=expected + 2).contains(&frames),
+            "expected {} frames, got {}",
+            expected,
+            frames
+        );
+    }
+
+    const FULL_VOLUME_RMS: f64 = 0.5 / std::f64::consts::SQRT_2;
+
+    #[test]
+    fn play() {
+        let mut render = Render::new(Config::default());
+        render.play(tone(1.0), false);
+
+        let samples = render.render_pcm(None, false).unwrap();
+        assert_frames(&samples, 1.0);
+        assert_near(rms(&samples), FULL_VOLUME_RMS);
+    }
+
+    #[test]
+    fn set_volume() {
+        let mut render = Render::new(Config::default());
+        let mut track = tone(1.0);
+        track.set_volume(0.5);
+        render.play(track, false);
+
+        let samples = render.render_pcm(None, false).unwrap();
+        assert_near(rms(&samples), FULL_VOLUME_RMS * 0.5);
+    }
+
+    #[test]
+    fn seek_time() {
+        let mut render = Render::new(Config::default());
+        let mut track = tone(1.0);
+        track.seek_time(Duration::from_millis(600)).unwrap();
+        render.play(track, false);
+
+        let samples = render.render_pcm(None, false).unwrap();
+        assert_frames(&samples, 0.4);
+    }
+
+    #[test]
+    fn play_only() {
+        let mut render = Render::new(Config::default());
+        render.play(tone(2.0), false);
+        let mut track = tone(1.0);
+        track.set_volume(0.5);
+        render.play(track, true);
+
+        let samples = render.render_pcm(None, false).unwrap();
+        assert_frames(&samples, 1.0);
+        assert_near(rms(&samples), FULL_VOLUME_RMS * 0.5);
+    }
+
+    #[test]
+    fn render_ogg() {
+        let path = std::env::temp_dir().join("songbird-py-render-test.ogg");
+        let mut render = Render::new(Config::default());
+        render.play(tone(1.0), false);
+        render
+            .render_ogg(Some(60), false, path.to_str().unwrap())
+            .unwrap();
+
+        let mut packets = OggPacketReader::new(File::open(&path).unwrap());
+        let mut count = 0;
+        while packets.next_packet().unwrap().is_some() {
+            count += 1;
+        }
+        std::fs::remove_file(&path).unwrap();
+        // Two header packets, then one packet per frame including the silent ones.
+        assert_eq!(count, 2 + 60);
+    }
+
+    #[test]
+    fn endless_tracks_need_a_duration() {
+        let mut render = Render::new(Config::default());
+        let mut track = tone(0.1);
+        track.set_loops(LoopState::Infinite).unwrap();
+        render.play(track, false);
+
+        assert!(render.render_pcm(None, false).is_err());
+        let samples = render.render_pcm(Some(20), false).unwrap();
+        assert_eq!(samples.len(), 20 * STEREO_FRAME_SIZE);
+        // The track is still playing after it has looped a few times.
+        assert!(rms(&samples[15 * STEREO_FRAME_SIZE..]) > 0.1);
+    }
+}
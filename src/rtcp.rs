@@ -0,0 +1,538 @@
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use discortp::pnet::packet::PrimitiveValues;
+use discortp::rtcp::report::{ReportBlockPacket, SenderInfoPacket};
+use discortp::rtcp::Rtcp;
+use pyo3::prelude::*;
+use songbird::events::context_data::RtcpData;
+
+const SENDER_REPORT: u8 = 200;
+const RECEIVER_REPORT: u8 = 201;
+const SOURCE_DESCRIPTION: u8 = 202;
+const GOODBYE: u8 = 203;
+
+const SENDER_INFO_LEN: usize = 20;
+const REPORT_BLOCK_LEN: usize = 24;
+
+/// Seconds between the NTP epoch (1900) and the unix epoch.
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+/// Clock rate of the RTP timestamps used by Discord.
+const CLOCK_RATE: f64 = 48_000.0;
+
+/// The middle 32 bits of the current NTP timestamp, in units of 1/65536 seconds.
+fn ntp_now_short() -> u32 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let seconds = now.as_secs() + NTP_UNIX_OFFSET;
+    let fraction = (u64::from(now.subsec_nanos()) << 16) / 1_000_000_000;
+    ((seconds as u32) << 16) | fraction as u32
+}
+
+/// Reception statistics for one SSRC, from a sender or receiver report.
+#[pyclass(name = "ReportBlock")]
+#[derive(Clone)]
+pub struct PyReportBlock {
+    /// The SSRC this block is about.
+    #[pyo3(get)]
+    pub ssrc: u32,
+    /// Fraction of packets lost since the previous report, between 0 and 1.
+    #[pyo3(get)]
+    pub fraction_lost: f64,
+    /// Number of packets lost since reception started. Can be negative when there were
+    /// duplicate packets.
+    #[pyo3(get)]
+    pub cumulative_lost: i32,
+    /// Highest sequence number received, extended with the number of wraparounds.
+    #[pyo3(get)]
+    pub highest_sequence: u32,
+    /// Interarrival jitter in RTP timestamp units.
+    #[pyo3(get)]
+    pub jitter: u32,
+    /// Interarrival jitter in seconds.
+    #[pyo3(get)]
+    pub jitter_seconds: f64,
+    /// Middle 32 bits of the NTP timestamp of the last sender report from `ssrc`.
+    #[pyo3(get)]
+    pub last_sender_report: u32,
+    /// Seconds between receiving the last sender report from `ssrc` and sending this report.
+    #[pyo3(get)]
+    pub delay_since_last_sender_report: f64,
+    /// Round trip time in seconds, calculated when the packet was received.
+    /// :data:`None` if no sender report has been received from `ssrc`.
+    #[pyo3(get)]
+    pub round_trip_time: Option<f64>,
+}
+
+impl PyReportBlock {
+    fn parse(data: &[u8], arrival: u32) -> Option<Self> {
+        let block = ReportBlockPacket::new(data)?;
+
+        // The cumulative loss is a signed 24 bit integer.
+        let cumulative_lost = ((block.get_cumulative_pkts_lost() << 8) as i32) >> 8;
+        let jitter = block.get_interarrival_jitter();
+        let last_sr = block.get_last_sr_timestamp();
+        let delay = block.get_last_sr_delay();
+
+        let round_trip_time = if last_sr == 0 {
+            None
+        } else {
+            let rtt = arrival.wrapping_sub(last_sr).wrapping_sub(delay);
+            // Anything this large means the clocks do not line up.
+            (rtt < 1 << 31).then(|| f64::from(rtt) / 65536.0)
+        };
+
+        Some(Self {
+            ssrc: block.get_ssrc(),
+            fraction_lost: f64::from(block.get_fraction_lost()) / 256.0,
+            cumulative_lost,
+            highest_sequence: (u32::from(block.get_cycles()) << 16)
+                | u32::from(block.get_sequence()),
+            jitter,
+            jitter_seconds: f64::from(jitter) / CLOCK_RATE,
+            last_sender_report: last_sr,
+            delay_since_last_sender_report: f64::from(delay) / 65536.0,
+            round_trip_time,
+        })
+    }
+
+    fn parse_all(data: &[u8], count: u8, arrival: u32) -> Vec<Self> {
+        data.chunks_exact(REPORT_BLOCK_LEN)
+            .take(count.into())
+            .filter_map(|block| Self::parse(block, arrival))
+            .collect()
+    }
+}
+
+/// An RTCP sender report.
+#[pyclass(name = "SenderReport")]
+#[derive(Clone)]
+pub struct PySenderReport {
+    #[pyo3(get)]
+    pub ssrc: u32,
+    /// Wallclock time the report was sent as a 64 bit NTP timestamp.
+    #[pyo3(get)]
+    pub ntp_timestamp: u64,
+    /// `ntp_timestamp` in the same units as the RTP timestamps of the sender.
+    #[pyo3(get)]
+    pub rtp_timestamp: u32,
+    /// Packets sent since the start of the session.
+    #[pyo3(get)]
+    pub packet_count: u32,
+    /// Payload bytes sent since the start of the session.
+    #[pyo3(get)]
+    pub octet_count: u32,
+    #[pyo3(get)]
+    pub reports: Vec<PyReportBlock>,
+}
+
+/// An RTCP receiver report.
+#[pyclass(name = "ReceiverReport")]
+#[derive(Clone)]
+pub struct PyReceiverReport {
+    #[pyo3(get)]
+    pub ssrc: u32,
+    #[pyo3(get)]
+    pub reports: Vec<PyReportBlock>,
+}
+
+/// The items describing a single SSRC in a source description.
+#[pyclass(name = "SdesChunk")]
+#[derive(Clone)]
+pub struct PySdesChunk {
+    #[pyo3(get)]
+    pub ssrc: u32,
+    /// Items by name, such as ``"cname"`` and ``"tool"``.
+    #[pyo3(get)]
+    pub items: HashMap<String, String>,
+}
+
+/// An RTCP source description (SDES) packet.
+#[pyclass(name = "SourceDescription")]
+#[derive(Clone)]
+pub struct PySourceDescription {
+    #[pyo3(get)]
+    pub chunks: Vec<PySdesChunk>,
+}
+
+/// An RTCP goodbye (BYE) packet.
+#[pyclass(name = "Goodbye")]
+#[derive(Clone)]
+pub struct PyGoodbye {
+    /// The SSRCs that are leaving.
+    #[pyo3(get)]
+    pub ssrcs: Vec<u32>,
+    #[pyo3(get)]
+    pub reason: Option<String>,
+}
+
+#[derive(Clone)]
+pub enum Report {
+    Sender(PySenderReport),
+    Receiver(PyReceiverReport),
+    SourceDescription(PySourceDescription),
+    Goodbye(PyGoodbye),
+}
+
+impl IntoPy<PyObject> for Report {
+    fn into_py(self, py: Python) -> PyObject {
+        match self {
+            Self::Sender(report) => report.into_py(py),
+            Self::Receiver(report) => report.into_py(py),
+            Self::SourceDescription(sdes) => sdes.into_py(py),
+            Self::Goodbye(bye) => bye.into_py(py),
+        }
+    }
+}
+
+fn read_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn sdes_item_name(item: u8) -> String {
+    match item {
+        1 => "cname".to_string(),
+        2 => "name".to_string(),
+        3 => "email".to_string(),
+        4 => "phone".to_string(),
+        5 => "loc".to_string(),
+        6 => "tool".to_string(),
+        7 => "note".to_string(),
+        8 => "priv".to_string(),
+        other => other.to_string(),
+    }
+}
+
+fn parse_sdes(data: &[u8], count: u8) -> PySourceDescription {
+    let mut chunks = Vec::new();
+    let mut at = 0;
+
+    for _ in 0..count {
+        let ssrc = match read_u32(data, at) {
+            Some(ssrc) => ssrc,
+            None => break,
+        };
+        at += 4;
+
+        let mut items = HashMap::new();
+        while let Some(&item) = data.get(at) {
+            if item == 0 {
+                break;
+            }
+            let len = match data.get(at + 1) {
+                Some(&len) => usize::from(len),
+                None => break,
+            };
+            if let Some(text) = data.get(at + 2..at + 2 + len) {
+                items.insert(
+                    sdes_item_name(item),
+                    String::from_utf8_lossy(text).into_owned(),
+                );
+            }
+            at += 2 + len;
+        }
+        // The item list ends with a zero byte and is padded to the next 32 bit boundary.
+        at = (at + 4) & !3;
+
+        chunks.push(PySdesChunk { ssrc, items });
+    }
+
+    PySourceDescription { chunks }
+}
+
+fn parse_bye(data: &[u8], count: u8) -> PyGoodbye {
+    let ssrcs: Vec<u32> = (0..usize::from(count))
+        .map_while(|i| read_u32(data, i * 4))
+        .collect();
+
+    let at = ssrcs.len() * 4;
+    let reason = data.get(at).and_then(|&len| {
+        data.get(at + 1..at + 1 + usize::from(len))
+            .map(|text| String::from_utf8_lossy(text).into_owned())
+    });
+
+    PyGoodbye { ssrcs, reason }
+}
+
+/// Parses the body of a single RTCP packet. For sender and receiver reports `body` starts
+/// with the SSRC of the sender.
+fn parse_packet(packet_type: u8, count: u8, body: &[u8], arrival: u32) -> Option<Report> {
+    match packet_type {
+        SENDER_REPORT => {
+            let ssrc = read_u32(body, 0)?;
+            let info = SenderInfoPacket::new(body.get(4..4 + SENDER_INFO_LEN)?)?;
+            Some(Report::Sender(PySenderReport {
+                ssrc,
+                ntp_timestamp: (u64::from(info.get_ntp_timestamp_second()) << 32)
+                    | u64::from(info.get_ntp_timestamp_fraction()),
+                rtp_timestamp: info.get_rtp_timestamp(),
+                packet_count: info.get_pkt_count(),
+                octet_count: info.get_byte_count(),
+                reports: PyReportBlock::parse_all(&body[4 + SENDER_INFO_LEN..], count, arrival),
+            }))
+        }
+        RECEIVER_REPORT => Some(Report::Receiver(PyReceiverReport {
+            ssrc: read_u32(body, 0)?,
+            reports: PyReportBlock::parse_all(&body[4..], count, arrival),
+        })),
+        SOURCE_DESCRIPTION => Some(Report::SourceDescription(parse_sdes(body, count))),
+        GOODBYE => Some(Report::Goodbye(parse_bye(body, count))),
+        _ => None,
+    }
+}
+
+/// Parses the packets that follow the first one in a compound RTCP packet.
+fn parse_compound(mut data: &[u8], arrival: u32, reports: &mut Vec<Report>) {
+    while data.len() >= 4 {
+        let count = data[0] & 0x1f;
+        let packet_type = data[1];
+        let len = (usize::from(u16::from_be_bytes([data[2], data[3]])) + 1) * 4;
+        let body = match data.get(4..len) {
+            Some(body) => body,
+            None => break,
+        };
+
+        reports.extend(parse_packet(packet_type, count, body, arrival));
+        data = &data[len..];
+    }
+}
+
+/// An RTCP packet from the voice server, split into its reports.
+///
+/// The packet body is only readable when the ``DecodeMode`` of the driver decrypts packets.
+/// songbird only keeps the contents of compound packets that start with a sender or
+/// receiver report, which is how RTCP packets are normally sent.
+#[pyclass(name = "RtcpData")]
+#[derive(Clone)]
+pub struct PyRtcpData {
+    /// The packet type of the first packet.
+    #[pyo3(get)]
+    pub packet_type: u8,
+    /// ``SenderReport``, ``ReceiverReport``, ``SourceDescription`` and ``Goodbye`` objects,
+    /// in the order they appear in the packet.
+    #[pyo3(get)]
+    pub packets: Vec<Report>,
+    #[pyo3(get)]
+    pub payload_offset: usize,
+    #[pyo3(get)]
+    pub payload_end_pad: usize,
+}
+
+impl PyRtcpData {
+    pub fn from(data: &RtcpData) -> Self {
+        let arrival = ntp_now_short();
+        let mut packets = Vec::new();
+
+        let (packet_type, first) = match data.packet {
+            Rtcp::SenderReport(report) => (
+                SENDER_REPORT,
+                Some((
+                    report.rx_report_count,
+                    report.ssrc,
+                    report.pkt_length,
+                    &report.payload,
+                )),
+            ),
+            Rtcp::ReceiverReport(report) => (
+                RECEIVER_REPORT,
+                Some((
+                    report.rx_report_count,
+                    report.ssrc,
+                    report.pkt_length,
+                    &report.payload,
+                )),
+            ),
+            Rtcp::KnownType(packet_type) => (packet_type.to_primitive_values().0, None),
+            _ => (0, None),
+        };
+
+        if let Some((count, ssrc, length, payload)) = first {
+            let end = payload.len().saturating_sub(data.payload_end_pad);
+            let decrypted = payload.get(data.payload_offset..end).unwrap_or_default();
+
+            // The first packet's header has already been parsed by songbird, the SSRC is put
+            // back in front so it can be parsed like the others.
+            let len = ((usize::from(length) + 1) * 4).saturating_sub(8);
+            let (body, rest) = decrypted.split_at(len.min(decrypted.len()));
+            let mut first = ssrc.to_be_bytes().to_vec();
+            first.extend_from_slice(body);
+
+            packets.extend(parse_packet(packet_type, count, &first, arrival));
+            parse_compound(rest, arrival, &mut packets);
+        }
+
+        Self {
+            packet_type,
+            packets,
+            payload_offset: data.payload_offset,
+            payload_end_pad: data.payload_end_pad,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The arrival time used for round trip times, 3 seconds in NTP short format.
+    const ARRIVAL: u32 = 3 << 16;
+
+    /// A report block about `ssrc` with a 24 bit cumulative loss.
+    fn block(ssrc: u32, cumulative_lost: [u8; 3], last_sr: u32, delay: u32) -> Vec<u8> {
+        let mut block = ssrc.to_be_bytes().to_vec();
+        block.push(64);
+        block.extend_from_slice(&cumulative_lost);
+        block.extend_from_slice(&1u16.to_be_bytes());
+        block.extend_from_slice(&5u16.to_be_bytes());
+        block.extend_from_slice(&480u32.to_be_bytes());
+        block.extend_from_slice(&last_sr.to_be_bytes());
+        block.extend_from_slice(&delay.to_be_bytes());
+        block
+    }
+
+    /// Puts an RTCP header in front of `body`, which must be a multiple of 4 bytes long.
+    fn packet(packet_type: u8, count: u8, body: &[u8]) -> Vec<u8> {
+        let words = (body.len() / 4) as u16;
+        let mut packet = vec![0x80 | count, packet_type];
+        packet.extend_from_slice(&words.to_be_bytes());
+        packet.extend_from_slice(body);
+        packet
+    }
+
+    fn receiver_report() -> Vec<u8> {
+        let mut body = 7u32.to_be_bytes().to_vec();
+        body.extend(block(1, [0xff, 0xff, 0xff], 0, 0));
+        body
+    }
+
+    fn sdes() -> Vec<u8> {
+        let mut body = Vec::new();
+        // Items that end on a 32 bit boundary are followed by a whole word of zeros.
+        body.extend_from_slice(&1u32.to_be_bytes());
+        body.extend_from_slice(&[1, 2, b'a', b'b', 0, 0, 0, 0]);
+        body.extend_from_slice(&2u32.to_be_bytes());
+        body.extend_from_slice(&[2, 1, b'x', 6, 2, b'a', b'b', 0]);
+        body
+    }
+
+    fn bye() -> Vec<u8> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&1u32.to_be_bytes());
+        body.extend_from_slice(&2u32.to_be_bytes());
+        body.push(7);
+        body.extend_from_slice(b"leaving");
+        body
+    }
+
+    #[test]
+    fn sender_report() {
+        let mut body = 7u32.to_be_bytes().to_vec();
+        for field in [100, 1 << 31, 48_000, 10, 1_000] {
+            body.extend_from_slice(&u32::to_be_bytes(field));
+        }
+        // The last sender report was at 1s and the report was sent 0.5s after it.
+        body.extend(block(1, [0xff, 0xff, 0xfe], 1 << 16, 1 << 15));
+
+        let report = match parse_packet(SENDER_REPORT, 1, &body, ARRIVAL) {
+            Some(Report::Sender(report)) => report,
+            _ => panic!("not a sender report"),
+        };
+        assert_eq!(report.ssrc, 7);
+        assert_eq!(report.ntp_timestamp, (100 << 32) | 1 << 31);
+        assert_eq!(report.rtp_timestamp, 48_000);
+        assert_eq!(report.packet_count, 10);
+        assert_eq!(report.octet_count, 1_000);
+
+        let block = &report.reports[0];
+        assert_eq!(report.reports.len(), 1);
+        assert_eq!(block.ssrc, 1);
+        assert_eq!(block.fraction_lost, 0.25);
+        assert_eq!(block.cumulative_lost, -2);
+        assert_eq!(block.highest_sequence, (1 << 16) | 5);
+        assert_eq!(block.jitter_seconds, 0.01);
+        assert_eq!(block.delay_since_last_sender_report, 0.5);
+        assert_eq!(block.round_trip_time, Some(1.5));
+    }
+
+    #[test]
+    fn receiver_report_without_a_sender_report() {
+        // The count is larger than the number of blocks in the packet.
+        let report = match parse_packet(RECEIVER_REPORT, 2, &receiver_report(), ARRIVAL) {
+            Some(Report::Receiver(report)) => report,
+            _ => panic!("not a receiver report"),
+        };
+        assert_eq!(report.ssrc, 7);
+        assert_eq!(report.reports.len(), 1);
+        assert_eq!(report.reports[0].cumulative_lost, -1);
+        assert_eq!(report.reports[0].round_trip_time, None);
+    }
+
+    #[test]
+    fn source_description() {
+        let sdes = match parse_packet(SOURCE_DESCRIPTION, 2, &sdes(), ARRIVAL) {
+            Some(Report::SourceDescription(sdes)) => sdes,
+            _ => panic!("not a source description"),
+        };
+        assert_eq!(sdes.chunks.len(), 2);
+        assert_eq!(sdes.chunks[0].ssrc, 1);
+        assert_eq!(sdes.chunks[0].items["cname"], "ab");
+        assert_eq!(sdes.chunks[1].ssrc, 2);
+        assert_eq!(sdes.chunks[1].items["name"], "x");
+        assert_eq!(sdes.chunks[1].items["tool"], "ab");
+    }
+
+    #[test]
+    fn goodbye() {
+        let bye = match parse_packet(GOODBYE, 2, &bye(), ARRIVAL) {
+            Some(Report::Goodbye(bye)) => bye,
+            _ => panic!("not a goodbye"),
+        };
+        assert_eq!(bye.ssrcs, [1, 2]);
+        assert_eq!(bye.reason.as_deref(), Some("leaving"));
+    }
+
+    #[test]
+    fn compound() {
+        let mut data = packet(RECEIVER_REPORT, 1, &receiver_report());
+        data.extend(packet(SOURCE_DESCRIPTION, 2, &sdes()));
+        data.extend(packet(GOODBYE, 2, &bye()));
+
+        let mut reports = Vec::new();
+        parse_compound(&data, ARRIVAL, &mut reports);
+        assert!(matches!(
+            reports.as_slice(),
+            [
+                Report::Receiver(_),
+                Report::SourceDescription(_),
+                Report::Goodbye(_)
+            ]
+        ));
+
+        // Every truncation of the packet is parsed without panicking.
+        for len in 0..data.len() {
+            let mut reports = Vec::new();
+            parse_compound(&data[..len], ARRIVAL, &mut reports);
+            assert!(reports.len() < 3);
+        }
+    }
+
+    #[test]
+    fn truncated_packets() {
+        let mut sender_report = 7u32.to_be_bytes().to_vec();
+        sender_report.extend([0; SENDER_INFO_LEN]);
+        sender_report.extend(block(1, [0; 3], 0, 0));
+
+        for (packet_type, body) in [
+            (SENDER_REPORT, sender_report),
+            (RECEIVER_REPORT, receiver_report()),
+            (SOURCE_DESCRIPTION, sdes()),
+            (GOODBYE, bye()),
+        ] {
+            for len in 0..body.len() {
+                parse_packet(packet_type, 31, &body[..len], ARRIVAL);
+            }
+        }
+    }
+}
@@ -1,7 +1,8 @@
 use std::mem;
 
 use pyo3::prelude::*;
-use songbird::input::{cached::Compressed, Restartable};
+use songbird::input::error::Result as InputResult;
+use songbird::input::{cached::Compressed, Input, Restartable};
 
 use crate::config::PyBitrate;
 use crate::exceptions::{
@@ -9,19 +10,35 @@ use crate::exceptions::{
 };
 use crate::source::PySource;
 
-#[pyclass(name = "RestartableSource")]
-pub struct PyRestartableSource {
-    restartable: Option<Restartable>,
+/// Makes new inputs that play the same audio as a source, so a track that has ended can
+/// be played again.
+#[derive(Clone)]
+pub enum Replay {
+    Compressed(Box<Compressed>),
+    Ytdl(String),
+    Ffmpeg(String),
 }
 
-impl From<Restartable> for PyRestartableSource {
-    fn from(restartable: Restartable) -> Self {
-        Self {
-            restartable: Some(restartable),
+impl Replay {
+    /// Creates a new input. Restartable sources are created lazily, so this does not wait
+    /// for ffmpeg or youtube-dl to start.
+    pub async fn input(&self) -> InputResult<Input> {
+        match self {
+            Replay::Compressed(compressed) => Ok(compressed.new_handle().into()),
+            Replay::Ytdl(url) => Ok(Restartable::ytdl(url.clone(), true).await?.into()),
+            Replay::Ffmpeg(filename) => {
+                Ok(Restartable::ffmpeg(filename.clone(), true).await?.into())
+            }
         }
     }
 }
 
+#[pyclass(name = "RestartableSource")]
+pub struct PyRestartableSource {
+    restartable: Option<Restartable>,
+    replay: Replay,
+}
+
 #[pymethods]
 impl PyRestartableSource {
     /// Convert the `RestartableSource` into a `Source`
@@ -36,7 +53,10 @@ impl PyRestartableSource {
     fn into_source(&mut self) -> Result<PySource, PyErr> {
         let maybe_restartable = mem::take(&mut self.restartable);
         if let Some(restartable) = maybe_restartable {
-            Ok(PySource::from(restartable.into()))
+            Ok(PySource::replayable(
+                restartable.into(),
+                self.replay.clone(),
+            ))
         } else {
             Err(ConsumedSourceError::new_err(
                 "RestartableSource already converted to source.",
@@ -48,8 +68,11 @@ impl PyRestartableSource {
     #[staticmethod]
     fn ytdl<'p>(py: Python, url: String, lazy: bool) -> PyResult<&PyAny> {
         pyo3_asyncio::tokio::future_into_py(py, async move {
-            match Restartable::ytdl(url, lazy).await {
-                Ok(res) => Ok(Self::from(res)),
+            match Restartable::ytdl(url.clone(), lazy).await {
+                Ok(res) => Ok(Self {
+                    restartable: Some(res),
+                    replay: Replay::Ytdl(url),
+                }),
                 Err(err) => Err(YtdlError::new_err(format!("{:?}", err))),
             }
         })
@@ -59,8 +82,11 @@ impl PyRestartableSource {
     #[staticmethod]
     fn ffmpeg<'p>(py: Python, filename: String, lazy: bool) -> PyResult<&PyAny> {
         pyo3_asyncio::tokio::future_into_py(py, async move {
-            match Restartable::ffmpeg(filename, lazy).await {
-                Ok(res) => Ok(Self::from(res)),
+            match Restartable::ffmpeg(filename.clone(), lazy).await {
+                Ok(res) => Ok(Self {
+                    restartable: Some(res),
+                    replay: Replay::Ffmpeg(filename),
+                }),
                 Err(err) => Err(FfmpegError::new_err(format!("{:?}", err))),
             }
         })
@@ -93,7 +119,8 @@ impl PyCompressedSource {
     fn into_source(&mut self) -> Result<PySource, PyErr> {
         let maybe_compressed = mem::take(&mut self.compressed);
         if let Some(compressed) = maybe_compressed {
-            Ok(PySource::from(compressed.into()))
+            let replay = Replay::Compressed(Box::new(compressed.new_handle()));
+            Ok(PySource::replayable(compressed.into(), replay))
         } else {
             Err(ConsumedSourceError::new_err(
                 "CompressedSource already converted to source.",
@@ -1,13 +1,26 @@
+use std::collections::HashMap;
 use std::fs::File;
+use std::io::{self, BufRead, BufReader, Cursor, Read};
+use std::path::Path;
+use std::process::{ChildStderr, Command, Stdio};
 use std::sync::Arc;
+use std::thread;
 
 use tokio::sync::Mutex;
 
+use pyo3::exceptions::PyValueError;
 use pyo3::prelude::*;
 use pyo3::types::{PyDict, PyString};
-use songbird::input::{Input, Reader};
+use songbird::constants::{CHILD_BUFFER_LEN, STEREO_FRAME_BYTE_SIZE};
+use songbird::input::error::{DcaError, Error as InputError, Result as InputResult};
+use songbird::input::{ChildContainer, Codec, Container, Input, Metadata, Reader};
 
+use crate::decoder;
 use crate::exceptions::{ConsumedSourceError, CouldNotOpenFileError, FfmpegError, YtdlError};
+use crate::opus;
+use crate::pcm::PyPcmFormat;
+use crate::reader::PyReader;
+use crate::seekable::Replay;
 use crate::track_handle::PyMetadata;
 
 mod builtins {
@@ -21,6 +34,8 @@ pub struct PySource {
     /// This method of creating inputs allows you to use an Input multiple times in
     /// Python, which is probably expected.
     pub source: Arc<Mutex<Option<Input>>>,
+    /// Set for sources that a ``TrackQueue`` can play again after they ended.
+    pub replay: Option<Replay>,
     consumed: bool,
 }
 
@@ -28,10 +43,18 @@ impl PySource {
     pub fn from(input: Input) -> Self {
         Self {
             source: Arc::from(Mutex::from(Some(input))),
+            replay: None,
             consumed: false,
         }
     }
 
+    pub fn replayable(input: Input, replay: Replay) -> Self {
+        Self {
+            replay: Some(replay),
+            ..Self::from(input)
+        }
+    }
+
     pub fn raise_if_consumed(&mut self) -> Result<(), PyErr> {
         if self.consumed {
             Err(ConsumedSourceError::new_err(concat!(
@@ -62,6 +85,161 @@ fn map_args<'a>(value: Option<&'a PyAny>) -> Result<Vec<String>, PyErr> {
         .collect::<Vec<String>>())
 }
 
+/// ffmpeg protocols whose inputs do not start with `//`, like `pipe:0`.
+const PROTOCOLS: [&str; 5] = ["pipe", "data", "fd", "concat", "subfile"];
+
+/// Seconds ffmpeg waits for data from a URL when no `timeout` is given.
+const DEFAULT_TIMEOUT: f64 = 30.0;
+
+/// Whether `input` is a URL or protocol input for ffmpeg, like `https://...` or `pipe:0`,
+/// rather than a path. Other inputs with a colon, like `C:\music` or `a:b.mp3`, are paths.
+fn is_url(input: &str) -> bool {
+    match input.split_once(':') {
+        Some((scheme, rest)) => {
+            // Single letter schemes are Windows drive letters.
+            scheme.len() > 1
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+                && (rest.starts_with("//") || PROTOCOLS.contains(&scheme))
+        }
+        None => false,
+    }
+}
+
+/// Turns the `headers`, `reconnect` and `timeout` keyword arguments into ffmpeg input options.
+/// Without a `timeout`, ffmpeg gives up after `DEFAULT_TIMEOUT` so that a server that stops
+/// responding can not block forever.
+fn network_args(kwargs: Option<&PyDict>) -> PyResult<Vec<String>> {
+    let mut args = vec![];
+    let get = |key| {
+        kwargs
+            .and_then(|kwargs| kwargs.get_item(key))
+            .filter(|value| !value.is_none())
+    };
+
+    if let Some(headers) = get("headers") {
+        let headers: HashMap<String, String> = headers.extract()?;
+        let headers: String = headers
+            .iter()
+            .map(|(name, value)| format!("{}: {}\r\n", name, value))
+            .collect();
+        args.extend(["-headers".to_string(), headers]);
+    }
+
+    if let Some(reconnect) = get("reconnect") {
+        if reconnect.extract::<bool>()? {
+            args.extend(
+                [
+                    "-reconnect",
+                    "1",
+                    "-reconnect_streamed",
+                    "1",
+                    "-reconnect_delay_max",
+                    "5",
+                ]
+                .map(String::from),
+            );
+        }
+    }
+
+    let timeout = match get("timeout") {
+        Some(timeout) => timeout.extract()?,
+        None => DEFAULT_TIMEOUT,
+    };
+    if !(timeout > 0.0 && timeout.is_finite()) {
+        return Err(PyValueError::new_err(
+            "timeout must be a positive number of seconds.",
+        ));
+    }
+    // ffmpeg takes the timeout in microseconds.
+    args.extend([
+        "-rw_timeout".to_string(),
+        ((timeout * 1_000_000.0) as u64).to_string(),
+    ]);
+
+    Ok(args)
+}
+
+/// Reads ffmpeg's log until it exits, so it never blocks on a full pipe, and returns the
+/// end of it.
+fn read_log(mut stderr: ChildStderr) -> String {
+    const KEEP: usize = 4096;
+    let mut log = Vec::new();
+    let mut buf = [0; 1024];
+    while let Ok(len @ 1..) = stderr.read(&mut buf) {
+        log.extend_from_slice(&buf[..len]);
+        if log.len() > KEEP {
+            log.drain(..log.len() - KEEP);
+        }
+    }
+    String::from_utf8_lossy(&log).trim().to_string()
+}
+
+/// Starts ffmpeg with stereo output. Unlike `songbird::ffmpeg` this does not run ffprobe on
+/// the input first, which would not get the input options and could wait forever on a URL
+/// that does not respond.
+///
+/// This blocks until ffmpeg produces its first output, so an input it can not open, like a
+/// URL that gives a 404, is an error with ffmpeg's message instead of a silent source.
+fn ffmpeg_stereo(input: &str, pre_input_args: &[String], args: &[String]) -> InputResult<Input> {
+    let default_args = [
+        "-f",
+        "f32le",
+        "-ac",
+        "2",
+        "-ar",
+        "48000",
+        "-acodec",
+        "pcm_f32le",
+        "-",
+    ];
+
+    let mut command = Command::new("ffmpeg");
+    command.args(pre_input_args).arg("-i").arg(input);
+    if args.is_empty() {
+        command.args(default_args);
+    } else {
+        command.args(args);
+    }
+    let mut child = command
+        .stderr(Stdio::piped())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    let stderr = child.stderr.take().unwrap();
+    let log = thread::spawn(move || read_log(stderr));
+
+    let mut reader = BufReader::with_capacity(
+        STEREO_FRAME_BYTE_SIZE * CHILD_BUFFER_LEN,
+        ChildContainer::new(vec![child]),
+    );
+    if reader.fill_buf()?.is_empty() {
+        drop(reader);
+        let log = log.join().unwrap_or_default();
+        let message = if log.is_empty() {
+            format!("ffmpeg produced no audio from `{}`.", input)
+        } else {
+            format!("ffmpeg could not play `{}`: {}", input, log)
+        };
+        return Err(InputError::Io(io::Error::other(message)));
+    }
+
+    Ok(Input::new(
+        true,
+        Reader::Pipe(reader),
+        Codec::FloatPcm,
+        Container::Raw,
+        Some(Metadata {
+            source_url: Some(input.to_string()),
+            channels: Some(2),
+            sample_rate: Some(48000),
+            ..Default::default()
+        }),
+    ))
+}
+
 #[pymethods]
 impl PySource {
     /// Use youtube dl to play a video from a URL
@@ -91,7 +269,7 @@ impl PySource {
     }
 
     /// This plays the bytes from the file, DO NOT use for mp3s, etc
-    /// ffmpeg should be used instead.
+    /// `Source.decode` or ffmpeg should be used instead.
     #[staticmethod]
     fn file<'p>(filepath: String, stereo: bool) -> PyResult<Self> {
         match File::open(filepath) {
@@ -100,11 +278,193 @@ impl PySource {
         }
     }
 
+    /// Decodes a WAV, FLAC, MP3, Ogg Vorbis or Ogg Opus file in this process instead of
+    /// starting ffmpeg, so short clips start playing right away. The format is detected from
+    /// the file's contents. Seeking is supported.
+    ///
+    /// .. code-block:: python
+    ///
+    ///     await driver.play(Source.decode("song.mp3"))
+    ///
+    /// Raises
+    /// ------
+    /// CouldNotOpenFileError
+    ///     The file could not be opened or its format is not supported.
+    #[staticmethod]
+    #[pyo3(text_signature = "(filepath: str)")]
+    fn decode(filepath: String) -> PyResult<Self> {
+        match decoder::decode(filepath) {
+            Ok(res) => Ok(Self::from(res)),
+            Err(err) => Err(CouldNotOpenFileError::new_err(format!("{:?}", err))),
+        }
+    }
+
+    /// Create a source from raw PCM in any sample format, sample rate and number of
+    /// channels.
+    ///
+    /// .. code-block:: python
+    ///
+    ///     await driver.play(Source.pcm(audio, PcmFormat(SampleFormat.S16LE, 22050, 1)))
+    #[staticmethod]
+    #[pyo3(text_signature = "(bytes: bytes, format: PcmFormat)")]
+    fn pcm(bytes: Vec<u8>, format: &PyPcmFormat) -> Self {
+        Self::from(format.input(Box::new(Cursor::new(bytes))))
+    }
+
+    /// Create a source from a file of raw PCM in any sample format, sample rate and number of
+    /// channels. See ``Source.pcm``.
+    #[staticmethod]
+    #[pyo3(text_signature = "(filepath: str, format: PcmFormat)")]
+    fn pcm_file(filepath: String, format: &PyPcmFormat) -> PyResult<Self> {
+        match File::open(filepath) {
+            Ok(res) => Ok(Self::from(format.input(Box::new(res)))),
+            Err(err) => Err(CouldNotOpenFileError::new_err(format!("{:?}", err))),
+        }
+    }
+
+    /// Create a source that reads raw PCM from a Python object while it plays, without
+    /// loading all of it first. The audio is 48kHz float PCM, stereo unless `stereo` is
+    /// ``False``. A `format` replaces this and `stereo` is then ignored. `reader` is either a
+    /// file-like object with ``read(size)``, or an async iterator of bytes, which has to be
+    /// created in the running event loop.
+    ///
+    /// Up to `buffer_size` bytes are read ahead on a separate thread. Seeking the track
+    /// calls ``reader.seek`` if `seekable` is ``True``.
+    ///
+    /// .. code-block:: python
+    ///
+    ///     async def chunks():
+    ///         async for chunk in response.content.iter_chunked(4096):
+    ///             yield chunk
+    ///
+    ///     await driver.play(Source.from_reader(chunks()))
+    ///
+    /// Raises
+    /// ------
+    /// TypeError
+    ///     `reader` has no ``read()`` and is not an async iterator.
+    /// ValueError
+    ///     `seekable` is ``True`` but `reader` can not seek.
+    #[staticmethod]
+    #[args(
+        stereo = "true",
+        seekable = "false",
+        buffer_size = "1048576",
+        format = "None"
+    )]
+    #[pyo3(
+        text_signature = "(reader: Union[BinaryIO, AsyncIterator[bytes]], stereo: bool, seekable: bool, buffer_size: int, format: Optional[PcmFormat])"
+    )]
+    fn from_reader(
+        py: Python,
+        reader: &PyAny,
+        stereo: bool,
+        seekable: bool,
+        buffer_size: usize,
+        format: Option<&PyPcmFormat>,
+    ) -> PyResult<Self> {
+        let reader = Box::new(PyReader::new(py, reader, seekable, buffer_size)?);
+        Ok(Self::from(match format {
+            Some(format) => format.input(reader),
+            None => Input::float_pcm(stereo, Reader::Extension(reader)),
+        }))
+    }
+
+    /// Plays an Ogg Opus file, such as the ``.opus`` files made by ``opusenc``.
+    ///
+    /// If every packet in the file is 20 ms long, its Opus packets are sent to Discord as they
+    /// are while the track is the only one playing and its volume is ``1.0``, which saves
+    /// decoding and re-encoding them. Otherwise the file is decoded like any other source.
+    ///
+    /// The pre-skip and output gain in the file's header are ignored.
+    ///
+    /// .. code-block:: python
+    ///
+    ///     await driver.play(Source.opus_file("airhorn.opus"))
+    ///
+    /// Raises
+    /// ------
+    /// CouldNotOpenFileError
+    ///     The file could not be opened or is not a mono or stereo Ogg Opus file.
+    #[staticmethod]
+    #[pyo3(text_signature = "(filepath: str)")]
+    fn opus_file(filepath: String) -> PyResult<Self> {
+        match opus::ogg_opus(filepath) {
+            Ok(res) => Ok(Self::from(res)),
+            Err(err) => Err(CouldNotOpenFileError::new_err(format!("{:?}", err))),
+        }
+    }
+
+    /// Plays a DCA file. Both DCA1 and headerless DCA0 files are supported.
+    ///
+    /// Like with ``Source.opus_file``, the Opus frames are sent to Discord without being
+    /// re-encoded while the track plays alone at volume ``1.0``.
+    ///
+    /// .. code-block:: python
+    ///
+    ///     await driver.play(await Source.dca("airhorn.dca"))
+    ///
+    /// Raises
+    /// ------
+    /// CouldNotOpenFileError
+    ///     The file could not be opened or is not a DCA file.
+    #[staticmethod]
+    #[pyo3(text_signature = "(filepath: str)")]
+    fn dca(py: Python, filepath: String) -> PyResult<&PyAny> {
+        pyo3_asyncio::tokio::future_into_py(py, async move {
+            match songbird::input::dca(&filepath).await {
+                Ok(mut res) => {
+                    // The Opus decoder always produces stereo, even for mono files.
+                    res.stereo = true;
+                    Ok(Self::from(res))
+                }
+                Err(DcaError::InvalidHeader) => match opus::dca0(&filepath) {
+                    Ok(res) => Ok(Self::from(res)),
+                    Err(err) => Err(CouldNotOpenFileError::new_err(format!("{:?}", err))),
+                },
+                Err(err) => Err(CouldNotOpenFileError::new_err(format!("{:?}", err))),
+            }
+        })
+    }
+
     /// Function used to play most audio formats
     ///
     /// .. code-block:: python
     ///
     ///     await driver.play(Source.ffmpeg("song.mp3"))
+    ///
+    /// Besides files, anything ffmpeg can open works, such as ``http(s)``, ``rtmp``, Icecast
+    /// and HLS URLs. These keyword arguments help with network inputs:
+    ///
+    /// * `headers` A dict of HTTP headers to send.
+    /// * `reconnect` Reconnect to HTTP(S) inputs when the connection drops.
+    /// * `timeout` Give up if no data was received for this many seconds, 30 by default. The
+    ///   source can not be created until ffmpeg has read from the URL, so this is also how
+    ///   long awaiting it can take when the server does not respond.
+    ///
+    /// .. code-block:: python
+    ///
+    ///     # python -m http.server serves the current directory on port 8000
+    ///     await driver.play(await Source.ffmpeg(
+    ///         "http://localhost:8000/song.mp3",
+    ///         headers={"Authorization": "Bearer token"},
+    ///         reconnect=True,
+    ///         timeout=10,
+    ///     ))
+    ///
+    /// `headers`, `reconnect` and `timeout` are ignored for files, and `-rw_timeout` in
+    /// `pre_input_args` replaces `timeout`. URLs are not probed with ffprobe first and are
+    /// always played as stereo.
+    ///
+    /// Raises
+    /// ------
+    /// FileNotFoundError
+    ///     `filepath` is neither a file nor a URL.
+    /// ValueError
+    ///     `timeout` is not a positive number.
+    /// FfmpegError
+    ///     ffmpeg could not be started or could not open the input, for example because the
+    ///     URL gives a 404 or the server can not be reached.
     #[staticmethod]
     #[args(kwargs = "**")]
     fn ffmpeg<'a, 'p>(
@@ -112,31 +472,45 @@ impl PySource {
         filepath: String,
         kwargs: Option<&'a PyDict>,
     ) -> PyResult<&'p PyAny> {
-        let pre_input_args: Vec<String>;
+        let exists = Path::new(&filepath).exists();
+        let url = !exists && is_url(&filepath);
+        // Checked for files too, so that a bad value is not silently ignored.
+        let network_args = network_args(kwargs)?;
+        let mut pre_input_args: Vec<String> = if url { network_args } else { vec![] };
         let args: Vec<String>;
 
         if let Some(kwargs) = kwargs {
             let _pre_input_args = kwargs.get_item("pre_input_args");
             let _args = kwargs.get_item("args");
 
-            pre_input_args = map_args(_pre_input_args)?;
+            pre_input_args.extend(map_args(_pre_input_args)?);
             args = map_args(_args)?;
         } else {
-            pre_input_args = vec![];
             args = vec![];
         };
 
         pyo3_asyncio::tokio::future_into_py(py, async move {
-            let pre_input_args: Vec<&str> = pre_input_args.iter().map(String::as_str).collect();
-            let args: Vec<&str> = args.iter().map(String::as_str).collect();
-
-            if !std::path::Path::new(&filepath).exists() {
+            if !exists && !url {
                 return Err(builtins::FileNotFoundError::new_err(format!(
                     "File `{}` does not exist",
                     filepath
                 )));
             };
 
+            if url {
+                let res = pyo3_asyncio::tokio::get_runtime()
+                    .spawn_blocking(move || ffmpeg_stereo(&filepath, &pre_input_args, &args))
+                    .await;
+                return match res {
+                    Ok(Ok(res)) => Ok(Self::from(res)),
+                    Ok(Err(err)) => Err(FfmpegError::new_err(format!("{:?}", err))),
+                    Err(err) => Err(FfmpegError::new_err(format!("{:?}", err))),
+                };
+            }
+
+            let pre_input_args: Vec<&str> = pre_input_args.iter().map(String::as_str).collect();
+            let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
             match if pre_input_args.is_empty() && args.is_empty() {
                 songbird::ffmpeg(filepath).await
             } else {
@@ -168,3 +542,175 @@ impl PySource {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::net::TcpListener;
+
+    use super::*;
+
+    fn has_ffmpeg() -> bool {
+        Command::new("ffmpeg").arg("-version").output().is_ok()
+    }
+
+    /// Serves `status` and `body` for every request and returns the base URL.
+    fn serve(status: &'static str, body: Vec<u8>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while let Ok(len @ 1..) = stream.read(&mut buf) {
+                    request.extend_from_slice(&buf[..len]);
+                    if request.ends_with(b"\r\n\r\n") {
+                        break;
+                    }
+                }
+                let head = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    body.len()
+                );
+                let _ = stream.write_all(head.as_bytes());
+                let _ = stream.write_all(&body);
+            }
+        });
+        url
+    }
+
+    /// A second of a 16 bit 48kHz stereo WAV file.
+    fn wav() -> Vec<u8> {
+        let samples: Vec<i16> = (0..96_000).map(|i| ((i % 200) * 100) as i16).collect();
+        let len = samples.len() as u32 * 2;
+        let mut wav = Vec::new();
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + len).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&48_000u32.to_le_bytes());
+        wav.extend_from_slice(&(48_000u32 * 4).to_le_bytes());
+        wav.extend_from_slice(&4u16.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&len.to_le_bytes());
+        samples
+            .iter()
+            .for_each(|sample| wav.extend_from_slice(&sample.to_le_bytes()));
+        wav
+    }
+
+    #[test]
+    fn urls() {
+        assert!(is_url("https://example.com/song.mp3"));
+        assert!(is_url("rtmp://example.com/live"));
+        assert!(is_url("pipe:0"));
+        assert!(!is_url("C:\\music\\song.mp3"));
+        assert!(!is_url("song.mp3"));
+        assert!(!is_url("a:b.mp3"));
+        assert!(!is_url("song:remix.mp3"));
+    }
+
+    #[test]
+    fn missing_files_are_not_found() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let module = PyModule::from_code(
+                py,
+                "import asyncio\n\
+                 def play(path):\n    \
+                     async def main():\n        \
+                         await Source.ffmpeg(path)\n    \
+                     asyncio.run(main())\n",
+                "missing.py",
+                "missing",
+            )
+            .unwrap();
+            module.add_class::<PySource>().unwrap();
+            for path in ["a:b.mp3", "song:remix.mp3", "songbird-py-missing.mp3"] {
+                let err = module.getattr("play").unwrap().call1((path,)).unwrap_err();
+                assert!(
+                    err.is_instance_of::<builtins::FileNotFoundError>(py),
+                    "{}: {}",
+                    path,
+                    err
+                );
+            }
+        });
+    }
+
+    #[test]
+    fn timeout_must_be_positive() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let args = |timeout: f64| {
+                let kwargs = PyDict::new(py);
+                kwargs.set_item("timeout", timeout).unwrap();
+                network_args(Some(kwargs))
+            };
+            assert_eq!(args(2.5).unwrap(), ["-rw_timeout", "2500000"]);
+            // URLs time out even without a `timeout`.
+            assert_eq!(network_args(None).unwrap(), ["-rw_timeout", "30000000"]);
+            let kwargs = PyDict::new(py);
+            kwargs.set_item("timeout", py.None()).unwrap();
+            assert_eq!(
+                network_args(Some(kwargs)).unwrap(),
+                ["-rw_timeout", "30000000"]
+            );
+            for timeout in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+                assert!(args(timeout)
+                    .unwrap_err()
+                    .is_instance_of::<PyValueError>(py));
+            }
+        });
+    }
+
+    #[test]
+    fn plays_url() {
+        if !has_ffmpeg() {
+            return;
+        }
+        let url = serve("200 OK", wav()) + "/song.wav";
+        let mut input = ffmpeg_stereo(&url, &[], &[]).unwrap();
+        let mut audio = Vec::new();
+        input.reader.read_to_end(&mut audio).unwrap();
+        assert_eq!(audio.len(), 96_000 * 4);
+    }
+
+    #[test]
+    fn missing_url_is_an_error() {
+        if !has_ffmpeg() {
+            return;
+        }
+        let url = serve("404 Not Found", b"Not Found".to_vec()) + "/missing.wav";
+        let err = format!("{:?}", ffmpeg_stereo(&url, &[], &[]).unwrap_err());
+        assert!(err.contains("404"), "{}", err);
+    }
+
+    #[test]
+    fn unreachable_url_is_an_error() {
+        if !has_ffmpeg() {
+            return;
+        }
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/song.wav", listener.local_addr().unwrap());
+        drop(listener);
+        assert!(ffmpeg_stereo(&url, &[], &[]).is_err());
+    }
+
+    #[test]
+    fn stalled_url_times_out() {
+        if !has_ffmpeg() {
+            return;
+        }
+        // The server accepts the connection and never answers.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/song.wav", listener.local_addr().unwrap());
+        let args = ["-rw_timeout".to_string(), "500000".to_string()];
+        assert!(ffmpeg_stereo(&url, &args, &[]).is_err());
+        drop(listener);
+    }
+}
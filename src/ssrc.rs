@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use songbird::driver::Driver;
+use songbird::{CoreEvent, Event, EventContext, EventHandler};
+
+/// Maps the SSRCs of other users in the voice channel to their user ids.
+/// Discord only sends the user id of an SSRC in speaking state updates, so the map is
+/// built from those.
+pub struct SsrcMap {
+    users: Mutex<HashMap<u32, u64>>,
+}
+
+impl SsrcMap {
+    pub fn new() -> Self {
+        Self {
+            users: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Registers the events needed to keep the map up to date on a driver.
+    /// This has to be done again if the global events are removed.
+    pub fn register(map: &Arc<Self>, driver: &mut Driver) {
+        for event in [
+            CoreEvent::SpeakingStateUpdate,
+            CoreEvent::ClientDisconnect,
+            CoreEvent::DriverConnect,
+            CoreEvent::DriverReconnect,
+        ] {
+            driver.add_global_event(Event::Core(event), SsrcHandler(map.clone()));
+        }
+    }
+
+    pub fn user_id(&self, ssrc: u32) -> Option<u64> {
+        self.users.lock().unwrap().get(&ssrc).copied()
+    }
+
+    pub fn users(&self) -> HashMap<u32, u64> {
+        self.users.lock().unwrap().clone()
+    }
+}
+
+struct SsrcHandler(Arc<SsrcMap>);
+
+#[async_trait]
+impl EventHandler for SsrcHandler {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        let mut users = self.0.users.lock().unwrap();
+        match ctx {
+            EventContext::SpeakingStateUpdate(speaking) => {
+                if let Some(user_id) = speaking.user_id {
+                    users.insert(speaking.ssrc, user_id.0);
+                }
+            }
+            EventContext::ClientDisconnect(disconnect) => {
+                users.retain(|_, user_id| *user_id != disconnect.user_id.0);
+            }
+            // SSRCs are only valid for a single session.
+            EventContext::DriverConnect(_) | EventContext::DriverReconnect(_) => users.clear(),
+            _ => {}
+        }
+        None
+    }
+}
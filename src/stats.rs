@@ -0,0 +1,621 @@
+use std::collections::HashMap;
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use serde::Serialize;
+use songbird::{CoreEvent, Event, EventContext, EventHandler};
+
+use crate::driver::PyDriver;
+use crate::registry::Registrations;
+use crate::ssrc::SsrcMap;
+use crate::vad::level;
+
+/// Running average of audio levels in dBFS.
+#[derive(Clone, Copy, Default)]
+struct Level {
+    sum: f64,
+    count: u64,
+}
+
+impl Level {
+    fn add(&mut self, level: f32) {
+        if level.is_finite() {
+            self.sum += f64::from(level);
+            self.count += 1;
+        }
+    }
+
+    fn merge(&mut self, other: Level) {
+        self.sum += other.sum;
+        self.count += other.count;
+    }
+
+    fn mean(&self) -> Option<f32> {
+        if self.count == 0 {
+            None
+        } else {
+            Some((self.sum / self.count as f64) as f32)
+        }
+    }
+}
+
+/// Who a speaking interval belongs to. SSRCs are used until the user id is known.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Speaker {
+    User(u64),
+    Ssrc(u32),
+}
+
+/// Someone who is speaking right now.
+struct Active {
+    user_id: Option<u64>,
+    start: Instant,
+    level: Level,
+    overlap_time: f64,
+    overlaps: u64,
+}
+
+#[derive(Clone, Copy, Default)]
+struct Totals {
+    talk_time: f64,
+    utterances: u64,
+    overlap_time: f64,
+    overlaps: u64,
+    level: Level,
+}
+
+impl Totals {
+    fn add(&mut self, active: &Active, talk_time: f64) {
+        self.talk_time += talk_time;
+        self.utterances += 1;
+        self.overlap_time += active.overlap_time;
+        self.overlaps += active.overlaps;
+        self.level.merge(active.level);
+    }
+}
+
+struct State {
+    started: Instant,
+    started_unix: f64,
+    active: HashMap<u32, Active>,
+    intervals: Vec<PySpeakingInterval>,
+    totals: HashMap<Speaker, Totals>,
+    overlap_time: f64,
+    overlaps: u64,
+    /// When the set of people speaking last changed.
+    changed: Instant,
+}
+
+impl State {
+    fn new(now: Instant) -> Self {
+        Self {
+            started: now,
+            started_unix: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0.0, |time| time.as_secs_f64()),
+            active: HashMap::new(),
+            intervals: Vec::new(),
+            totals: HashMap::new(),
+            overlap_time: 0.0,
+            overlaps: 0,
+            changed: now,
+        }
+    }
+
+    fn seconds(&self, instant: Instant) -> f64 {
+        instant
+            .saturating_duration_since(self.started)
+            .as_secs_f64()
+    }
+
+    /// Adds the time since the last change to the overlap of everyone speaking.
+    fn advance(&mut self, now: Instant) {
+        if self.active.len() >= 2 {
+            let elapsed = now.saturating_duration_since(self.changed).as_secs_f64();
+            self.overlap_time += elapsed;
+            for active in self.active.values_mut() {
+                active.overlap_time += elapsed;
+            }
+        }
+        self.changed = now;
+    }
+
+    fn start(&mut self, ssrc: u32, user_id: Option<u64>, now: Instant) {
+        if self.active.contains_key(&ssrc) {
+            return;
+        }
+        self.advance(now);
+
+        let mut overlaps = 0;
+        if !self.active.is_empty() {
+            // Everyone speaking is talked over by the new speaker.
+            overlaps = 1;
+            self.overlaps += 1;
+            self.active
+                .values_mut()
+                .for_each(|active| active.overlaps += 1);
+        }
+
+        self.active.insert(
+            ssrc,
+            Active {
+                user_id,
+                start: now,
+                level: Level::default(),
+                overlap_time: 0.0,
+                overlaps,
+            },
+        );
+    }
+
+    fn stop(&mut self, ssrc: u32, now: Instant) {
+        if !self.active.contains_key(&ssrc) {
+            return;
+        }
+        self.advance(now);
+
+        let active = self.active.remove(&ssrc).unwrap();
+        let interval = PySpeakingInterval::from(self, ssrc, &active, Some(now));
+        self.totals
+            .entry(speaker(ssrc, active.user_id))
+            .or_default()
+            .add(&active, interval.end.unwrap_or_default() - interval.start);
+        self.intervals.push(interval);
+    }
+
+    /// Returns the totals and intervals including the people that are speaking right now.
+    fn snapshot(&self, now: Instant) -> Snapshot {
+        let overlapping = self.active.len() >= 2;
+        let elapsed = now.saturating_duration_since(self.changed).as_secs_f64();
+
+        let mut totals = self.totals.clone();
+        let mut timeline = self.intervals.clone();
+        for (ssrc, active) in &self.active {
+            let interval = PySpeakingInterval::from(self, *ssrc, active, None);
+            let entry = totals.entry(speaker(*ssrc, active.user_id)).or_default();
+            entry.add(active, self.seconds(now) - interval.start);
+            if overlapping {
+                entry.overlap_time += elapsed;
+            }
+            timeline.push(interval);
+        }
+        timeline.sort_by(|a, b| a.start.total_cmp(&b.start));
+
+        let mut users: Vec<PySpeakerStats> = totals
+            .into_iter()
+            .map(|(speaker, totals)| PySpeakerStats::from(speaker, &totals))
+            .collect();
+        users.sort_by(|a, b| b.talk_time.total_cmp(&a.talk_time));
+
+        Snapshot {
+            started: self.started_unix,
+            duration: self.seconds(now),
+            overlap_time: self.overlap_time + if overlapping { elapsed } else { 0.0 },
+            overlaps: self.overlaps,
+            users,
+            timeline,
+        }
+    }
+
+    /// Clears the stats and counts the people who are speaking as if they started at `now`.
+    fn reset(&mut self, now: Instant) {
+        let active = mem::take(&mut self.active);
+        *self = State::new(now);
+        for (ssrc, active) in active {
+            self.start(ssrc, active.user_id, now);
+        }
+    }
+}
+
+fn speaker(ssrc: u32, user_id: Option<u64>) -> Speaker {
+    user_id.map_or(Speaker::Ssrc(ssrc), Speaker::User)
+}
+
+#[derive(Serialize)]
+struct Snapshot {
+    started: f64,
+    duration: f64,
+    overlap_time: f64,
+    overlaps: u64,
+    users: Vec<PySpeakerStats>,
+    timeline: Vec<PySpeakingInterval>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    ssrcs: Arc<SsrcMap>,
+    closed: AtomicBool,
+    registrations: Registrations,
+}
+
+struct StatsHandler(Arc<Shared>);
+
+#[async_trait]
+impl EventHandler for StatsHandler {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        let shared = &self.0;
+        if shared.closed.load(Ordering::Acquire) {
+            return Some(Event::Cancel);
+        }
+
+        let mut state = shared.state.lock().unwrap();
+        let now = Instant::now();
+        match ctx {
+            EventContext::SpeakingUpdate(data) if data.speaking => {
+                state.start(data.ssrc, shared.ssrcs.user_id(data.ssrc), now);
+            }
+            EventContext::SpeakingUpdate(data) => state.stop(data.ssrc, now),
+            EventContext::VoicePacket(data) => {
+                if let Some(active) = state.active.get_mut(&data.packet.ssrc) {
+                    if let Some(audio) = data.audio {
+                        active.level.add(level(audio));
+                    }
+                    // The user id can arrive after someone started speaking.
+                    if active.user_id.is_none() {
+                        active.user_id = shared.ssrcs.user_id(data.packet.ssrc);
+                    }
+                }
+            }
+            EventContext::ClientDisconnect(disconnect) => {
+                let ssrcs: Vec<u32> = state
+                    .active
+                    .iter()
+                    .filter(|(_, active)| active.user_id == Some(disconnect.user_id.0))
+                    .map(|(ssrc, _)| *ssrc)
+                    .collect();
+                ssrcs.into_iter().for_each(|ssrc| state.stop(ssrc, now));
+            }
+            EventContext::DriverDisconnect(_) => {
+                let ssrcs: Vec<u32> = state.active.keys().copied().collect();
+                ssrcs.into_iter().for_each(|ssrc| state.stop(ssrc, now));
+            }
+            _ => {}
+        }
+        None
+    }
+}
+
+/// The totals of one user, see ``SpeakingStats``.
+#[pyclass(name = "SpeakerStats")]
+#[derive(Clone, Serialize)]
+pub struct PySpeakerStats {
+    /// The user id, if it is known.
+    #[pyo3(get)]
+    pub user_id: Option<u64>,
+    /// The SSRC of the user if their user id is not known.
+    #[pyo3(get)]
+    pub ssrc: Option<u32>,
+    /// Seconds spent speaking.
+    #[pyo3(get)]
+    pub talk_time: f64,
+    /// How many times the user started speaking.
+    #[pyo3(get)]
+    pub utterances: u64,
+    /// Seconds the user spent speaking at the same time as someone else.
+    #[pyo3(get)]
+    pub overlap_time: f64,
+    /// How many times the user started speaking over someone, or someone started speaking
+    /// over them.
+    #[pyo3(get)]
+    pub overlaps: u64,
+    /// The average level of the user's audio in dBFS. This is only known if the driver
+    /// decodes audio.
+    #[pyo3(get)]
+    pub level: Option<f32>,
+}
+
+impl PySpeakerStats {
+    fn from(speaker: Speaker, totals: &Totals) -> Self {
+        let (user_id, ssrc) = match speaker {
+            Speaker::User(user_id) => (Some(user_id), None),
+            Speaker::Ssrc(ssrc) => (None, Some(ssrc)),
+        };
+        Self {
+            user_id,
+            ssrc,
+            talk_time: totals.talk_time,
+            utterances: totals.utterances,
+            overlap_time: totals.overlap_time,
+            overlaps: totals.overlaps,
+            level: totals.level.mean(),
+        }
+    }
+}
+
+/// A time when a user was speaking.
+/// `start` and `end` are seconds since the stats were started or reset.
+#[pyclass(name = "SpeakingInterval")]
+#[derive(Clone, Serialize)]
+pub struct PySpeakingInterval {
+    #[pyo3(get)]
+    pub user_id: Option<u64>,
+    #[pyo3(get)]
+    pub ssrc: u32,
+    #[pyo3(get)]
+    pub start: f64,
+    /// :data:`None` if the user is still speaking.
+    #[pyo3(get)]
+    pub end: Option<f64>,
+    /// The average level of the audio in dBFS, if the driver decodes audio.
+    #[pyo3(get)]
+    pub level: Option<f32>,
+}
+
+impl PySpeakingInterval {
+    fn from(state: &State, ssrc: u32, active: &Active, end: Option<Instant>) -> Self {
+        Self {
+            user_id: active.user_id,
+            ssrc,
+            start: state.seconds(active.start),
+            end: end.map(|end| state.seconds(end)),
+            level: active.level.mean(),
+        }
+    }
+}
+
+/// Keeps track of who is speaking in a voice channel, for how long and over whom.
+///
+/// Speaking is detected by the driver from the packets users send, so stats are collected
+/// as long as the driver receives audio. Audio levels are only measured when the driver's
+/// ``DecodeMode`` is ``Decode``. The handlers are removed from the driver when the stats are
+/// dropped.
+///
+/// .. code-block:: python
+///
+///     stats = SpeakingStats(driver)
+///     ...
+///     for user in stats.users:
+///         print(user.user_id, user.talk_time, user.overlaps)
+///     save(stats.to_json())
+#[pyclass(name = "SpeakingStats")]
+#[pyo3(text_signature = "(driver: Driver)")]
+pub struct PySpeakingStats {
+    shared: Arc<Shared>,
+}
+
+#[pymethods]
+impl PySpeakingStats {
+    #[new]
+    fn new(driver: &PyDriver) -> Self {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State::new(Instant::now())),
+            ssrcs: driver.ssrcs(),
+            closed: AtomicBool::new(false),
+            registrations: Registrations::new(),
+        });
+
+        let handlers: Vec<_> = [
+            CoreEvent::SpeakingUpdate,
+            CoreEvent::VoicePacket,
+            CoreEvent::ClientDisconnect,
+            CoreEvent::DriverDisconnect,
+        ]
+        .into_iter()
+        .map(|event| (Event::Core(event), StatsHandler(shared.clone())))
+        .collect();
+
+        let registry = driver.registry();
+        let driver = driver.driver();
+        let registered = shared.clone();
+        pyo3_asyncio::tokio::get_runtime().spawn(async move {
+            let mut driver = driver.lock().await;
+            for (event, handler) in handlers {
+                let id = registry.add(&mut driver, event, handler);
+                registered.registrations.push(&registry, id);
+            }
+        });
+
+        Self { shared }
+    }
+
+    /// The unix time when the stats were started or last reset.
+    #[getter]
+    fn started(&self) -> f64 {
+        self.shared.state.lock().unwrap().started_unix
+    }
+
+    /// Seconds since the stats were started or last reset.
+    #[getter]
+    fn duration(&self) -> f64 {
+        let state = self.shared.state.lock().unwrap();
+        state.seconds(Instant::now())
+    }
+
+    /// The stats of every user that spoke, the most talkative first.
+    #[getter]
+    fn users(&self) -> Vec<PySpeakerStats> {
+        self.snapshot().users
+    }
+
+    /// The stats of a user, or :data:`None` if they have not spoken.
+    #[pyo3(text_signature = "($self, user_id: int) -> Optional[SpeakerStats]")]
+    fn user(&self, user_id: u64) -> Option<PySpeakerStats> {
+        self.snapshot()
+            .users
+            .into_iter()
+            .find(|user| user.user_id == Some(user_id))
+    }
+
+    /// Every time someone spoke, in the order they started.
+    #[getter]
+    fn timeline(&self) -> Vec<PySpeakingInterval> {
+        self.snapshot().timeline
+    }
+
+    /// Seconds during which at least two people were speaking.
+    #[getter]
+    fn overlap_time(&self) -> f64 {
+        self.snapshot().overlap_time
+    }
+
+    /// How many times someone started speaking while someone else was already speaking.
+    #[getter]
+    fn overlaps(&self) -> u64 {
+        self.shared.state.lock().unwrap().overlaps
+    }
+
+    /// Clears the stats and starts counting again from now. People who are speaking are
+    /// counted as if they started speaking now.
+    #[pyo3(text_signature = "($self)")]
+    fn reset(&self) {
+        self.shared.state.lock().unwrap().reset(Instant::now());
+    }
+
+    /// Returns all of the stats and the timeline as a JSON string.
+    #[pyo3(text_signature = "($self) -> str")]
+    fn to_json(&self) -> PyResult<String> {
+        serde_json::to_string(&self.snapshot())
+            .map_err(|err| PyValueError::new_err(err.to_string()))
+    }
+}
+
+impl PySpeakingStats {
+    fn snapshot(&self) -> Snapshot {
+        self.shared.state.lock().unwrap().snapshot(Instant::now())
+    }
+}
+
+impl Drop for PySpeakingStats {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Release);
+        self.shared.registrations.remove();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    /// A state started at `start`, so `at(start, seconds)` is `seconds` into the stats.
+    fn state() -> (State, Instant) {
+        let start = Instant::now();
+        (State::new(start), start)
+    }
+
+    fn at(start: Instant, seconds: u64) -> Instant {
+        start + Duration::from_secs(seconds)
+    }
+
+    fn user(snapshot: &Snapshot, user_id: u64) -> &PySpeakerStats {
+        let user = snapshot
+            .users
+            .iter()
+            .find(|user| user.user_id == Some(user_id));
+        user.unwrap()
+    }
+
+    #[test]
+    fn talk_time() {
+        let (mut state, start) = state();
+        state.start(1, Some(1), at(start, 0));
+        state.stop(1, at(start, 2));
+        state.start(1, Some(1), at(start, 3));
+        state.stop(1, at(start, 4));
+
+        let snapshot = state.snapshot(at(start, 5));
+        assert_eq!(snapshot.duration, 5.0);
+        assert_eq!(snapshot.overlaps, 0);
+        assert_eq!(snapshot.overlap_time, 0.0);
+        let user = user(&snapshot, 1);
+        assert_eq!(user.talk_time, 3.0);
+        assert_eq!(user.utterances, 2);
+        assert_eq!(user.overlap_time, 0.0);
+        let timeline: Vec<_> = snapshot.timeline.iter().map(|i| (i.start, i.end)).collect();
+        assert_eq!(timeline, [(0.0, Some(2.0)), (3.0, Some(4.0))]);
+    }
+
+    #[test]
+    fn two_speakers() {
+        let (mut state, start) = state();
+        state.start(1, Some(1), at(start, 0));
+        state.start(2, Some(2), at(start, 1));
+        state.stop(2, at(start, 3));
+        state.stop(1, at(start, 4));
+
+        let snapshot = state.snapshot(at(start, 4));
+        assert_eq!(snapshot.overlaps, 1);
+        assert_eq!(snapshot.overlap_time, 2.0);
+        for (user_id, talk_time) in [(1, 4.0), (2, 2.0)] {
+            let user = user(&snapshot, user_id);
+            assert_eq!(user.talk_time, talk_time);
+            assert_eq!(user.overlaps, 1);
+            assert_eq!(user.overlap_time, 2.0);
+        }
+    }
+
+    #[test]
+    fn three_speakers() {
+        let (mut state, start) = state();
+        state.start(1, Some(1), at(start, 0));
+        state.start(2, Some(2), at(start, 1));
+        state.start(3, Some(3), at(start, 2));
+        state.stop(3, at(start, 3));
+        state.stop(2, at(start, 5));
+        state.stop(1, at(start, 6));
+
+        // Both the second and the third speaker talk over someone.
+        let snapshot = state.snapshot(at(start, 6));
+        assert_eq!(snapshot.overlaps, 2);
+        assert_eq!(snapshot.overlap_time, 4.0);
+        for (user_id, overlaps, overlap_time) in [(1, 2, 4.0), (2, 2, 4.0), (3, 1, 1.0)] {
+            let user = user(&snapshot, user_id);
+            assert_eq!(user.overlaps, overlaps, "overlaps of {}", user_id);
+            assert_eq!(
+                user.overlap_time, overlap_time,
+                "overlap time of {}",
+                user_id
+            );
+        }
+    }
+
+    #[test]
+    fn snapshot_while_speaking() {
+        let (mut state, start) = state();
+        state.start(1, Some(1), at(start, 0));
+        state.start(2, None, at(start, 1));
+
+        let snapshot = state.snapshot(at(start, 3));
+        assert_eq!(snapshot.overlap_time, 2.0);
+        assert_eq!(user(&snapshot, 1).talk_time, 3.0);
+        assert_eq!(user(&snapshot, 1).overlap_time, 2.0);
+        let ssrc = snapshot
+            .users
+            .iter()
+            .find(|user| user.ssrc == Some(2))
+            .unwrap();
+        assert_eq!(ssrc.talk_time, 2.0);
+        let timeline: Vec<_> = snapshot.timeline.iter().map(|i| (i.start, i.end)).collect();
+        assert_eq!(timeline, [(0.0, None), (1.0, None)]);
+
+        // Taking a snapshot does not change the state.
+        let snapshot = state.snapshot(at(start, 4));
+        assert_eq!(snapshot.overlap_time, 3.0);
+        assert_eq!(user(&snapshot, 1).talk_time, 4.0);
+    }
+
+    #[test]
+    fn reset_while_speaking() {
+        let (mut state, start) = state();
+        state.start(1, Some(1), at(start, 0));
+        state.start(2, Some(2), at(start, 1));
+        state.stop(2, at(start, 2));
+        state.start(3, Some(3), at(start, 3));
+        state.reset(at(start, 10));
+
+        let snapshot = state.snapshot(at(start, 11));
+        assert_eq!(snapshot.duration, 1.0);
+        assert_eq!(snapshot.overlaps, 1);
+        assert_eq!(snapshot.overlap_time, 1.0);
+        assert_eq!(snapshot.users.len(), 2);
+        assert!(snapshot.users.iter().all(|user| user.talk_time == 1.0));
+        let timeline: Vec<_> = snapshot.timeline.iter().map(|i| (i.start, i.end)).collect();
+        assert_eq!(timeline, [(0.0, None), (0.0, None)]);
+    }
+}
@@ -0,0 +1,427 @@
+use std::future::{poll_fn, Future};
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::Poll;
+
+use async_trait::async_trait;
+use flume::{Receiver, Sender, TrySendError};
+use pyo3::basic::CompareOp;
+use pyo3::exceptions::{PyStopAsyncIteration, PyTypeError};
+use pyo3::prelude::*;
+use songbird::driver::Driver;
+use songbird::{Event, EventContext, EventHandler};
+use tokio::sync::Notify;
+
+use crate::event::{Payload, PyEvent};
+use crate::registry::{Registrations, Registry};
+use crate::ssrc::SsrcMap;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Overflow {
+    DropOldest,
+    DropNewest,
+    Block,
+}
+
+/// What an ``EventStream`` does with a new event when it is full.
+#[pyclass(name = "OverflowPolicy")]
+#[derive(Clone)]
+pub struct PyOverflowPolicy {
+    overflow: Overflow,
+}
+
+impl PyOverflowPolicy {
+    fn from(overflow: Overflow) -> Self {
+        Self { overflow }
+    }
+}
+
+#[allow(non_snake_case)]
+#[pymethods]
+impl PyOverflowPolicy {
+    #[classattr]
+    /// Remove the oldest event in the stream to make room for the new one.
+    fn DropOldest() -> Self {
+        Self::from(Overflow::DropOldest)
+    }
+    #[classattr]
+    /// Discard the new event.
+    fn DropNewest() -> Self {
+        Self::from(Overflow::DropNewest)
+    }
+    #[classattr]
+    /// Wait until there is room. No events are lost, but the driver's other events are held
+    /// up until the stream is read.
+    fn Block() -> Self {
+        Self::from(Overflow::Block)
+    }
+
+    fn __str__(&self) -> &str {
+        match self.overflow {
+            Overflow::DropOldest => "<OverflowPolicy.DropOldest>",
+            Overflow::DropNewest => "<OverflowPolicy.DropNewest>",
+            Overflow::Block => "<OverflowPolicy.Block>",
+        }
+    }
+
+    fn __richcmp__(&self, other: Self, op: CompareOp) -> PyResult<PyObject> {
+        Python::with_gil(|py| match op {
+            CompareOp::Eq => PyResult::Ok((self.overflow == other.overflow).into_py(py)),
+            _ => PyResult::Err(PyTypeError::new_err(
+                "Only __eq__ is implemented for this type",
+            )),
+        })
+    }
+}
+
+/// An event in a stream. `kind` is only set for tagged streams.
+struct Item {
+    kind: Option<Event>,
+    payload: PyResult<Payload>,
+}
+
+impl Item {
+    fn into_py(self, py: Python) -> PyResult<PyObject> {
+        let payload = self.payload?.into_py(py);
+        Ok(match self.kind {
+            Some(event) => (PyEvent { event }, payload).into_py(py),
+            None => payload,
+        })
+    }
+}
+
+/// State shared between a stream and the event handlers feeding it.
+/// `None` is sent when the stream is closed to wake up a pending read.
+struct Shared {
+    tx: Sender<Option<Item>>,
+    rx: Receiver<Option<Item>>,
+    overflow: Overflow,
+    lost: AtomicU64,
+    closed: AtomicBool,
+    /// Wakes up handlers waiting on a full stream when it is closed.
+    closing: Notify,
+    /// The handlers feeding the stream, which are removed when it is closed.
+    registrations: Registrations,
+}
+
+impl Shared {
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+
+    /// Adds an item to the stream. Returns `false` if the stream is or has been closed.
+    async fn push(&self, item: Item) -> bool {
+        if self.is_closed() {
+            return false;
+        }
+
+        let mut item = Some(item);
+        match self.overflow {
+            Overflow::Block => {
+                // `closing` has to be created before `closed` is checked again, or a close in
+                // between would not wake it up.
+                let mut closing = pin!(self.closing.notified());
+                if self.is_closed() {
+                    return false;
+                }
+                let mut send = pin!(self.tx.send_async(item));
+                poll_fn(|cx| {
+                    if send.as_mut().poll(cx).is_ready() || closing.as_mut().poll(cx).is_ready() {
+                        Poll::Ready(())
+                    } else {
+                        Poll::Pending
+                    }
+                })
+                .await;
+            }
+            Overflow::DropNewest => {
+                if self.tx.try_send(item).is_err() {
+                    self.lost.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            Overflow::DropOldest => {
+                while let Err(TrySendError::Full(rejected)) = self.tx.try_send(item) {
+                    item = rejected;
+                    if self.rx.try_recv().is_ok() {
+                        self.lost.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            }
+        }
+        !self.is_closed()
+    }
+}
+
+/// Feeds the events it receives into an ``EventStream``.
+pub struct StreamHandler {
+    shared: Arc<Shared>,
+    event: Event,
+    kind: Option<Event>,
+    ssrcs: Arc<SsrcMap>,
+}
+
+impl StreamHandler {
+    /// Adds the handler to `driver` through `registry`, so it is removed when the stream is
+    /// closed.
+    pub fn register(self, registry: &Arc<Registry>, driver: &mut Driver) {
+        let shared = self.shared.clone();
+        let id = registry.add(driver, self.event, self);
+        shared.registrations.push(registry, id);
+    }
+}
+
+#[async_trait]
+impl EventHandler for StreamHandler {
+    async fn act(&self, ctx: &EventContext<'_>) -> Option<Event> {
+        if self.shared.is_closed() {
+            return Some(Event::Cancel);
+        }
+        let item = Item {
+            kind: self.kind,
+            payload: Payload::from(ctx, Some(&self.ssrcs)),
+        };
+
+        if self.shared.push(item).await {
+            None
+        } else {
+            Some(Event::Cancel)
+        }
+    }
+}
+
+/// Feeds items that do not come from driver events into an ``EventStream``.
+pub struct StreamSender {
+    shared: Arc<Shared>,
+}
+
+impl StreamSender {
+    pub fn is_closed(&self) -> bool {
+        self.shared.is_closed()
+    }
+
+    /// Adds `payload` to the stream. Returns `false` once the stream is closed.
+    pub(crate) async fn send(&self, payload: Payload) -> bool {
+        let item = Item {
+            kind: None,
+            payload: Ok(payload),
+        };
+        self.shared.push(item).await
+    }
+}
+
+/// An async iterator over events from a driver.
+///
+/// .. code-block:: python
+///
+///     async for packet in driver.voice_packets():
+///         print(packet.packet.ssrc, packet.audio)
+///
+///     stream = driver.events(Event.SpeakingUpdate, Event.ClientDisconnect)
+///     async for event, data in stream:
+///         ...
+///
+/// Events are buffered in the stream until they are read. The stream stops receiving events
+/// once it is closed or garbage collected.
+#[pyclass(name = "EventStream")]
+pub struct PyEventStream {
+    shared: Arc<Shared>,
+}
+
+impl PyEventStream {
+    pub fn new(capacity: usize, overflow: Option<PyOverflowPolicy>) -> Self {
+        let (tx, rx) = flume::bounded(capacity.max(1));
+        Self {
+            shared: Arc::new(Shared {
+                tx,
+                rx,
+                overflow: overflow.map_or(Overflow::DropOldest, |policy| policy.overflow),
+                lost: AtomicU64::new(0),
+                closed: AtomicBool::new(false),
+                closing: Notify::new(),
+                registrations: Registrations::new(),
+            }),
+        }
+    }
+
+    /// Returns a handler that feeds `event` into this stream.
+    /// Items from a `tagged` handler are `(event, data)` tuples instead of just the data.
+    pub fn handler(&self, event: Event, tagged: bool, ssrcs: Arc<SsrcMap>) -> StreamHandler {
+        StreamHandler {
+            shared: self.shared.clone(),
+            event,
+            kind: if tagged { Some(event) } else { None },
+            ssrcs,
+        }
+    }
+
+    /// Returns a sender for items that are not driver events.
+    pub fn sender(&self) -> StreamSender {
+        StreamSender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+#[pymethods]
+impl PyEventStream {
+    /// The number of events that were dropped because the stream was full.
+    #[getter]
+    fn lost(&self) -> u64 {
+        self.shared.lost.load(Ordering::Relaxed)
+    }
+
+    /// The number of events waiting to be read.
+    fn __len__(&self) -> usize {
+        self.shared.rx.len()
+    }
+
+    /// Stops the stream. Events that have not been read yet are discarded.
+    #[pyo3(text_signature = "($self)")]
+    fn close(&self) {
+        self.shared.closed.store(true, Ordering::Release);
+        self.shared.closing.notify_waiters();
+        self.shared.registrations.remove();
+        self.shared.rx.drain().for_each(drop);
+        // A pending read is woken up by `None` or, if a handler got in first, by its item.
+        let _ = self.shared.tx.try_send(None);
+    }
+
+    fn __aiter__(slf: PyRef<Self>) -> PyRef<Self> {
+        slf
+    }
+
+    fn __anext__(&self, py: Python) -> PyResult<Option<PyObject>> {
+        if self.shared.is_closed() {
+            return Ok(None);
+        }
+
+        let shared = self.shared.clone();
+
+        let next = pyo3_asyncio::tokio::future_into_py(py, async move {
+            match shared.rx.recv_async().await {
+                Ok(Some(item)) if !shared.is_closed() => Python::with_gil(|py| item.into_py(py)),
+                _ => Err(PyStopAsyncIteration::new_err(())),
+            }
+        })?;
+        Ok(Some(next.into()))
+    }
+}
+
+impl Drop for PyEventStream {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use songbird::model::id::UserId;
+    use songbird::model::payload::ClientDisconnect;
+    use songbird::{Config, CoreEvent};
+
+    use super::*;
+
+    const EVENT: Event = Event::Core(CoreEvent::ClientDisconnect);
+
+    fn stream(capacity: usize, overflow: Overflow) -> PyEventStream {
+        PyEventStream::new(capacity, Some(PyOverflowPolicy::from(overflow)))
+    }
+
+    fn send(stream: &PyEventStream, user_id: u64) -> bool {
+        let sender = stream.sender();
+        let runtime = pyo3_asyncio::tokio::get_runtime();
+        runtime.block_on(sender.send(Payload::ClientDisconnect(user_id)))
+    }
+
+    /// Reads everything in the stream. A `None` from `close` is read as 0.
+    fn read(stream: &PyEventStream) -> Vec<u64> {
+        let items = std::iter::from_fn(|| stream.shared.rx.try_recv().ok());
+        items
+            .map(|item| match item.map(|item| item.payload) {
+                Some(Ok(Payload::ClientDisconnect(user_id))) => user_id,
+                None => 0,
+                _ => panic!("unexpected payload"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn drop_oldest() {
+        let stream = stream(2, Overflow::DropOldest);
+        for user_id in 1..=4 {
+            assert!(send(&stream, user_id));
+        }
+        assert_eq!(stream.lost(), 2);
+        assert_eq!(read(&stream), [3, 4]);
+    }
+
+    #[test]
+    fn drop_newest() {
+        let stream = stream(2, Overflow::DropNewest);
+        for user_id in 1..=4 {
+            assert!(send(&stream, user_id));
+        }
+        assert_eq!(stream.lost(), 2);
+        assert_eq!(read(&stream), [1, 2]);
+    }
+
+    #[test]
+    fn block() {
+        let stream = stream(1, Overflow::Block);
+        assert!(send(&stream, 1));
+
+        let sender = stream.sender();
+        let runtime = pyo3_asyncio::tokio::get_runtime();
+        let blocked = runtime.spawn(async move { sender.send(Payload::ClientDisconnect(2)).await });
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(!blocked.is_finished());
+
+        // Reading makes room for the blocked item.
+        assert_eq!(read(&stream), [1, 2]);
+        assert!(runtime.block_on(blocked).unwrap());
+        assert_eq!(stream.lost(), 0);
+    }
+
+    #[test]
+    fn close_wakes_up_a_blocked_handler() {
+        let stream = stream(1, Overflow::Block);
+        assert!(send(&stream, 1));
+
+        let sender = stream.sender();
+        let runtime = pyo3_asyncio::tokio::get_runtime();
+        let blocked = runtime.spawn(async move { sender.send(Payload::ClientDisconnect(2)).await });
+        std::thread::sleep(Duration::from_millis(100));
+
+        stream.close();
+        let sent =
+            runtime.block_on(async { tokio::time::timeout(Duration::from_secs(5), blocked).await });
+        assert!(!sent.unwrap().unwrap());
+        assert_eq!(read(&stream), [0]);
+        assert!(!send(&stream, 3));
+        assert!(read(&stream).is_empty());
+    }
+
+    #[test]
+    fn close_removes_the_handlers() {
+        let runtime = pyo3_asyncio::tokio::get_runtime();
+        let _guard = runtime.enter();
+        let mut driver = Driver::new(Config::default());
+        let registry = Registry::new();
+        let stream = stream(4, Overflow::DropOldest);
+        let handler = stream.handler(EVENT, false, Arc::new(SsrcMap::new()));
+        let ctx = EventContext::ClientDisconnect(ClientDisconnect { user_id: UserId(1) });
+        assert_eq!(runtime.block_on(handler.act(&ctx)), None);
+        stream
+            .handler(EVENT, false, Arc::new(SsrcMap::new()))
+            .register(&registry, &mut driver);
+        assert_eq!(registry.len(EVENT), 1);
+
+        stream.close();
+        assert_eq!(registry.len(EVENT), 0);
+        assert_eq!(runtime.block_on(handler.act(&ctx)), Some(Event::Cancel));
+        assert_eq!(read(&stream), [0]);
+    }
+}
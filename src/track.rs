@@ -6,6 +6,7 @@ use pyo3::prelude::*;
 use songbird::tracks::{Track, TrackHandle};
 use tokio::sync::Mutex;
 
+use crate::seekable::Replay;
 use crate::source::PySource;
 use crate::track_handle::{
     handle_track_result, PyLoopState, PyPlayMode, PyTrackHandle, PyTrackState,
@@ -18,6 +19,7 @@ use crate::track_handle::{
 pub fn py_create_player<'p>(py: Python<'p>, source: &'p mut PySource) -> PyResult<&'p PyAny> {
     source.raise_if_consumed()?;
 
+    let replay = source.replay.clone();
     let source = source.source.clone();
 
     pyo3_asyncio::tokio::future_into_py(py, async move {
@@ -30,6 +32,7 @@ pub fn py_create_player<'p>(py: Python<'p>, source: &'p mut PySource) -> PyResul
             PyTrack {
                 track: Arc::from(Mutex::from(Some(track))),
                 handle: handle.clone(),
+                replay,
             },
             PyTrackHandle::from(handle),
         ))
@@ -48,6 +51,7 @@ pub(crate) fn register(py: Python, m: &PyModule) -> PyResult<()> {
 pub struct PyTrack {
     pub track: Arc<Mutex<Option<Track>>>,
     pub handle: TrackHandle,
+    pub replay: Option<Replay>,
 }
 
 #[pymethods(name = "Track")]
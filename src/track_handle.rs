@@ -7,7 +7,7 @@ use songbird::input::Metadata;
 use songbird::tracks::{LoopState, PlayMode, TrackHandle, TrackResult, TrackState};
 use std::sync::Arc;
 
-use crate::event::{EventHanlder, PyEvent};
+use crate::event::{EventHanlder, PyEvent, PyEventSubscription, Registrar};
 use crate::exceptions::TrackError;
 use crate::utils::unwrap_duration;
 
@@ -282,13 +282,27 @@ impl PyTrackHandle {
         )
     }
     /// Adds an event to the track.
+    /// The handler can return an ``Event`` to reschedule itself or ``Event.Cancel()`` to
+    /// remove itself, see ``Driver.add_event``.
+    ///
+    /// Returns an ``EventSubscription`` that removes the handler.
     #[pyo3(text_signature = "($self)")]
-    fn add_event(&self, py: Python, event: &PyEvent, call: PyObject) -> PyResult<()> {
+    fn add_event(
+        &self,
+        py: Python,
+        event: &PyEvent,
+        call: PyObject,
+    ) -> PyResult<PyEventSubscription> {
         let current_loop = pyo3_asyncio::get_running_loop(py)?;
-        handle_track_result(self.track_handle.add_event(
+        let handler = EventHanlder::new(
+            call,
+            PyObject::from(current_loop),
             event.event,
-            EventHanlder::new(call, PyObject::from(current_loop)),
-        ))
+            Registrar::Track((*self.track_handle).clone()),
+        );
+        let subscription = handler.subscription();
+        handle_track_result(handler.register_track(&self.track_handle))?;
+        Ok(subscription)
     }
     /// Gets the `TrackState` for a track.
     #[pyo3(text_signature = "($self)")]
@@ -1,5 +1,9 @@
 use std::time::Duration;
 
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
 pub fn unwrap_duration(duration: Option<Duration>) -> Option<f64> {
     match duration {
         Some(duration) => Some(duration.as_secs_f64()),
@@ -13,3 +17,74 @@ pub fn unwrap_f64_to_duration(duration: Option<f64>) -> Option<Duration> {
         None => None,
     }
 }
+
+/// Reads a snowflake from a gateway payload. Discord sends ids as strings but
+/// most python libraries convert them to ints, so both are accepted.
+pub fn get_snowflake(payload: &PyDict, key: &str) -> PyResult<Option<u64>> {
+    match payload.get_item(key) {
+        None => Ok(None),
+        Some(value) if value.is_none() => Ok(None),
+        Some(value) => match value.extract::<u64>() {
+            Ok(id) => Ok(Some(id)),
+            Err(_) => match value.extract::<String>()?.parse::<u64>() {
+                Ok(id) => Ok(Some(id)),
+                Err(_) => Err(PyValueError::new_err(format!(
+                    "`{}` is not a valid snowflake",
+                    key
+                ))),
+            },
+        },
+    }
+}
+
+/// Reads an optional string from a gateway payload.
+pub fn get_string(payload: &PyDict, key: &str) -> PyResult<Option<String>> {
+    match payload.get_item(key) {
+        None => Ok(None),
+        Some(value) if value.is_none() => Ok(None),
+        Some(value) => Ok(Some(value.extract::<String>()?)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload<'p>(py: Python<'p>, code: &str) -> &'p PyDict {
+        py.eval(code, None, None).unwrap().downcast().unwrap()
+    }
+
+    #[test]
+    fn snowflakes() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let payload = payload(
+                py,
+                "{'int': 1, 'str': '80351110224678912', 'none': None, 'bad': 'abc', 'neg': -1}",
+            );
+            assert_eq!(get_snowflake(payload, "int").unwrap(), Some(1));
+            assert_eq!(
+                get_snowflake(payload, "str").unwrap(),
+                Some(80351110224678912)
+            );
+            assert_eq!(get_snowflake(payload, "none").unwrap(), None);
+            assert_eq!(get_snowflake(payload, "missing").unwrap(), None);
+            assert!(get_snowflake(payload, "bad")
+                .unwrap_err()
+                .is_instance_of::<PyValueError>(py));
+            assert!(get_snowflake(payload, "neg").is_err());
+        });
+    }
+
+    #[test]
+    fn strings() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let payload = payload(py, "{'token': 'abc', 'none': None, 'int': 1}");
+            assert_eq!(get_string(payload, "token").unwrap().unwrap(), "abc");
+            assert_eq!(get_string(payload, "none").unwrap(), None);
+            assert_eq!(get_string(payload, "missing").unwrap(), None);
+            assert!(get_string(payload, "int").is_err());
+        });
+    }
+}
@@ -0,0 +1,438 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+
+use crate::buffer::pcm_to_py;
+use crate::driver::PyDriver;
+use crate::event::Payload;
+use crate::jitter::{self, Frame, FrameSink};
+use crate::ssrc::SsrcMap;
+use crate::stream::{PyEventStream, PyOverflowPolicy, StreamSender};
+
+const FRAME_LENGTH: f64 = 0.02;
+const SAMPLE_RATE: f64 = 48_000.0;
+
+/// Returns the RMS level of interleaved samples in dBFS.
+pub fn level(samples: &[i16]) -> f32 {
+    if samples.is_empty() {
+        return f32::NEG_INFINITY;
+    }
+    let sum: f64 = samples.iter().map(|s| f64::from(*s).powi(2)).sum();
+    let rms = (sum / samples.len() as f64).sqrt() / 32768.0;
+    (20.0 * rms.log10()) as f32
+}
+
+fn seconds_to_frames(seconds: f64) -> usize {
+    (seconds / FRAME_LENGTH).round().max(0.0) as usize
+}
+
+#[derive(Clone, Copy)]
+struct Thresholds {
+    start: f32,
+    stop: f32,
+}
+
+impl Thresholds {
+    fn new(start: f32, stop: f32) -> PyResult<Self> {
+        if stop > start {
+            return Err(PyValueError::new_err(
+                "The stop threshold can not be above the start threshold.",
+            ));
+        }
+        Ok(Self { start, stop })
+    }
+}
+
+struct Settings {
+    default: Thresholds,
+    users: HashMap<u64, Thresholds>,
+    /// Silent frames after which an utterance ends.
+    hangover: usize,
+    /// Frames of speech an utterance needs before it is reported.
+    min_length: usize,
+    /// Frames after which an utterance is ended even if the user is still speaking.
+    max_length: usize,
+}
+
+/// An utterance that is in progress.
+struct Speaker {
+    timestamp: u32,
+    /// Shared with the utterance that reported the start, so it is only copied if that
+    /// one is still alive when more audio arrives.
+    audio: Arc<Vec<i16>>,
+    /// Frames up to and including the last loud one.
+    length: usize,
+    frames: usize,
+    silent: usize,
+    /// Whether the start has been reported.
+    reported: bool,
+}
+
+/// Runs voice activity detection on the frames of a jitter buffer.
+struct Detector {
+    settings: Arc<Mutex<Settings>>,
+    speakers: HashMap<u32, Speaker>,
+    sender: StreamSender,
+    ssrcs: Arc<SsrcMap>,
+}
+
+impl Detector {
+    fn utterance(
+        &self,
+        ssrc: u32,
+        timestamp: u32,
+        audio: Arc<Vec<i16>>,
+        finished: bool,
+    ) -> PyUtterance {
+        PyUtterance {
+            ssrc,
+            user_id: self.ssrcs.user_id(ssrc),
+            timestamp,
+            finished,
+            audio,
+        }
+    }
+
+    /// Handles 20 ms of a source, where `frame` is `None` if the source sent nothing.
+    fn process(&mut self, ssrc: u32, frame: Option<&Frame>, events: &mut Vec<PyUtterance>) {
+        let settings = self.settings.lock().unwrap();
+        let thresholds = self
+            .ssrcs
+            .user_id(ssrc)
+            .and_then(|user_id| settings.users.get(&user_id))
+            .copied()
+            .unwrap_or(settings.default);
+        let level = frame.map_or(f32::NEG_INFINITY, |frame| level(&frame.audio));
+
+        let speaker = match self.speakers.get_mut(&ssrc) {
+            Some(speaker) => speaker,
+            None if level >= thresholds.start => self.speakers.entry(ssrc).or_insert(Speaker {
+                timestamp: frame.map_or(0, |frame| frame.timestamp),
+                audio: Arc::new(Vec::new()),
+                length: 0,
+                frames: 0,
+                silent: 0,
+                reported: false,
+            }),
+            None => return,
+        };
+
+        if let Some(frame) = frame {
+            Arc::make_mut(&mut speaker.audio).extend_from_slice(&frame.audio);
+        }
+        speaker.frames += 1;
+        if level >= thresholds.stop {
+            speaker.length = speaker.frames;
+            speaker.silent = 0;
+        } else {
+            speaker.silent += 1;
+        }
+
+        let start = !speaker.reported && speaker.length >= settings.min_length;
+        let end =
+            speaker.silent >= settings.hangover.max(1) || speaker.frames >= settings.max_length;
+        drop(settings);
+
+        if start {
+            let speaker = &self.speakers[&ssrc];
+            let utterance = self.utterance(ssrc, speaker.timestamp, speaker.audio.clone(), false);
+            events.push(utterance);
+            self.speakers.get_mut(&ssrc).unwrap().reported = true;
+        }
+        if end {
+            // Utterances that are too short are dropped without being reported. One that is
+            // too long starts over with the next frame that is above the start threshold.
+            let speaker = self.speakers.remove(&ssrc).unwrap();
+            if speaker.reported {
+                events.push(self.utterance(ssrc, speaker.timestamp, speaker.audio, true));
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl FrameSink for Detector {
+    async fn frames(&mut self, frames: Vec<Frame>) -> bool {
+        if self.sender.is_closed() {
+            return false;
+        }
+
+        let mut events = Vec::new();
+        let mut seen = HashSet::new();
+        for frame in &frames {
+            seen.insert(frame.ssrc);
+            self.process(frame.ssrc, Some(frame), &mut events);
+        }
+        // Sources stop sending while they are silent, which counts towards the hangover.
+        let quiet: Vec<u32> = self
+            .speakers
+            .keys()
+            .filter(|ssrc| !seen.contains(ssrc))
+            .copied()
+            .collect();
+        for ssrc in quiet {
+            self.process(ssrc, None, &mut events);
+        }
+
+        for utterance in events {
+            if !self.sender.send(Payload::Utterance(utterance)).await {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// The start or the end of something a user said.
+#[pyclass(name = "Utterance")]
+pub struct PyUtterance {
+    #[pyo3(get)]
+    pub ssrc: u32,
+    /// The user id for `ssrc`, if it is known.
+    #[pyo3(get)]
+    pub user_id: Option<u64>,
+    /// The RTP timestamp of the first sample.
+    #[pyo3(get)]
+    pub timestamp: u32,
+    /// ``False`` when the user started speaking and ``True`` once they stopped.
+    #[pyo3(get)]
+    pub finished: bool,
+    audio: Arc<Vec<i16>>,
+}
+
+#[pymethods]
+impl PyUtterance {
+    /// The audio of the utterance so far as interleaved 48kHz stereo 16 bit samples.
+    /// This is a numpy array of ``int16`` if numpy is installed, otherwise a ``memoryview``.
+    #[getter]
+    fn audio(&self, py: Python) -> PyResult<PyObject> {
+        pcm_to_py(py, self.audio.clone())
+    }
+
+    /// The length of `audio` in seconds.
+    #[getter]
+    fn duration(&self) -> f64 {
+        self.audio.len() as f64 / 2.0 / SAMPLE_RATE
+    }
+}
+
+/// Detects when users start and stop speaking from the loudness of their audio, instead of
+/// the speaking flag their client sends.
+///
+/// A user starts speaking when a 20 ms frame is at least `start_threshold` dBFS loud and
+/// stops once their audio stays below `stop_threshold` for `hangover` seconds. Utterances
+/// shorter than `min_length` seconds are ignored and ones longer than `max_length` seconds
+/// are ended early, so a user who keeps talking gives several utterances. Audio goes
+/// through a jitter buffer with `depth` packets first, see ``Driver.buffered_audio``.
+///
+/// Iterating over the detector gives an ``Utterance`` when a user starts speaking and one
+/// with the whole utterance when they stop.
+///
+/// .. code-block:: python
+///
+///     detector = VoiceActivityDetector(driver)
+///     async for utterance in detector:
+///         if utterance.finished:
+///             text = recognize(utterance.audio)
+///
+/// The driver's ``DecodeMode`` must not be ``Pass``.
+///
+/// Raises
+/// ------
+/// ValueError
+///     `stop_threshold` is above `start_threshold`, or `max_length` is below `min_length`.
+#[pyclass(name = "VoiceActivityDetector")]
+#[pyo3(
+    text_signature = "(driver: Driver, start_threshold: float, stop_threshold: float, hangover: float, min_length: float, max_length: float, depth: int, capacity: int, overflow: Optional[OverflowPolicy])"
+)]
+pub struct PyVoiceActivityDetector {
+    settings: Arc<Mutex<Settings>>,
+    events: Py<PyEventStream>,
+}
+
+#[pymethods]
+impl PyVoiceActivityDetector {
+    #[new]
+    #[args(
+        start_threshold = "-40.0",
+        stop_threshold = "-50.0",
+        hangover = "0.3",
+        min_length = "0.2",
+        max_length = "30.0",
+        depth = "3",
+        capacity = "64",
+        overflow = "None"
+    )]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        py: Python,
+        driver: &PyDriver,
+        start_threshold: f32,
+        stop_threshold: f32,
+        hangover: f64,
+        min_length: f64,
+        max_length: f64,
+        depth: usize,
+        capacity: usize,
+        overflow: Option<PyOverflowPolicy>,
+    ) -> PyResult<Self> {
+        if !(max_length >= min_length && max_length > 0.0) {
+            return Err(PyValueError::new_err(
+                "max_length must be positive and at least min_length.",
+            ));
+        }
+        let settings = Arc::new(Mutex::new(Settings {
+            default: Thresholds::new(start_threshold, stop_threshold)?,
+            users: HashMap::new(),
+            hangover: seconds_to_frames(hangover),
+            min_length: seconds_to_frames(min_length),
+            max_length: seconds_to_frames(max_length).max(1),
+        }));
+
+        let events = PyEventStream::new(capacity, overflow);
+        let detector = Detector {
+            settings: settings.clone(),
+            speakers: HashMap::new(),
+            sender: events.sender(),
+            ssrcs: driver.ssrcs(),
+        };
+        jitter::start(driver, depth, detector);
+
+        Ok(Self {
+            settings,
+            events: Py::new(py, events)?,
+        })
+    }
+
+    /// The ``EventStream`` of ``Utterance`` objects. Closing it stops the detector.
+    #[getter]
+    fn events(&self, py: Python) -> Py<PyEventStream> {
+        self.events.clone_ref(py)
+    }
+
+    fn __aiter__(&self, py: Python) -> Py<PyEventStream> {
+        self.events.clone_ref(py)
+    }
+
+    /// Uses different thresholds for a user, for example for someone with a noisy microphone.
+    ///
+    /// Raises
+    /// ------
+    /// ValueError
+    ///     `stop` is above `start`.
+    #[pyo3(text_signature = "($self, user_id: int, start: float, stop: float)")]
+    fn set_thresholds(&self, user_id: u64, start: f32, stop: f32) -> PyResult<()> {
+        let thresholds = Thresholds::new(start, stop)?;
+        let mut settings = self.settings.lock().unwrap();
+        settings.users.insert(user_id, thresholds);
+        Ok(())
+    }
+
+    /// Goes back to the default thresholds for a user.
+    #[pyo3(text_signature = "($self, user_id: int)")]
+    fn reset_thresholds(&self, user_id: u64) {
+        self.settings.lock().unwrap().users.remove(&user_id);
+    }
+
+    /// The start and stop thresholds of a user in dBFS.
+    #[pyo3(text_signature = "($self, user_id: int) -> Tuple[float, float]")]
+    fn thresholds(&self, user_id: u64) -> (f32, f32) {
+        let settings = self.settings.lock().unwrap();
+        let thresholds = settings.users.get(&user_id).unwrap_or(&settings.default);
+        (thresholds.start, thresholds.stop)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detector(
+        events: &PyEventStream,
+        hangover: usize,
+        min_length: usize,
+        max_length: usize,
+    ) -> Detector {
+        Detector {
+            settings: Arc::new(Mutex::new(Settings {
+                default: Thresholds::new(-40.0, -50.0).unwrap(),
+                users: HashMap::new(),
+                hangover,
+                min_length,
+                max_length,
+            })),
+            speakers: HashMap::new(),
+            sender: events.sender(),
+            ssrcs: Arc::new(SsrcMap::new()),
+        }
+    }
+
+    fn frame(timestamp: u32, sample: i16) -> Frame {
+        Frame {
+            ssrc: 1,
+            timestamp,
+            audio: Arc::new(vec![sample; 1920]),
+            concealed: false,
+        }
+    }
+
+    /// Feeds `frames` frames of `sample` and returns the utterances as (finished, frames).
+    fn feed(detector: &mut Detector, frames: u32, sample: i16) -> Vec<(bool, usize)> {
+        let mut events = Vec::new();
+        for i in 0..frames {
+            detector.process(1, Some(&frame(i * 960, sample)), &mut events);
+        }
+        events
+            .iter()
+            .map(|utterance| (utterance.finished, utterance.audio.len() / 1920))
+            .collect()
+    }
+
+    #[test]
+    fn reports_start_and_end() {
+        let events = PyEventStream::new(1, None);
+        let mut detector = detector(&events, 3, 2, 100);
+        assert_eq!(feed(&mut detector, 5, 10_000), [(false, 2)]);
+        assert_eq!(feed(&mut detector, 3, 0), [(true, 8)]);
+        assert!(detector.speakers.is_empty());
+    }
+
+    #[test]
+    fn short_utterances_are_dropped() {
+        let events = PyEventStream::new(1, None);
+        let mut detector = detector(&events, 2, 5, 100);
+        assert_eq!(feed(&mut detector, 2, 10_000), []);
+        assert_eq!(feed(&mut detector, 2, 0), []);
+        assert!(detector.speakers.is_empty());
+    }
+
+    #[test]
+    fn long_utterances_are_split() {
+        let events = PyEventStream::new(1, None);
+        let mut detector = detector(&events, 3, 1, 4);
+        assert_eq!(
+            feed(&mut detector, 10, 10_000),
+            [(false, 1), (true, 4), (false, 1), (true, 4), (false, 1)]
+        );
+    }
+
+    #[test]
+    fn start_shares_the_audio() {
+        let events = PyEventStream::new(1, None);
+        let mut detector = detector(&events, 3, 1, 100);
+        let mut utterances = Vec::new();
+        detector.process(1, Some(&frame(0, 10_000)), &mut utterances);
+        assert!(Arc::ptr_eq(
+            &utterances[0].audio,
+            &detector.speakers[&1].audio
+        ));
+
+        // More audio copies the buffer because the reported start still holds it.
+        detector.process(1, Some(&frame(960, 10_000)), &mut utterances);
+        assert_eq!(utterances[0].audio.len(), 1920);
+        assert_eq!(detector.speakers[&1].audio.len(), 3840);
+    }
+}